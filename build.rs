@@ -0,0 +1,56 @@
+//! Expose the git commit and the build date of the binary, shown by `--version`
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use time::OffsetDateTime;
+
+/// The short hash of the current git commit, unknown outside of a git checkout
+fn git_commit() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// The build date (UTC), SOURCE_DATE_EPOCH taking precedence for reproducible builds
+fn build_date() -> String {
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or_default()
+        });
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map(|datetime| datetime.date().to_string())
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+fn main() {
+    println!("cargo:rustc-env=NETBOX2NETSHOT_GIT_COMMIT={}", git_commit());
+    println!("cargo:rustc-env=NETBOX2NETSHOT_BUILD_DATE={}", build_date());
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Rebuild on new commits, a missing path would trigger a rebuild every time
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        let reference = fs::read_to_string(head).unwrap_or_default();
+        if let Some(reference) = reference.trim().strip_prefix("ref: ") {
+            let reference = Path::new(".git").join(reference);
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+}
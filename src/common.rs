@@ -1 +0,0 @@
-pub const APP_USER_AGENT: &str = "netbox2netshot";
@@ -0,0 +1,181 @@
+use anyhow::{Context, Error, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The source of the current time, injectable to test the expiry
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The content of the cache file
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    /// When the value was stored, in seconds since the epoch
+    created_at: u64,
+    /// What the value was fetched for, a different key invalidates it
+    key: String,
+    value: T,
+}
+
+/// A single value cached as JSON on disk, reused until it is older than the TTL
+#[derive(Debug, Clone)]
+pub struct DiskCache<C: Clock = SystemClock> {
+    pub path: PathBuf,
+    pub ttl: Duration,
+    clock: C,
+}
+
+impl DiskCache {
+    pub fn new(path: impl Into<PathBuf>, ttl: Duration) -> Self {
+        DiskCache::with_clock(path, ttl, SystemClock)
+    }
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl<C: Clock> DiskCache<C> {
+    /// Create a cache using the given clock
+    pub fn with_clock(path: impl Into<PathBuf>, ttl: Duration, clock: C) -> Self {
+        Self {
+            path: path.into(),
+            ttl,
+            clock,
+        }
+    }
+
+    /// The cached value stored for the given key, none when missing, expired or unreadable
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        let entry: CacheEntry<T> = match serde_json::from_str(&content) {
+            Ok(entry) => entry,
+            Err(error) => {
+                log::warn!(
+                    "Ignoring the unreadable cache {}: {}",
+                    self.path.display(),
+                    error
+                );
+                return None;
+            }
+        };
+        if entry.key != key {
+            log::debug!("Cache {} was stored for another key", self.path.display());
+            return None;
+        }
+
+        let age = epoch_secs(self.clock.now()).saturating_sub(entry.created_at);
+        if age >= self.ttl.as_secs() {
+            log::debug!("Cache {} expired {}s ago", self.path.display(), age);
+            return None;
+        }
+        log::debug!(
+            "Using the cache {} stored {}s ago",
+            self.path.display(),
+            age
+        );
+        Some(entry.value)
+    }
+
+    /// Store the value for the given key, timestamped now
+    pub fn store<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Error> {
+        let entry = CacheEntry {
+            created_at: epoch_secs(self.clock.now()),
+            key: key.to_string(),
+            value,
+        };
+        fs::write(&self.path, serde_json::to_string(&entry)?)
+            .with_context(|| format!("Unable to write the cache {}", self.path.display()))
+    }
+
+    /// Remove the cached value, if any
+    pub fn invalidate(&self) -> Result<(), Error> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Error::new(error).context(format!(
+                "Unable to remove the cache {}",
+                self.path.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A clock only moving when told to
+    struct FakeClock(Cell<SystemTime>);
+
+    impl FakeClock {
+        fn advance(&self, secs: u64) {
+            self.0.set(self.0.get() + Duration::from_secs(secs));
+        }
+    }
+
+    impl Clock for &FakeClock {
+        fn now(&self) -> SystemTime {
+            self.0.get()
+        }
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("netbox2netshot-cache-{}.json", name))
+    }
+
+    #[test]
+    fn expiry() {
+        let clock = FakeClock(Cell::new(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+        let cache = DiskCache::with_clock(cache_path("expiry"), Duration::from_secs(60), &clock);
+        cache.store("domains", &vec![1, 2]).unwrap();
+
+        clock.advance(59);
+        assert_eq!(cache.load::<Vec<u32>>("domains"), Some(vec![1, 2]));
+        assert!(cache.load::<Vec<u32>>("other-domains").is_none());
+
+        clock.advance(1);
+        assert!(cache.load::<Vec<u32>>("domains").is_none());
+        cache.invalidate().unwrap();
+    }
+
+    #[test]
+    fn invalidation() {
+        let clock = FakeClock(Cell::new(UNIX_EPOCH));
+        let cache =
+            DiskCache::with_clock(cache_path("invalidation"), Duration::from_secs(60), &clock);
+        cache.store("key", &"value").unwrap();
+        assert_eq!(cache.load::<String>("key").unwrap(), "value");
+
+        cache.invalidate().unwrap();
+        assert!(cache.load::<String>("key").is_none());
+        // Invalidating a missing cache is not an error
+        cache.invalidate().unwrap();
+    }
+
+    #[test]
+    fn unreadable_cache() {
+        let path = cache_path("unreadable");
+        fs::write(&path, "not json").unwrap();
+        let cache = DiskCache::new(&path, Duration::from_secs(60));
+
+        assert!(cache.load::<String>("key").is_none());
+        cache.invalidate().unwrap();
+    }
+}
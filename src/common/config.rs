@@ -0,0 +1,415 @@
+use anyhow::{Context, Error, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+use crate::common::secret::Secret;
+
+/// The environment variable that can be used instead of `--config`
+pub const CONFIG_ENV: &str = "NETBOX2NETSHOT_CONFIG";
+
+/// A commented configuration file with every key, printed by `--generate-config`
+pub const TEMPLATE: &str = include_str!("config_template.toml");
+
+/// The configuration file content, keys are the CLI long flags using underscores.
+///
+/// Values are applied with the lowest precedence: CLI flags > environment variables > file
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    #[serde(default)]
+    pub debug: bool,
+    #[serde(default)]
+    pub quiet: bool,
+    #[serde(default)]
+    pub no_color: bool,
+    pub log_spec: Option<String>,
+    pub log_directory: Option<String>,
+    pub log_max_size_mb: Option<u64>,
+    pub log_keep_files: Option<usize>,
+    pub log_format: Option<String>,
+    pub progress_every: Option<usize>,
+    pub netshot_url: Option<String>,
+    pub netshot_api_base: Option<String>,
+    pub netshot_tls_client_certificate: Option<String>,
+    pub netshot_tls_client_certificate_password: Option<String>,
+    pub netshot_tls_client_cert_pem: Option<String>,
+    pub netshot_tls_client_key_pem: Option<String>,
+    pub netshot_ca_cert: Option<String>,
+    pub netshot_token: Option<String>,
+    pub netshot_auth_scheme: Option<String>,
+    pub netshot_domain_id: Option<u32>,
+    pub netshot_domain_name: Option<String>,
+    pub netshot_group_id: Option<u32>,
+    pub netshot_proxy: Option<String>,
+    pub netbox_url: Option<String>,
+    pub netbox_tls_client_certificate: Option<String>,
+    pub netbox_tls_client_certificate_password: Option<String>,
+    pub netbox_tls_client_cert_pem: Option<String>,
+    pub netbox_tls_client_key_pem: Option<String>,
+    pub netbox_ca_cert: Option<String>,
+    pub netbox_token: Option<String>,
+    pub netbox_auth_scheme: Option<String>,
+    pub netbox_devices_filter: Option<String>,
+    pub netbox_vms_filter: Option<String>,
+    pub netbox_proxy: Option<String>,
+    pub netbox_page_size: Option<u32>,
+    #[serde(default)]
+    pub netbox_graphql: bool,
+    #[serde(default)]
+    pub netbox_cursor_pagination: bool,
+    pub since: Option<String>,
+    pub netbox_ip_field: Option<String>,
+    #[serde(default)]
+    pub check: bool,
+    #[serde(default)]
+    pub fail_on_drift: bool,
+    #[serde(default)]
+    pub match_by_name: bool,
+    pub report: Option<String>,
+    pub csv: Option<String>,
+    pub dry_run_diff: Option<String>,
+    pub metrics_file: Option<String>,
+    pub state_file: Option<String>,
+    pub register_name_template: Option<String>,
+    pub notify_webhook: Option<String>,
+    pub concurrency: Option<usize>,
+    pub register_batch_size: Option<usize>,
+    pub user_agent: Option<String>,
+    pub http_connect_timeout_secs: Option<u64>,
+    pub http_read_timeout_secs: Option<u64>,
+    pub http_retries: Option<u32>,
+    pub http_retry_base_ms: Option<u64>,
+    pub max_rate_wait_secs: Option<u64>,
+    pub max_rps: Option<u32>,
+    #[serde(default)]
+    pub retry_writes: bool,
+    #[serde(default)]
+    pub danger_insecure_tls: bool,
+    #[serde(default)]
+    pub no_compression: bool,
+    #[serde(default)]
+    pub snapshot_on_register: bool,
+    #[serde(default)]
+    pub wait_snapshots: bool,
+    pub snapshot_timeout_secs: Option<u64>,
+    pub interval_secs: Option<u64>,
+    pub jitter_secs: Option<u64>,
+    pub netshot_cache: Option<String>,
+    pub netshot_cache_ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub no_cache: bool,
+    pub register_from_file: Option<String>,
+    pub netbox_token_file: Option<String>,
+    pub netshot_token_file: Option<String>,
+    pub max_disable: Option<usize>,
+    pub max_disable_percent: Option<u32>,
+    #[serde(default)]
+    pub force: bool,
+    pub min_netbox_devices: Option<usize>,
+    pub on_missing: Option<String>,
+    pub limit: Option<usize>,
+    pub run_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub interactive: bool,
+    #[serde(default)]
+    pub yes: bool,
+    #[serde(default)]
+    pub reenable: bool,
+    #[serde(default)]
+    pub scope_to_domain: bool,
+    #[serde(default)]
+    pub copy_tags: bool,
+    #[serde(default)]
+    pub update_names: bool,
+    #[serde(default)]
+    pub fail_fast: bool,
+    #[serde(default)]
+    pub no_disable: bool,
+    #[serde(default)]
+    pub no_register: bool,
+    #[serde(default)]
+    pub ignore_write_errors: bool,
+    /// Repeatable on the command line, the file list is used when none is given there
+    #[serde(default)]
+    pub hostname_strip_suffix: Vec<String>,
+    pub hostname_regex: Option<String>,
+    #[serde(default)]
+    pub netbox_status: Vec<String>,
+    #[serde(default)]
+    pub netbox_role: Vec<String>,
+    #[serde(default)]
+    pub netbox_device_type: Vec<u32>,
+    #[serde(default)]
+    pub disable_offline: bool,
+    #[serde(default)]
+    pub offline_status: Vec<String>,
+    #[serde(default)]
+    pub exclude_ip: Vec<IpAddr>,
+    #[serde(default)]
+    pub exclude_subnet: Vec<IpNet>,
+    #[serde(default)]
+    pub only_subnet: Vec<IpNet>,
+    /// Netbox site name to Netshot domain ID, not available on the command line
+    #[serde(default)]
+    pub site_domains: HashMap<String, u32>,
+    /// Netbox tenant name to Netshot domain ID, not available on the command line
+    #[serde(default)]
+    pub tenant_domains: HashMap<String, u32>,
+    /// Netbox platform slug to Netshot driver, not available on the command line
+    #[serde(default)]
+    pub platform_drivers: HashMap<String, String>,
+    /// Additional Netbox instances whose inventories are merged with the `netbox_url` one
+    #[serde(default)]
+    pub netbox_sources: Vec<NetboxSource>,
+}
+
+/// A Netbox instance of the `[[netbox_sources]]` list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetboxSource {
+    pub url: String,
+    pub token: Option<Secret>,
+    pub token_file: Option<String>,
+}
+
+impl FileConfig {
+    /// Parse a TOML configuration, rejecting unknown keys
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Load the TOML configuration file from the given path
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read configuration file {}", path))?;
+        FileConfig::parse(&content).with_context(|| format!("Invalid configuration file {}", path))
+    }
+
+    /// The (environment variable, value) pairs of the options defined in the file, flags excluded
+    pub fn env_values(&self) -> Result<Vec<(String, String)>, Error> {
+        let table = toml::Value::try_from(self)?;
+        let values = table
+            .as_table()
+            .map(|table| {
+                table
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        let value = match value {
+                            toml::Value::String(s) => s.clone(),
+                            toml::Value::Integer(i) => i.to_string(),
+                            _ => return None,
+                        };
+                        Some((key.to_uppercase(), value))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(values)
+    }
+
+    /// Expose the file values as environment variables unless they are already set,
+    /// so the CLI parser sees them with a lower precedence than the real environment
+    pub fn apply_to_env(&self) -> Result<(), Error> {
+        for (key, value) in self.env_values()? {
+            if env::var_os(&key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the configuration file path from the command line arguments or the environment
+pub fn find_config_path<I: IntoIterator<Item = String>>(args: I) -> Option<String> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    env::var(CONFIG_ENV).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = FileConfig::parse(
+            r#"
+            debug = true
+            netbox_url = "https://netbox.example.org"
+            netshot_domain_id = 2
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.debug);
+        assert_eq!(config.netbox_url.unwrap(), "https://netbox.example.org");
+        assert_eq!(config.netshot_domain_id.unwrap(), 2);
+    }
+
+    #[test]
+    fn parse_domain_mappings() {
+        let config = FileConfig::parse(
+            r#"
+            netshot_domain_id = 1
+
+            [site_domains]
+            "Paris DC1" = 2
+
+            [tenant_domains]
+            customer-a = 3
+
+            [platform_drivers]
+            ios = "CiscoIOS12"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.site_domains["Paris DC1"], 2);
+        assert_eq!(config.tenant_domains["customer-a"], 3);
+        assert_eq!(config.platform_drivers["ios"], "CiscoIOS12");
+        assert_eq!(
+            config.env_values().unwrap(),
+            vec![("NETSHOT_DOMAIN_ID".to_string(), "1".to_string())]
+        );
+    }
+
+    #[test]
+    fn parse_netbox_sources() {
+        let config = FileConfig::parse(
+            r#"
+            netbox_url = "https://netbox-eu.example.org"
+
+            [[netbox_sources]]
+            url = "https://netbox-us.example.org"
+            token_file = "/etc/netbox-us.token"
+
+            [[netbox_sources]]
+            url = "https://netbox-ap.example.org"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.netbox_sources.len(), 2);
+        assert_eq!(
+            config.netbox_sources[0].token_file.as_deref(),
+            Some("/etc/netbox-us.token")
+        );
+        assert!(config.netbox_sources[1].token.is_none());
+        assert_eq!(config.env_values().unwrap().len(), 1);
+        assert!(FileConfig::parse("[[netbox_sources]]\nuri = \"x\"").is_err());
+    }
+
+    #[test]
+    fn netbox_source_token_redacted() {
+        let config = FileConfig::parse(
+            r#"
+            [[netbox_sources]]
+            url = "https://netbox-us.example.org"
+            token = "SUPERSECRETTOKEN"
+            "#,
+        )
+        .unwrap();
+
+        let source = &config.netbox_sources[0];
+        assert_eq!(source.token.as_ref().unwrap().expose(), "SUPERSECRETTOKEN");
+        assert!(!format!("{:?}", source).contains("SUPERSECRETTOKEN"));
+        assert!(!format!("{:#?}", config).contains("SUPERSECRETTOKEN"));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let config = FileConfig::parse(r#"netbox_uri = "https://netbox.example.org""#);
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn env_values_skip_flags() {
+        let config = FileConfig::parse(
+            r#"
+            check = true
+            netbox_url = "https://netbox.example.org"
+            concurrency = 8
+            "#,
+        )
+        .unwrap();
+
+        let mut values = config.env_values().unwrap();
+        values.sort();
+
+        assert_eq!(
+            values,
+            vec![
+                ("CONCURRENCY".to_string(), "8".to_string()),
+                (
+                    "NETBOX_URL".to_string(),
+                    "https://netbox.example.org".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_path_from_args() {
+        let args = vec!["netbox2netshot", "--check", "--config", "/etc/n2n.toml"];
+        assert_eq!(
+            find_config_path(args.into_iter().map(String::from)).unwrap(),
+            "/etc/n2n.toml"
+        );
+
+        let args = vec!["netbox2netshot", "--config=/etc/n2n.toml"];
+        assert_eq!(
+            find_config_path(args.into_iter().map(String::from)).unwrap(),
+            "/etc/n2n.toml"
+        );
+    }
+
+    /// The template with its commented settings enabled, the documentation lines (`##`) left out
+    fn uncommented_template() -> String {
+        TEMPLATE
+            .lines()
+            .filter(|line| !line.starts_with("##"))
+            .map(|line| line.strip_prefix("# ").unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn template_covers_every_key() {
+        let config = FileConfig::parse(TEMPLATE).unwrap();
+        assert_eq!(config.netshot_domain_id, Some(1));
+        assert!(config.site_domains.is_empty());
+
+        let config = FileConfig::parse(&uncommented_template()).unwrap();
+        assert_eq!(config.platform_drivers["ios"], "CiscoIOS12");
+        assert_eq!(config.netbox_sources.len(), 1);
+
+        // The unknown key error lists every supported key
+        let error = FileConfig::parse("unknown_key = 1")
+            .unwrap_err()
+            .to_string();
+        let keys: Vec<&str> = error.split('`').skip(3).step_by(2).collect();
+        assert!(keys.contains(&"debug") && keys.contains(&"netbox_sources"));
+        let template = uncommented_template();
+        for key in keys {
+            let defined = template.lines().any(|line| {
+                line.starts_with(&format!("{} = ", key)) || line.contains(&format!("[{}]", key))
+            });
+            assert!(
+                defined,
+                "{} is missing from the configuration template",
+                key
+            );
+        }
+    }
+}
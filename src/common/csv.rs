@@ -0,0 +1,122 @@
+use anyhow::{Context, Error, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::{DeviceRef, InventoryRow};
+
+/// Quote a CSV field when it contains a separator, a quote or a line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write the (action, device) pairs as CSV with the `action,ip,hostname,source` columns
+pub fn write_actions<W: Write>(
+    writer: &mut W,
+    actions: &[(&str, &DeviceRef)],
+) -> Result<(), Error> {
+    writeln!(writer, "action,ip,hostname,source")?;
+    for (action, device) in actions {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            escape(action),
+            device.ip,
+            escape(&device.hostname),
+            device.source
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the (action, device) pairs as CSV to the given path
+pub fn write_actions_file(path: &str, actions: &[(&str, &DeviceRef)]) -> Result<(), Error> {
+    let file = File::create(path).with_context(|| format!("Unable to create CSV file {}", path))?;
+    let mut writer = BufWriter::new(file);
+    write_actions(&mut writer, actions)
+        .and_then(|_| Ok(writer.flush()?))
+        .with_context(|| format!("Unable to write CSV file {}", path))
+}
+
+/// Write the inventory rows as CSV with the `ip,netbox_hostname,netshot_hostname,in_netbox,in_netshot` columns
+pub fn write_inventory_rows<W: Write>(writer: &mut W, rows: &[InventoryRow]) -> Result<(), Error> {
+    writeln!(
+        writer,
+        "ip,netbox_hostname,netshot_hostname,in_netbox,in_netshot"
+    )?;
+    for row in rows {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            row.ip,
+            escape(row.netbox_hostname.as_deref().unwrap_or("")),
+            escape(row.netshot_hostname.as_deref().unwrap_or("")),
+            row.in_netbox,
+            row.in_netshot
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Source;
+
+    #[test]
+    fn actions_as_csv() {
+        let register = DeviceRef::new(
+            "10.0.0.1".parse().unwrap(),
+            "sw1".to_string(),
+            Source::Device,
+        );
+        let disable = DeviceRef::new(
+            "10.0.0.2".parse().unwrap(),
+            "rtr, \"old\"".to_string(),
+            Source::Netshot,
+        );
+
+        let mut output = Vec::new();
+        write_actions(
+            &mut output,
+            &[("register", &register), ("disable", &disable)],
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "action,ip,hostname,source\nregister,10.0.0.1,sw1,device\ndisable,10.0.0.2,\"rtr, \"\"old\"\"\",netshot\n"
+        );
+    }
+
+    #[test]
+    fn inventory_rows_as_csv() {
+        let rows = vec![
+            InventoryRow {
+                ip: "10.0.0.1".parse().unwrap(),
+                netbox_hostname: Some("sw1".to_string()),
+                netshot_hostname: Some("sw1".to_string()),
+                in_netbox: true,
+                in_netshot: true,
+            },
+            InventoryRow {
+                ip: "10.0.0.2".parse().unwrap(),
+                netbox_hostname: None,
+                netshot_hostname: Some("old".to_string()),
+                in_netbox: false,
+                in_netshot: true,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_inventory_rows(&mut output, &rows).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ip,netbox_hostname,netshot_hostname,in_netbox,in_netshot\n10.0.0.1,sw1,sw1,true,true\n10.0.0.2,,old,false,true\n"
+        );
+    }
+}
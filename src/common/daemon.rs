@@ -0,0 +1,98 @@
+use anyhow::{Error, Result};
+use rand::Rng;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a sleeping daemon checks whether it was asked to stop
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Tracks the termination requests (SIGINT/SIGTERM) received while running as a daemon
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Catch SIGINT and SIGTERM so they stop the daemon between two runs instead of killing it,
+    /// a second signal still terminates the process immediately
+    pub fn register() -> Result<Self, Error> {
+        let shutdown = Shutdown::default();
+        for signal in &[SIGINT, SIGTERM] {
+            signal_hook::flag::register_conditional_shutdown(
+                *signal,
+                1,
+                Arc::clone(&shutdown.requested),
+            )?;
+            signal_hook::flag::register(*signal, Arc::clone(&shutdown.requested))?;
+        }
+        Ok(shutdown)
+    }
+
+    /// Ask the daemon to stop
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Was the daemon asked to stop
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for the given duration, returning early (and false) if asked to stop
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.is_requested() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+/// The wait between two runs: the interval plus a random jitter of up to `jitter_secs`
+pub fn next_delay(interval_secs: u64, jitter_secs: u64) -> Duration {
+    let jitter_ms = rand::thread_rng().gen_range(0..=jitter_secs.saturating_mul(1000));
+    Duration::from_secs(interval_secs) + Duration::from_millis(jitter_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_with_jitter() {
+        assert_eq!(next_delay(30, 0), Duration::from_secs(30));
+        for _ in 0..10 {
+            let delay = next_delay(30, 5);
+            assert!(delay >= Duration::from_secs(30));
+            assert!(delay <= Duration::from_secs(35));
+        }
+    }
+
+    #[test]
+    fn sleep_until_deadline() {
+        let shutdown = Shutdown::default();
+        assert!(shutdown.sleep(Duration::from_millis(10)));
+        assert!(!shutdown.is_requested());
+    }
+
+    #[test]
+    fn sleep_interrupted_by_shutdown() {
+        let shutdown = Shutdown::default();
+        let requester = shutdown.clone();
+        let handle = thread::spawn(move || requester.request());
+        handle.join().unwrap();
+
+        let start = Instant::now();
+        assert!(!shutdown.sleep(Duration::from_secs(60)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
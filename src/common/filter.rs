@@ -0,0 +1,101 @@
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::DeviceRef;
+
+/// The management IPs left out of the synchronization
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub exclude_ips: Vec<IpAddr>,
+    pub exclude_subnets: Vec<IpNet>,
+    /// When not empty, only the IPs within these subnets are synchronized (exclusions still apply)
+    pub only_subnets: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Remove the excluded entries from the given inventory, logging what each exclusion removed
+    pub fn apply(&self, inventory: &mut HashMap<IpAddr, DeviceRef>, name: &str) {
+        if !self.only_subnets.is_empty() {
+            let before = inventory.len();
+            inventory.retain(|ip, _| self.only_subnets.iter().any(|s| s.contains(ip)));
+            log::info!(
+                "Subnets allowlist removed {} {} entries",
+                before - inventory.len(),
+                name
+            );
+        }
+
+        for ip in &self.exclude_ips {
+            let removed = inventory.remove(ip).map_or(0, |_| 1);
+            log::info!("Excluded IP {} removed {} {} entries", ip, removed, name);
+        }
+
+        for subnet in &self.exclude_subnets {
+            let before = inventory.len();
+            inventory.retain(|ip, _| !subnet.contains(ip));
+            log::info!(
+                "Excluded subnet {} removed {} {} entries",
+                subnet,
+                before - inventory.len(),
+                name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Source;
+
+    #[test]
+    fn exclusions() {
+        let mut inventory: HashMap<IpAddr, DeviceRef> =
+            ["10.0.0.1", "10.0.0.2", "10.1.0.1", "2001:db8::1"]
+                .iter()
+                .map(|ip| {
+                    let ip: IpAddr = ip.parse().unwrap();
+                    (ip, DeviceRef::new(ip, ip.to_string(), Source::Device))
+                })
+                .collect();
+
+        let filter = IpFilter {
+            exclude_ips: vec!["10.1.0.1".parse().unwrap(), "192.0.2.1".parse().unwrap()],
+            exclude_subnets: vec![
+                "10.0.0.0/30".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap(),
+            ],
+            only_subnets: Vec::new(),
+        };
+        filter.apply(&mut inventory, "Netbox");
+
+        assert!(inventory.is_empty());
+
+        let mut inventory: HashMap<IpAddr, DeviceRef> = HashMap::new();
+        let ip: IpAddr = "172.16.0.1".parse().unwrap();
+        inventory.insert(ip, DeviceRef::new(ip, "sw1".to_string(), Source::Netshot));
+        filter.apply(&mut inventory, "Netshot");
+        assert_eq!(inventory.len(), 1);
+    }
+
+    #[test]
+    fn allowlist_with_exclusions() {
+        let filter = IpFilter {
+            exclude_ips: vec!["10.0.0.1".parse().unwrap()],
+            exclude_subnets: Vec::new(),
+            only_subnets: vec!["10.0.0.0/24".parse().unwrap()],
+        };
+        let mut inventory: HashMap<IpAddr, DeviceRef> = ["10.0.0.1", "10.0.0.2", "10.0.1.1"]
+            .iter()
+            .map(|ip| {
+                let ip: IpAddr = ip.parse().unwrap();
+                (ip, DeviceRef::new(ip, ip.to_string(), Source::Device))
+            })
+            .collect();
+        filter.apply(&mut inventory, "Netbox");
+
+        let remaining: Vec<&IpAddr> = inventory.keys().collect();
+        assert_eq!(remaining, vec![&"10.0.0.2".parse::<IpAddr>().unwrap()]);
+    }
+}
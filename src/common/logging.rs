@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Error};
+use flexi_logger::DeferredNow;
+use log::Record;
+use std::io::{self, Write};
+use std::str::FromStr;
+use time::format_description::well_known::Rfc3339;
+
+/// How the log lines are formatted, in the files and on the console
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The human format, colored on a terminal
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!(
+                "Invalid log format {}, expected text or json",
+                value
+            )),
+        }
+    }
+}
+
+/// Format a log record as a JSON object with the timestamp, level, module and message fields
+pub fn json_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    let line = serde_json::json!({
+        "timestamp": now.format(&Rfc3339),
+        "level": record.level().to_string(),
+        "module": record.module_path().unwrap_or("<unnamed>"),
+        "message": record.args().to_string(),
+    });
+    write!(w, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let mut output = Vec::new();
+        json_format(
+            &mut output,
+            &mut DeferredNow::new(),
+            &Record::builder()
+                .args(format_args!("Fetched {} devices", 2))
+                .level(log::Level::Warn)
+                .module_path(Some("netbox2netshot::rest"))
+                .build(),
+        )
+        .unwrap();
+
+        let line: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["module"], "netbox2netshot::rest");
+        assert_eq!(line["message"], "Fetched 2 devices");
+        assert!(line["timestamp"].as_str().unwrap().contains('T'));
+        assert!("JSON".parse::<LogFormat>().is_ok());
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
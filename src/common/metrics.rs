@@ -0,0 +1,183 @@
+use anyhow::{Context, Error, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::common::report::{Report, ReportEntry};
+use crate::common::timing::Timings;
+
+const PREFIX: &str = "netbox2netshot";
+
+/// The figures of a synchronization run, exposed in the Prometheus text format
+#[derive(Debug, Default, PartialEq)]
+pub struct Metrics {
+    pub devices_to_register: usize,
+    pub devices_to_disable: usize,
+    pub register_failures: usize,
+    pub disable_failures: usize,
+    /// When the run ended, in seconds since the epoch
+    pub last_run_timestamp_seconds: f64,
+    pub run_duration_seconds: f64,
+    /// Duration of each phase of the run, see [`Timings`]
+    pub phase_duration_seconds: Vec<(&'static str, f64)>,
+}
+
+fn failures(entries: &[ReportEntry]) -> usize {
+    entries
+        .iter()
+        .filter(|entry| entry.success == Some(false))
+        .count()
+}
+
+impl Metrics {
+    /// The metrics of a run described by its report, timestamped now
+    pub fn from_report(report: &Report, duration: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            devices_to_register: report.devices_to_register.len(),
+            devices_to_disable: report.devices_to_disable.len(),
+            register_failures: failures(&report.devices_to_register),
+            disable_failures: failures(&report.devices_to_disable),
+            last_run_timestamp_seconds: now.as_secs_f64(),
+            run_duration_seconds: duration.as_secs_f64(),
+            phase_duration_seconds: Vec::new(),
+        }
+    }
+
+    /// Add the durations of the phases of the run
+    pub fn with_timings(mut self, timings: &Timings) -> Self {
+        self.phase_duration_seconds = timings
+            .phases()
+            .iter()
+            .map(|(phase, duration)| (*phase, duration.as_secs_f64()))
+            .collect();
+        self
+    }
+
+    /// Render the metrics as gauges in the Prometheus/OpenMetrics text format
+    pub fn render(&self) -> String {
+        let gauges = [
+            (
+                "devices_to_register",
+                "Devices missing on Netshot during the last run",
+                self.devices_to_register as f64,
+            ),
+            (
+                "devices_to_disable",
+                "Devices missing on Netbox during the last run",
+                self.devices_to_disable as f64,
+            ),
+            (
+                "register_failures",
+                "Failed device registrations during the last run",
+                self.register_failures as f64,
+            ),
+            (
+                "disable_failures",
+                "Failed device disables during the last run",
+                self.disable_failures as f64,
+            ),
+            (
+                "last_run_timestamp_seconds",
+                "End of the last run, in seconds since the epoch",
+                self.last_run_timestamp_seconds,
+            ),
+            (
+                "run_duration_seconds",
+                "Duration of the last run",
+                self.run_duration_seconds,
+            ),
+        ];
+
+        let mut content = String::new();
+        for (name, help, value) in gauges.iter() {
+            let _ = writeln!(content, "# HELP {}_{} {}", PREFIX, name, help);
+            let _ = writeln!(content, "# TYPE {}_{} gauge", PREFIX, name);
+            let _ = writeln!(content, "{}_{} {}", PREFIX, name, value);
+        }
+        if !self.phase_duration_seconds.is_empty() {
+            let name = format!("{}_phase_duration_seconds", PREFIX);
+            let _ = writeln!(
+                content,
+                "# HELP {} Duration of each phase of the last run",
+                name
+            );
+            let _ = writeln!(content, "# TYPE {} gauge", name);
+            for (phase, value) in &self.phase_duration_seconds {
+                let _ = writeln!(content, "{}{{phase=\"{}\"}} {}", name, phase, value);
+            }
+        }
+        content
+    }
+
+    /// Write the metrics to the given path through a temporary file renamed over it,
+    /// so a scraper never reads a partial file
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        let temporary = format!("{}.tmp", path);
+        fs::write(&temporary, self.render())
+            .with_context(|| format!("Unable to write metrics file {}", temporary))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Unable to move metrics file {} to {}", temporary, path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rest::error::ApiError;
+    use crate::{DeviceRef, Source};
+
+    #[test]
+    fn metrics_from_report() {
+        let device = DeviceRef::new(
+            "1.2.3.4".parse().unwrap(),
+            "test-device".to_string(),
+            Source::Device,
+        );
+        let mut report = Report::new(false).unwrap();
+        report
+            .devices_to_register
+            .push(ReportEntry::applied(&device, &Ok(())));
+        report.devices_to_register.push(ReportEntry::applied::<()>(
+            &device,
+            &Err(ApiError::Server(500)),
+        ));
+        report
+            .devices_to_disable
+            .push(ReportEntry::planned(&device));
+
+        let metrics = Metrics::from_report(&report, Duration::from_millis(1500));
+        assert_eq!(metrics.devices_to_register, 2);
+        assert_eq!(metrics.devices_to_disable, 1);
+        assert_eq!(metrics.register_failures, 1);
+        assert_eq!(metrics.disable_failures, 0);
+        assert!(metrics.last_run_timestamp_seconds > 0.0);
+
+        let content = metrics.render();
+        assert!(content.contains("# TYPE netbox2netshot_devices_to_register gauge\n"));
+        assert!(content.contains("\nnetbox2netshot_register_failures 1\n"));
+        assert!(content.contains("\nnetbox2netshot_run_duration_seconds 1.5\n"));
+        assert!(!content.contains("phase_duration_seconds"));
+
+        let mut timings = Timings::default();
+        timings.record("netbox_fetch", Duration::from_millis(250));
+        let content = metrics.with_timings(&timings).render();
+        assert!(content
+            .contains("\nnetbox2netshot_phase_duration_seconds{phase=\"netbox_fetch\"} 0.25\n"));
+    }
+
+    #[test]
+    fn atomic_write() {
+        let path = std::env::temp_dir().join("netbox2netshot-metrics.prom");
+        let path = path.to_str().unwrap();
+        let metrics = Metrics::default();
+        metrics.write(path).unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), metrics.render());
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
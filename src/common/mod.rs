@@ -0,0 +1,375 @@
+pub mod cache;
+pub mod config;
+pub mod csv;
+pub mod daemon;
+pub mod filter;
+pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod notify;
+pub mod plan;
+pub mod progress;
+pub mod prompt;
+pub mod rate;
+pub mod report;
+pub mod retry;
+pub mod secret;
+pub mod state;
+pub mod timing;
+
+use anyhow::{anyhow, Context, Error, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::DeviceRef;
+
+/// The default User-Agent of the HTTP clients, identifying the tool and its version
+pub const APP_USER_AGENT: &str = concat!("netbox2netshot/", env!("CARGO_PKG_VERSION"));
+
+/// Parse an address with an optional prefix length (`10.0.0.1/32`, `2001:db8::1/128`)
+/// into its canonical IP, tolerating zero-padded IPv4 octets (`010.000.000.001`)
+pub fn parse_ip_from_cidr(address: &str) -> Result<IpAddr, Error> {
+    let mut parts = address.trim().splitn(2, '/');
+    let host = parts.next().unwrap_or_default();
+    if let Some(prefix) = parts.next() {
+        prefix
+            .parse::<u8>()
+            .map_err(|_| anyhow!("Invalid prefix length in address {}", address))?;
+    }
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+
+    let octets: Vec<u8> = host
+        .split('.')
+        .map(|octet| octet.parse::<u8>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("Invalid IP address {}", address))?;
+    match octets.as_slice() {
+        [a, b, c, d] => Ok(IpAddr::V4(Ipv4Addr::new(*a, *b, *c, *d))),
+        _ => Err(anyhow!("Invalid IP address {}", address)),
+    }
+}
+
+/// Parse a list of IPs, one per line, ignoring blank lines and `#` comments
+pub fn parse_ip_list(content: &str) -> Result<Vec<IpAddr>, Error> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let ip = parse_ip_from_cidr(line).with_context(|| format!("Line {}", index + 1))?;
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    }
+    Ok(ips)
+}
+
+/// Read a list of IPs from the given file, see [`parse_ip_list`]
+pub fn read_ip_list(path: &str) -> Result<Vec<IpAddr>, Error> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Unable to read IP list {}", path))?;
+    parse_ip_list(&content).with_context(|| format!("Invalid IP list {}", path))
+}
+
+/// Normalize a hostname for comparison: lowercased and without its domain suffix
+pub fn normalize_hostname(hostname: &str) -> String {
+    hostname
+        .trim()
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Extracts the canonical (comparable) name of a device from its hostname
+#[derive(Debug, Clone, Default)]
+pub struct HostnameNormalizer {
+    /// Suffixes removed from the hostnames, when none is configured everything after the
+    /// first dot is removed
+    pub strip_suffixes: Vec<String>,
+    /// A regex whose first capture group is the canonical name, taking precedence over the
+    /// suffixes for the hostnames it matches
+    pub regex: Option<Regex>,
+}
+
+impl HostnameNormalizer {
+    /// The canonical, lowercased, name of the given hostname
+    pub fn normalize(&self, hostname: &str) -> String {
+        let hostname = hostname.trim();
+        if let Some(regex) = &self.regex {
+            if let Some(name) = regex
+                .captures(hostname)
+                .and_then(|captures| captures.get(1))
+            {
+                return name.as_str().to_lowercase();
+            }
+        }
+        if self.strip_suffixes.is_empty() {
+            return normalize_hostname(hostname);
+        }
+
+        let hostname = hostname.to_lowercase();
+        for suffix in &self.strip_suffixes {
+            if let Some(name) = hostname.strip_suffix(&suffix.to_lowercase()) {
+                return name.to_string();
+            }
+        }
+        hostname
+    }
+}
+
+/// The placeholders of a [`NameTemplate`]
+const NAME_PLACEHOLDERS: [&str; 3] = ["name", "site", "tenant"];
+
+/// The Netshot name of the registered devices, rendered from the `{name}`, `{site}` and
+/// `{tenant}` of their Netbox object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    /// The name of the given device, `None` when a placeholder has no value for it
+    pub fn render(&self, device: &DeviceRef) -> Option<String> {
+        let mut name = self.0.clone();
+        for placeholder in NAME_PLACEHOLDERS.iter() {
+            let pattern = format!("{{{}}}", placeholder);
+            if !name.contains(&pattern) {
+                continue;
+            }
+            let value = match *placeholder {
+                "name" => Some(&device.hostname),
+                "site" => device.site.as_ref(),
+                _ => device.tenant.as_ref(),
+            }?;
+            name = name.replace(&pattern, value);
+        }
+        Some(name)
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        if template.trim().is_empty() {
+            return Err(anyhow!("Empty name template"));
+        }
+        let placeholders = Regex::new(r"\{([^{}]*)\}").unwrap();
+        for captures in placeholders.captures_iter(template) {
+            if !NAME_PLACEHOLDERS.contains(&&captures[1]) {
+                return Err(anyhow!(
+                    "Unknown placeholder {} in the name template, expected {{name}}, {{site}} or {{tenant}}",
+                    &captures[0]
+                ));
+            }
+        }
+        Ok(NameTemplate(template.to_string()))
+    }
+}
+
+/// Match the devices left over by the IP comparison using their normalized hostname.
+///
+/// Matched devices are removed from both lists and returned as (netbox, netshot) pairs
+pub fn match_by_name(
+    normalizer: &HostnameNormalizer,
+    devices_to_register: &mut Vec<DeviceRef>,
+    devices_to_disable: &mut Vec<DeviceRef>,
+) -> Vec<(DeviceRef, DeviceRef)> {
+    let mut netshot_by_name: HashMap<String, DeviceRef> = devices_to_disable
+        .iter()
+        .map(|device| (normalizer.normalize(&device.hostname), device.clone()))
+        .collect();
+
+    let mut matches = Vec::new();
+    devices_to_register.retain(|device| {
+        match netshot_by_name.remove(&normalizer.normalize(&device.hostname)) {
+            Some(netshot_device) => {
+                matches.push((device.clone(), netshot_device));
+                false
+            }
+            None => true,
+        }
+    });
+
+    devices_to_disable.retain(|device| !matches.iter().any(|(_, matched)| matched == device));
+    matches
+}
+
+/// Apply `f` to every item using at most `concurrency` worker threads, returning each item with its result
+pub fn run_parallel<T, R, F>(items: Vec<T>, concurrency: usize, f: F) -> Vec<(T, R)>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = concurrency.max(1).min(items.len().max(1));
+    let queue = Mutex::new(items.into_iter());
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let item = match queue.lock().unwrap().next() {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = f(&item);
+                results.lock().unwrap().push((item, result));
+            });
+        }
+    });
+
+    results.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Source;
+
+    #[test]
+    fn name_template() {
+        let device = DeviceRef::new(
+            "10.0.0.1".parse().unwrap(),
+            "sw1".to_string(),
+            Source::Device,
+        )
+        .with_location(Some("dc1".to_string()), None);
+        let template: NameTemplate = "{site}-{name}".parse().unwrap();
+        assert_eq!(template.render(&device).as_deref(), Some("dc1-sw1"));
+
+        let template: NameTemplate = "{name}.{tenant}".parse().unwrap();
+        assert_eq!(template.render(&device), None);
+
+        let template: NameTemplate = "net-{name}-{name}".parse().unwrap();
+        assert_eq!(template.render(&device).as_deref(), Some("net-sw1-sw1"));
+
+        assert!("{site}-{hostname}".parse::<NameTemplate>().is_err());
+        assert!(" ".parse::<NameTemplate>().is_err());
+    }
+
+    #[test]
+    fn parse_ip_list_content() {
+        let content = "# Core routers\n10.0.0.1\n\n2001:db8::1/128  # IPv6 only\n10.0.0.1\n";
+        let ips = parse_ip_list(content).unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_ip_list_invalid_line() {
+        let error = parse_ip_list("10.0.0.1\nnot-an-ip\n").unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Line 2"));
+    }
+
+    #[test]
+    fn parse_ipv4_cidr() {
+        let ip = parse_ip_from_cidr("10.0.0.1/32").unwrap();
+        assert_eq!(ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(parse_ip_from_cidr("10.0.0.1").unwrap(), ip);
+        assert_eq!(parse_ip_from_cidr("010.000.000.001/24").unwrap(), ip);
+    }
+
+    #[test]
+    fn parse_ipv6_cidr() {
+        let ip = parse_ip_from_cidr("2001:DB8:0:0::1/128").unwrap();
+        assert_eq!(ip.to_string(), "2001:db8::1");
+        assert_eq!(parse_ip_from_cidr("::/128").unwrap().to_string(), "::");
+    }
+
+    #[test]
+    fn parse_invalid_cidr() {
+        assert!(parse_ip_from_cidr("").is_err());
+        assert!(parse_ip_from_cidr("10.0.0/24").is_err());
+        assert!(parse_ip_from_cidr("10.0.0.256").is_err());
+        assert!(parse_ip_from_cidr("10.0.0.1/abc").is_err());
+        assert!(parse_ip_from_cidr("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn hostname_normalization() {
+        assert_eq!(normalize_hostname("SW1.dc1.example.com"), "sw1");
+        assert_eq!(normalize_hostname("rtr2"), "rtr2");
+    }
+
+    #[test]
+    fn fqdn_suffix_stripping() {
+        let normalizer = HostnameNormalizer {
+            strip_suffixes: vec![".dc1.example.com".to_string(), ".example.com".to_string()],
+            regex: None,
+        };
+        assert_eq!(normalizer.normalize("SW1.dc1.example.com"), "sw1");
+        assert_eq!(normalizer.normalize("sw1.dc2.example.com"), "sw1.dc2");
+        assert_eq!(normalizer.normalize("sw1.other.org"), "sw1.other.org");
+        assert_eq!(normalizer.normalize("sw1"), "sw1");
+    }
+
+    #[test]
+    fn capturing_regex() {
+        let normalizer = HostnameNormalizer {
+            strip_suffixes: Vec::new(),
+            regex: Some(Regex::new(r"^(?:prod-)?([^.]+)").unwrap()),
+        };
+        assert_eq!(normalizer.normalize("prod-SW1.dc1.example.com"), "sw1");
+        assert_eq!(normalizer.normalize("rtr2"), "rtr2");
+
+        let unmatched = HostnameNormalizer {
+            strip_suffixes: Vec::new(),
+            regex: Some(Regex::new(r"^dc\d+-(\w+)$").unwrap()),
+        };
+        assert_eq!(unmatched.normalize("dc1-sw1"), "sw1");
+        assert_eq!(unmatched.normalize("SW3.example.com"), "sw3");
+    }
+
+    #[test]
+    fn match_moved_device_by_name() {
+        let device = |ip: &str, hostname: &str, source: Source| {
+            DeviceRef::new(ip.parse().unwrap(), hostname.to_string(), source)
+        };
+        let moved_new = device("10.0.0.2", "sw1.example.com", Source::Device);
+        let moved_old = device("10.0.0.1", "SW1", Source::Netshot);
+        let added = device("10.0.0.4", "sw4", Source::Device);
+        let gone = device("10.0.0.3", "sw3", Source::Netshot);
+
+        let mut devices_to_register = vec![moved_new.clone(), added.clone()];
+        let mut devices_to_disable = vec![moved_old.clone(), gone.clone()];
+        let matches = match_by_name(
+            &HostnameNormalizer::default(),
+            &mut devices_to_register,
+            &mut devices_to_disable,
+        );
+
+        assert_eq!(matches, vec![(moved_new, moved_old)]);
+        assert_eq!(devices_to_register, vec![added]);
+        assert_eq!(devices_to_disable, vec![gone]);
+    }
+
+    #[test]
+    fn run_parallel_processes_every_item() {
+        let items: Vec<u32> = (0..100).collect();
+        let mut results = run_parallel(items, 8, |x| x * 2);
+        results.sort();
+
+        assert_eq!(results.len(), 100);
+        assert!(results.iter().all(|(x, y)| *y == x * 2));
+    }
+
+    #[test]
+    fn run_parallel_clamps_concurrency() {
+        let results = run_parallel(vec![1, 2, 3], 0, |x| x + 1);
+        assert_eq!(results.len(), 3);
+    }
+}
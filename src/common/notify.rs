@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+
+use crate::common::http::HttpClientOptions;
+use crate::rest::error::ApiError;
+use crate::SyncOutcome;
+
+/// The maximum number of changed devices listed by name in the notification text
+const TEXT_DEVICES_LIMIT: usize = 10;
+
+/// The summary sent to the webhook, `text` makes it displayable by Slack/Teams
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub text: String,
+    pub success: bool,
+    pub check: bool,
+    pub registered: usize,
+    pub disabled: usize,
+    pub enabled: usize,
+    pub renamed: usize,
+    pub errors: usize,
+    /// Every computed change as `action hostname(ip)`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The kind of API error that failed the run, see [`ApiError::category`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_category: Option<&'static str>,
+}
+
+/// Send run summaries to a webhook
+#[derive(Debug)]
+pub struct Notifier {
+    pub url: String,
+    pub client: reqwest::blocking::Client,
+}
+
+impl Notification {
+    /// Summarize the result of a synchronization run
+    pub fn from_result(check: bool, result: &Result<SyncOutcome, Error>) -> Self {
+        match result {
+            Ok(outcome) => {
+                let mode = if check { " (check mode)" } else { "" };
+                let changes: Vec<String> = outcome
+                    .actions()
+                    .iter()
+                    .map(|(action, device)| format!("{} {}", action, device))
+                    .collect();
+                let mut text = format!(
+                    "netbox2netshot run done{}: {} to register, {} to {}, {} to enable, {} to rename, {} errors",
+                    mode,
+                    outcome.devices_to_register.len(),
+                    outcome.devices_to_disable.len(),
+                    outcome.on_missing.action(),
+                    outcome.devices_to_enable.len(),
+                    outcome.devices_to_rename.len(),
+                    outcome.errors.len()
+                );
+                let snapshots = &outcome.report.snapshots;
+                if !snapshots.is_empty() {
+                    let succeeded = snapshots
+                        .iter()
+                        .filter(|entry| entry.success == Some(true))
+                        .count();
+                    text.push_str(&format!(
+                        ", {} of {} snapshots succeeded",
+                        succeeded,
+                        snapshots.len()
+                    ));
+                }
+                if outcome.report.skipped {
+                    text.push_str(", skipped as neither inventory changed");
+                }
+                if outcome.report.timed_out {
+                    text.push_str(", stopped by the run timeout");
+                }
+                if outcome.netbox_skipped > 0 {
+                    text.push_str(&format!(
+                        ", {} Netbox objects skipped without a usable management address",
+                        outcome.netbox_skipped
+                    ));
+                }
+                for change in changes.iter().take(TEXT_DEVICES_LIMIT) {
+                    text.push_str(&format!("\n- {}", change));
+                }
+                if changes.len() > TEXT_DEVICES_LIMIT {
+                    text.push_str(&format!(
+                        "\n- and {} more",
+                        changes.len() - TEXT_DEVICES_LIMIT
+                    ));
+                }
+                Notification {
+                    text,
+                    success: true,
+                    check,
+                    registered: outcome.devices_to_register.len(),
+                    disabled: outcome.devices_to_disable.len(),
+                    enabled: outcome.devices_to_enable.len(),
+                    renamed: outcome.devices_to_rename.len(),
+                    errors: outcome.errors.len(),
+                    changes,
+                    error: None,
+                    error_category: None,
+                }
+            }
+            Err(error) => Notification {
+                text: format!("netbox2netshot run failed: {:#}", error),
+                success: false,
+                check,
+                registered: 0,
+                disabled: 0,
+                enabled: 0,
+                renamed: 0,
+                errors: 1,
+                changes: Vec::new(),
+                error: Some(format!("{:#}", error)),
+                error_category: error
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<ApiError>())
+                    .map(ApiError::category),
+            },
+        }
+    }
+}
+
+impl Notifier {
+    /// Create a notifier posting to the given webhook URL
+    pub fn new(url: String, options: &HttpClientOptions) -> Result<Self, Error> {
+        Ok(Self {
+            url,
+            client: options.builder()?.build()?,
+        })
+    }
+
+    /// Post the notification to the webhook
+    pub fn send(&self, notification: &Notification) -> Result<(), Error> {
+        // The webhook URL is a credential, kept out of the error
+        let response = self
+            .client
+            .post(&self.url)
+            .json(notification)
+            .send()
+            .map_err(|error| error.without_url())?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Webhook answered with status {}",
+                response.status().to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Post the notification, logging a warning on failure so the caller can carry on
+    pub fn notify(&self, notification: &Notification) -> Result<(), Error> {
+        let result = self.send(notification);
+        match &result {
+            Ok(()) => log::debug!("Notification sent to the webhook"),
+            Err(error) => log::warn!("Failed to send the webhook notification: {:#}", error),
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::report::Report;
+    use crate::common::timing::Timings;
+    use crate::{DeviceRef, OnMissing, Source};
+
+    #[test]
+    fn success_notification_lists_devices() {
+        let devices_to_register = (1..=12)
+            .map(|i| {
+                DeviceRef::new(
+                    format!("10.0.0.{}", i).parse().unwrap(),
+                    format!("sw{}", i),
+                    Source::Device,
+                )
+            })
+            .collect();
+        let outcome = SyncOutcome {
+            devices_to_register,
+            devices_to_disable: vec![DeviceRef::new(
+                "10.0.1.1".parse().unwrap(),
+                "old-rtr".to_string(),
+                Source::Netshot,
+            )],
+            devices_to_enable: Vec::new(),
+            devices_to_rename: Vec::new(),
+            on_missing: OnMissing::Disable,
+            netbox_ip_collisions: 0,
+            netbox_skipped: 2,
+            netshot_duplicate_ips: 0,
+            failures: 0,
+            errors: Vec::new(),
+            report: Report::new(true).unwrap(),
+            timings: Timings::default(),
+            inventory_hashes: Default::default(),
+            inventories_unchanged: false,
+        };
+        let notification = Notification::from_result(true, &Ok(outcome));
+
+        assert_eq!(notification.changes.len(), 13);
+        assert_eq!(notification.changes[0], "register sw1(10.0.0.1)");
+        assert_eq!(notification.changes[12], "disable old-rtr(10.0.1.1)");
+        assert!(notification
+            .text
+            .contains("\n- register sw10(10.0.0.10)\n- and 3 more"));
+        assert!(notification.text.contains(", 2 Netbox objects skipped"));
+    }
+
+    #[test]
+    fn failure_notification() {
+        let notification = Notification::from_result(false, &Err(anyhow!("boom")));
+
+        assert!(!notification.success);
+        assert_eq!(notification.errors, 1);
+        assert_eq!(notification.error.unwrap(), "boom");
+        assert!(notification.error_category.is_none());
+    }
+
+    #[test]
+    fn api_failure_notification() {
+        let error = Error::from(ApiError::Unauthorized(401)).context("Unable to fetch Netbox");
+        let notification = Notification::from_result(false, &Err(error));
+
+        assert_eq!(notification.error_category, Some("unauthorized"));
+    }
+
+    #[test]
+    fn send_notification() {
+        let mock = mockito::mock("POST", "/webhook")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"success":false,"check":true,"errors":1}"#.to_string(),
+            ))
+            .create();
+
+        let notifier = Notifier::new(
+            format!("{}/webhook", mockito::server_url()),
+            &HttpClientOptions::default(),
+        )
+        .unwrap();
+        let notification = Notification::from_result(true, &Err(anyhow!("boom")));
+        notifier.send(&notification).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn failed_notification() {
+        let _mock = mockito::mock("POST", "/webhook").with_status(500).create();
+
+        let notifier = Notifier::new(
+            format!("{}/webhook", mockito::server_url()),
+            &HttpClientOptions::default(),
+        )
+        .unwrap();
+        let notification = Notification::from_result(true, &Err(anyhow!("boom")));
+
+        assert!(notifier.send(&notification).is_err());
+    }
+
+    #[test]
+    fn unreachable_webhook_url_not_in_error() {
+        let notifier = Notifier::new(
+            String::from("http://127.0.0.1:1/services/SECRETHOOK"),
+            &HttpClientOptions::default(),
+        )
+        .unwrap();
+        let notification = Notification::from_result(true, &Err(anyhow!("boom")));
+
+        let error = notifier.send(&notification).unwrap_err();
+        assert!(!format!("{:#}", error).contains("SECRETHOOK"));
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Error, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+
+use crate::SyncOutcome;
+
+/// The plan lines of the computed changes, sorted by IP (then by action for a shared IP)
+pub fn plan_lines(outcome: &SyncOutcome) -> Vec<String> {
+    let missing = outcome.on_missing.action();
+    let mut lines: Vec<(IpAddr, String)> = Vec::new();
+    for device in &outcome.devices_to_register {
+        lines.push((
+            device.ip,
+            format!("+ register {} ({})", device.ip, device.hostname),
+        ));
+    }
+    for device in &outcome.devices_to_disable {
+        lines.push((
+            device.ip,
+            format!("- {} {} ({})", missing, device.ip, device.hostname),
+        ));
+    }
+    for device in &outcome.devices_to_enable {
+        lines.push((
+            device.ip,
+            format!("~ enable {} ({})", device.ip, device.hostname),
+        ));
+    }
+    for rename in &outcome.devices_to_rename {
+        lines.push((
+            rename.device.ip,
+            format!(
+                "~ rename {} ({} -> {})",
+                rename.device.ip, rename.netshot_name, rename.device.hostname
+            ),
+        ));
+    }
+    lines.sort_by_key(|(ip, _)| *ip);
+    lines.into_iter().map(|(_, line)| line).collect()
+}
+
+/// Write the human-readable plan of the computed changes, one `+`/`-`/`~` line per change
+pub fn write_plan<W: Write>(writer: &mut W, outcome: &SyncOutcome) -> Result<(), Error> {
+    let lines = plan_lines(outcome);
+    if lines.is_empty() {
+        writeln!(writer, "# No changes, Netshot is in line with Netbox")?;
+    }
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Write the plan of the computed changes to the given path
+pub fn write_plan_file(path: &str, outcome: &SyncOutcome) -> Result<(), Error> {
+    let file =
+        File::create(path).with_context(|| format!("Unable to create plan file {}", path))?;
+    let mut writer = BufWriter::new(file);
+    write_plan(&mut writer, outcome)
+        .and_then(|_| Ok(writer.flush()?))
+        .with_context(|| format!("Unable to write plan file {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::report::Report;
+    use crate::common::timing::Timings;
+    use crate::{DeviceRef, OnMissing, Source};
+
+    fn device(ip: &str, hostname: &str, source: Source) -> DeviceRef {
+        DeviceRef::new(ip.parse().unwrap(), hostname.to_string(), source)
+    }
+
+    fn outcome(on_missing: OnMissing) -> SyncOutcome {
+        SyncOutcome {
+            devices_to_register: vec![
+                device("10.0.0.10", "sw10", Source::Device),
+                device("10.0.0.1", "sw1", Source::Device),
+            ],
+            devices_to_disable: vec![device("10.0.0.2", "rtr2", Source::Netshot)],
+            devices_to_enable: Vec::new(),
+            devices_to_rename: Vec::new(),
+            on_missing,
+            netbox_ip_collisions: 0,
+            netbox_skipped: 0,
+            netshot_duplicate_ips: 0,
+            failures: 0,
+            errors: Vec::new(),
+            report: Report::new(true).unwrap(),
+            timings: Timings::default(),
+            inventory_hashes: Default::default(),
+            inventories_unchanged: false,
+        }
+    }
+
+    #[test]
+    fn plan_sorted_by_ip() {
+        let mut output = Vec::new();
+        write_plan(&mut output, &outcome(OnMissing::Disable)).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+ register 10.0.0.1 (sw1)\n- disable 10.0.0.2 (rtr2)\n+ register 10.0.0.10 (sw10)\n"
+        );
+        assert_eq!(
+            plan_lines(&outcome(OnMissing::Delete))[1],
+            "- delete 10.0.0.2 (rtr2)"
+        );
+    }
+
+    #[test]
+    fn empty_plan() {
+        let mut empty = outcome(OnMissing::Disable);
+        empty.devices_to_register.clear();
+        empty.devices_to_disable.clear();
+
+        let mut output = Vec::new();
+        write_plan(&mut output, &empty).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# No changes, Netshot is in line with Netbox\n"
+        );
+    }
+}
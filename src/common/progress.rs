@@ -0,0 +1,61 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The default number of processed items between two progress logs
+pub const DEFAULT_PROGRESS_EVERY: usize = 500;
+
+/// Log the progress of a long phase every `every` processed items, e.g. `registered 1500/4200`.
+///
+/// Shared by the worker threads, a zero `every` disabling the logs
+#[derive(Debug)]
+pub struct Progress {
+    action: &'static str,
+    total: usize,
+    every: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(action: &'static str, total: usize, every: usize) -> Self {
+        Self {
+            action,
+            total,
+            every,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count processed items, logging when a multiple of `every` is crossed and returning whether
+    /// it did
+    pub fn add(&self, count: usize) -> bool {
+        let before = self.done.fetch_add(count, Ordering::SeqCst);
+        let after = before + count;
+        if self.every == 0 || after / self.every == before / self.every {
+            return false;
+        }
+        // The total isn't always known, e.g. with the Netbox cursor pagination
+        if self.total == 0 {
+            log::info!("{} {}", self.action, after);
+        } else {
+            log::info!("{} {}/{}", self.action, after, self.total);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_every_n_items() {
+        let progress = Progress::new("registered", 1200, 500);
+        assert!(!progress.add(499));
+        assert!(progress.add(1));
+        assert!(!progress.add(300));
+        assert!(progress.add(300));
+        assert!(!progress.add(100));
+
+        let disabled = Progress::new("registered", 1200, 0);
+        assert!(!disabled.add(1200));
+    }
+}
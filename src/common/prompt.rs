@@ -0,0 +1,44 @@
+use anyhow::{Error, Result};
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Are both the standard input and output attached to a terminal
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Ask a yes/no question, anything but `y` or `yes` (case insensitive) is a no
+pub fn confirm<R: BufRead, W: Write>(
+    question: &str,
+    input: &mut R,
+    output: &mut W,
+) -> Result<bool, Error> {
+    write!(output, "{} [y/N] ", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(input: &str) -> bool {
+        let mut output = Vec::new();
+        let confirmed = confirm("Proceed?", &mut input.as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Proceed? [y/N] ");
+        confirmed
+    }
+
+    #[test]
+    fn confirmation() {
+        assert!(answer("y\n"));
+        assert!(answer("Yes\n"));
+        assert!(!answer("\n"));
+        assert!(!answer("n\n"));
+        assert!(!answer("whatever\n"));
+        assert!(!answer(""));
+    }
+}
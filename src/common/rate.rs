@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Space the requests evenly to stay under a number of requests per second (a leaky bucket
+/// without burst).
+///
+/// Shared by every client and worker thread, each request reserving the next free slot
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Wait for the next free slot, returning how long it waited
+    pub fn acquire(&self) -> Duration {
+        let now = Instant::now();
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = next_slot.map_or(now, |next| next.max(now));
+            *next_slot = Some(slot + self.interval);
+            slot
+        };
+        let wait = slot.saturating_duration_since(now);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn space_requests_across_threads() {
+        let limiter = Arc::new(RateLimiter::new(50));
+        let start = Instant::now();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || {
+                    for _ in 0..3 {
+                        limiter.acquire();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // 12 requests at 50 per second, the first one being sent right away
+        assert!(start.elapsed() >= Duration::from_millis(220));
+    }
+
+    #[test]
+    fn no_wait_when_idle() {
+        let limiter = RateLimiter::new(10);
+        assert!(limiter.acquire().is_zero());
+        thread::sleep(Duration::from_millis(120));
+        assert!(limiter.acquire().is_zero());
+    }
+}
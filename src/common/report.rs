@@ -0,0 +1,382 @@
+use anyhow::{Context, Error, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::common::state::StateDelta;
+use crate::rest::error::{ApiError, ApiResult};
+use crate::{DeviceRef, Source};
+
+/// A device planned for (or affected by) a change
+#[derive(Debug, Serialize)]
+pub struct ReportEntry {
+    pub ip: String,
+    pub hostname: String,
+    pub source: Source,
+    /// Outcome of the change, absent when no change was pushed (check mode)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The kind of error, see [`ApiError::category`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_category: Option<&'static str>,
+    /// Netshot was already in the desired state, e.g. after a concurrent run, nothing was changed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+}
+
+/// A non-fatal error met during the run
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEntry {
+    /// The step of the run the error happened in, e.g. `netbox_inventory` or `register`
+    pub phase: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub message: String,
+}
+
+/// The number of devices per action
+#[derive(Debug, Default, Serialize)]
+pub struct ReportCounts {
+    pub register: usize,
+    pub disable: usize,
+    pub enable: usize,
+    pub rename: usize,
+    /// The snapshots waited for with `--wait-snapshots`
+    pub snapshot: usize,
+    pub failures: usize,
+    /// The successful changes that found Netshot already in the desired state
+    pub unchanged: usize,
+    /// The changes left for the next runs by `--limit`
+    pub deferred: usize,
+    /// The changes of the Netbox objects per source (device or VM), the devices to disable being
+    /// missing from Netbox
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_source: BTreeMap<Source, SourceCounts>,
+}
+
+/// The number of Netbox objects of a source per action
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SourceCounts {
+    pub register: usize,
+    pub enable: usize,
+    pub rename: usize,
+}
+
+/// The machine-readable result of a synchronization run
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub timestamp: String,
+    pub check: bool,
+    pub counts: ReportCounts,
+    /// Netbox objects skipped because another one already uses their IP
+    pub netbox_ip_collisions: usize,
+    /// Netbox objects skipped for lack of a usable management address
+    pub netbox_skipped: usize,
+    /// Netshot devices left out because another one has the same management IP
+    pub netshot_duplicate_ips: usize,
+    pub devices_to_register: Vec<ReportEntry>,
+    pub devices_to_disable: Vec<ReportEntry>,
+    pub devices_to_enable: Vec<ReportEntry>,
+    /// The renamed devices, with their Netbox name
+    pub devices_to_rename: Vec<ReportEntry>,
+    /// The outcome of the snapshots of the registered devices, when waited for
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<ReportEntry>,
+    /// Every non-fatal error of the run, failed changes included
+    pub errors: Vec<ErrorEntry>,
+    /// What changed since the previous run, with `--state-file`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since_last_run: Option<StateDelta>,
+    /// Whether the run timeout stopped the writes, the remaining changes being deferred
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+    /// Whether the disable step was skipped because Netbox returned too few devices, the other
+    /// changes being pushed anyway
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub disable_blocked: bool,
+    /// Whether neither inventory changed since the previous daemon cycle, nothing being compared
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+}
+
+impl ReportEntry {
+    /// An entry for a change that wasn't pushed
+    pub fn planned(device: &DeviceRef) -> Self {
+        Self {
+            ip: device.ip.to_string(),
+            hostname: device.hostname.clone(),
+            source: device.source,
+            success: None,
+            error: None,
+            error_category: None,
+            unchanged: false,
+        }
+    }
+
+    /// An entry for a change that was pushed, with its outcome, the ones deferred by the run
+    /// timeout staying planned
+    pub fn applied<T>(device: &DeviceRef, result: &ApiResult<T>) -> Self {
+        if let Err(ApiError::Deferred) = result {
+            return Self::planned(device);
+        }
+        Self {
+            ip: device.ip.to_string(),
+            hostname: device.hostname.clone(),
+            source: device.source,
+            success: Some(result.is_ok()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            error_category: result.as_ref().err().map(ApiError::category),
+            unchanged: false,
+        }
+    }
+
+    /// Flag a successful change as a no-op, Netshot being already in the desired state
+    pub fn with_unchanged(mut self, unchanged: bool) -> Self {
+        self.unchanged = unchanged;
+        self
+    }
+}
+
+impl ErrorEntry {
+    /// An error not tied to a device
+    pub fn new(phase: &'static str, message: impl ToString) -> Self {
+        Self {
+            phase,
+            ip: None,
+            hostname: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// An error about the given device, either side
+    pub fn for_device(phase: &'static str, device: &DeviceRef, message: impl ToString) -> Self {
+        Self {
+            ip: Some(device.ip.to_string()),
+            hostname: Some(device.hostname.clone()),
+            ..Self::new(phase, message)
+        }
+    }
+
+    /// An error about a device known only by its name, e.g. without a usable IP
+    pub fn for_hostname(phase: &'static str, hostname: &str, message: impl ToString) -> Self {
+        Self {
+            hostname: Some(hostname.to_string()),
+            ..Self::new(phase, message)
+        }
+    }
+
+    /// The error of a failed change from its report entry
+    pub fn from_entry(phase: &'static str, entry: &ReportEntry) -> Self {
+        Self {
+            ip: Some(entry.ip.clone()),
+            hostname: Some(entry.hostname.clone()),
+            ..Self::new(phase, entry.error.as_deref().unwrap_or("unknown error"))
+        }
+    }
+}
+
+impl fmt::Display for ErrorEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.phase)?;
+        match (&self.ip, &self.hostname) {
+            (Some(ip), Some(hostname)) => write!(f, " {} ({})", ip, hostname)?,
+            (Some(ip), None) => write!(f, " {}", ip)?,
+            (None, Some(hostname)) => write!(f, " {}", hostname)?,
+            (None, None) => {}
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Report {
+    /// Create an empty report timestamped now (UTC)
+    pub fn new(check: bool) -> Result<Self, Error> {
+        Ok(Self {
+            timestamp: OffsetDateTime::now_utc().format(&Rfc3339)?,
+            check,
+            counts: ReportCounts::default(),
+            netbox_ip_collisions: 0,
+            netbox_skipped: 0,
+            netshot_duplicate_ips: 0,
+            devices_to_register: Vec::new(),
+            devices_to_disable: Vec::new(),
+            devices_to_enable: Vec::new(),
+            devices_to_rename: Vec::new(),
+            snapshots: Vec::new(),
+            errors: Vec::new(),
+            since_last_run: None,
+            timed_out: false,
+            disable_blocked: false,
+            skipped: false,
+        })
+    }
+
+    /// Compute the counts from the entries
+    pub fn update_counts(&mut self) {
+        let changes = || {
+            self.devices_to_register
+                .iter()
+                .chain(&self.devices_to_disable)
+                .chain(&self.devices_to_enable)
+                .chain(&self.devices_to_rename)
+        };
+        self.counts = ReportCounts {
+            register: self.devices_to_register.len(),
+            disable: self.devices_to_disable.len(),
+            enable: self.devices_to_enable.len(),
+            rename: self.devices_to_rename.len(),
+            snapshot: self.snapshots.len(),
+            failures: changes()
+                .chain(&self.snapshots)
+                .filter(|entry| entry.success == Some(false))
+                .count(),
+            unchanged: changes().filter(|entry| entry.unchanged).count(),
+            deferred: if self.check {
+                0
+            } else {
+                changes().filter(|entry| entry.success.is_none()).count()
+            },
+            by_source: BTreeMap::new(),
+        };
+
+        let by_source = &mut self.counts.by_source;
+        let from_netbox = |entry: &&ReportEntry| entry.source != Source::Netshot;
+        for entry in self.devices_to_register.iter().filter(from_netbox) {
+            by_source.entry(entry.source).or_default().register += 1;
+        }
+        for entry in self.devices_to_enable.iter().filter(from_netbox) {
+            by_source.entry(entry.source).or_default().enable += 1;
+        }
+        for entry in self.devices_to_rename.iter().filter(from_netbox) {
+            by_source.entry(entry.source).or_default().rename += 1;
+        }
+    }
+
+    /// Write the report as JSON to the given path
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        let file =
+            File::create(path).with_context(|| format!("Unable to create report file {}", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .with_context(|| format!("Unable to write report file {}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(ip: &str, hostname: &str, source: Source) -> DeviceRef {
+        DeviceRef::new(ip.parse().unwrap(), hostname.to_string(), source)
+    }
+
+    #[test]
+    fn count_failures() {
+        let mut report = Report::new(false).unwrap();
+        report.devices_to_register.push(ReportEntry::applied(
+            &device("1.2.3.4", "a", Source::Device),
+            &Ok(()),
+        ));
+        report.devices_to_disable.push(ReportEntry::applied::<()>(
+            &device("1.2.3.5", "b", Source::Netshot),
+            &Err(ApiError::Server(500)),
+        ));
+        report.update_counts();
+
+        assert_eq!(report.counts.register, 1);
+        assert_eq!(report.counts.disable, 1);
+        assert_eq!(report.counts.failures, 1);
+        assert_eq!(report.devices_to_disable[0].error_category, Some("server"));
+    }
+
+    #[test]
+    fn count_by_source() {
+        let mut report = Report::new(true).unwrap();
+        report.devices_to_register = vec![
+            ReportEntry::planned(&device("1.2.3.4", "a", Source::Device)),
+            ReportEntry::planned(&device("1.2.3.5", "b", Source::Vm)),
+            ReportEntry::planned(&device("1.2.3.6", "c", Source::Vm)),
+        ];
+        report.devices_to_disable = vec![ReportEntry::planned(&device(
+            "1.2.3.7",
+            "d",
+            Source::Netshot,
+        ))];
+        report.devices_to_enable = vec![ReportEntry::planned(&device("1.2.3.8", "e", Source::Vm))];
+        report.update_counts();
+
+        let by_source = &report.counts.by_source;
+        assert_eq!(by_source.len(), 2);
+        assert_eq!(
+            by_source[&Source::Device],
+            SourceCounts {
+                register: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            by_source[&Source::Vm],
+            SourceCounts {
+                register: 2,
+                enable: 1,
+                rename: 0,
+            }
+        );
+        assert!(serde_json::to_string(&report.counts)
+            .unwrap()
+            .contains(r#""by_source":{"device":{"register":1,"enable":0,"rename":0},"vm":"#));
+
+        report.devices_to_register.clear();
+        report.devices_to_enable.clear();
+        report.update_counts();
+        assert!(!serde_json::to_string(&report.counts)
+            .unwrap()
+            .contains("by_source"));
+    }
+
+    #[test]
+    fn planned_entries_serialization() {
+        let entry = ReportEntry::planned(&device("1.2.3.4", "test-device", Source::Vm));
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"ip":"1.2.3.4","hostname":"test-device","source":"vm"}"#
+        );
+    }
+
+    #[test]
+    fn error_entries_display() {
+        let device = device("1.2.3.4", "test-device", Source::Netshot);
+        let entries = [
+            ErrorEntry::for_device("disable", &device, "boom"),
+            ErrorEntry::for_hostname("netbox_inventory", "test-device", "no primary IP"),
+            ErrorEntry::new("notify", "Webhook answered with status 500"),
+        ];
+        let lines: Vec<String> = entries.iter().map(ErrorEntry::to_string).collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "disable 1.2.3.4 (test-device): boom",
+                "netbox_inventory test-device: no primary IP",
+                "notify: Webhook answered with status 500",
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&entries[1]).unwrap(),
+            r#"{"phase":"netbox_inventory","hostname":"test-device","message":"no primary IP"}"#
+        );
+    }
+
+    #[test]
+    fn unwritable_path() {
+        let report = Report::new(true).unwrap();
+        assert!(report.write("/nonexistent/report.json").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use rand::Rng;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::RETRY_AFTER;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::common::rate::RateLimiter;
+use crate::rest::error::{ApiError, ApiResult};
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 200;
+const DEFAULT_MAX_RATE_WAIT_SECS: u64 = 60;
+
+/// The retry behaviour applied to the HTTP requests sent by the clients
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub retry_writes: bool,
+    /// Upper bound of the wait requested by a rate-limiting server
+    pub max_rate_wait: Duration,
+    /// The limiter every attempt goes through, shared by all the clients
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(DEFAULT_MAX_RETRIES, DEFAULT_BASE_DELAY_MS, false)
+    }
+}
+
+impl RetryPolicy {
+    /// Create a retry policy, `retry_writes` allows retrying non-idempotent requests
+    pub fn new(max_retries: u32, base_delay_ms: u64, retry_writes: bool) -> Self {
+        Self {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+            retry_writes,
+            max_rate_wait: Duration::from_secs(DEFAULT_MAX_RATE_WAIT_SECS),
+            rate_limiter: None,
+        }
+    }
+
+    /// Cap the wait requested through `Retry-After` by rate-limited responses
+    pub fn with_max_rate_wait(mut self, max_rate_wait_secs: u64) -> Self {
+        self.max_rate_wait = Duration::from_secs(max_rate_wait_secs);
+        self
+    }
+
+    /// Send the requests (retries included) through the given limiter
+    pub fn with_rate_limiter(mut self, rate_limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Wait for the rate limiter, if any, before sending a request
+    fn throttle(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let wait = rate_limiter.acquire();
+            if !wait.is_zero() {
+                log::trace!("Request delayed by {:?} by the rate limit", wait);
+            }
+        }
+    }
+
+    /// Compute the delay to wait before the given retry attempt (starting at 1), exponential with jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self.base_delay * 2u32.saturating_pow(attempt.saturating_sub(1));
+        let jitter_ms = rand::thread_rng().gen_range(0..=self.base_delay.as_millis() as u64);
+        exponential + Duration::from_millis(jitter_ms)
+    }
+
+    /// Send the request, retrying on connection errors and 5xx responses.
+    ///
+    /// Rate-limited (429) responses are retried even for writes as the server didn't process
+    /// them, waiting for what `Retry-After` requires (up to `max_rate_wait`).
+    /// All the retries share the same attempt counter.
+    pub fn send(&self, request: RequestBuilder, idempotent: bool) -> ApiResult<Response> {
+        let max_retries = if idempotent || self.retry_writes {
+            self.max_retries
+        } else {
+            0
+        };
+
+        let mut attempt = 0;
+        loop {
+            self.throttle();
+            // Requests with a streamed body can't be replayed, send them once
+            let current = match request.try_clone() {
+                Some(current) => current,
+                None => return Ok(request.send()?),
+            };
+
+            let (reason, requested_delay) = match current.send().map_err(ApiError::from) {
+                Ok(response) => match ApiError::from_status(response.status()) {
+                    Some(ApiError::RateLimited) if attempt < self.max_retries => {
+                        let delay = response
+                            .headers()
+                            .get(RETRY_AFTER)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(parse_retry_after);
+                        (format!("got status {}", response.status()), delay)
+                    }
+                    Some(error) if error.is_retryable() && attempt < max_retries => {
+                        (format!("got status {}", response.status()), None)
+                    }
+                    _ => return Ok(response),
+                },
+                Err(error) if error.is_retryable() && attempt < max_retries => {
+                    (error.to_string(), None)
+                }
+                Err(error) => return Err(error),
+            };
+
+            attempt += 1;
+            let delay = match requested_delay {
+                Some(delay) if delay > self.max_rate_wait => {
+                    log::warn!(
+                        "Server requested to wait {:?}, capping to {:?}",
+                        delay,
+                        self.max_rate_wait
+                    );
+                    self.max_rate_wait
+                }
+                Some(delay) => delay,
+                None => self.delay(attempt),
+            };
+            log::debug!(
+                "Request failed ({}), retry attempt {}/{} in {:?}",
+                reason,
+                attempt,
+                self.max_retries,
+                delay
+            );
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Parse a `Retry-After` header value, either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito;
+
+    fn fast_policy(retry_writes: bool) -> RetryPolicy {
+        RetryPolicy::new(2, 1, retry_writes)
+    }
+
+    #[test]
+    fn exponential_delay() {
+        let policy = RetryPolicy::new(3, 100, false);
+        assert!(policy.delay(1) >= Duration::from_millis(100));
+        assert!(policy.delay(1) <= Duration::from_millis(200));
+        assert!(policy.delay(3) >= Duration::from_millis(400));
+        assert!(policy.delay(3) <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retry_after_parsing() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let delay = parse_retry_after(&future).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn retry_rate_limited_writes() {
+        let mock = mockito::mock("POST", "/retry")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(3)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.post(format!("{}/retry", mockito::server_url()));
+        let response = fast_policy(false).send(request, false).unwrap();
+
+        assert_eq!(response.status().as_u16(), 429);
+        mock.assert();
+    }
+
+    #[test]
+    fn cap_rate_limit_wait() {
+        let mock = mockito::mock("GET", "/retry")
+            .with_status(429)
+            .with_header("Retry-After", "3600")
+            .expect(2)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.get(format!("{}/retry", mockito::server_url()));
+        let policy = RetryPolicy::new(1, 1, false).with_max_rate_wait(0);
+        policy.send(request, true).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn rate_limited_retries() {
+        let mock = mockito::mock("GET", "/throttled")
+            .with_status(502)
+            .expect(3)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.get(format!("{}/throttled", mockito::server_url()));
+        let policy = fast_policy(false).with_rate_limiter(Some(Arc::new(RateLimiter::new(20))));
+        let start = std::time::Instant::now();
+        policy.send(request, true).unwrap();
+
+        mock.assert();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn retry_server_errors() {
+        let mock = mockito::mock("GET", "/retry")
+            .with_status(502)
+            .expect(3)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.get(format!("{}/retry", mockito::server_url()));
+        let response = fast_policy(false).send(request, true).unwrap();
+
+        assert_eq!(response.status().as_u16(), 502);
+        mock.assert();
+    }
+
+    #[test]
+    fn no_retry_on_client_errors() {
+        let mock = mockito::mock("GET", "/retry")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.get(format!("{}/retry", mockito::server_url()));
+        fast_policy(false).send(request, true).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn no_retry_on_writes() {
+        let mock = mockito::mock("POST", "/retry")
+            .with_status(503)
+            .expect(1)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.post(format!("{}/retry", mockito::server_url()));
+        fast_policy(false).send(request, false).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn retry_on_writes_when_allowed() {
+        let mock = mockito::mock("POST", "/retry")
+            .with_status(503)
+            .expect(3)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let request = client.post(format!("{}/retry", mockito::server_url()));
+        fast_policy(true).send(request, false).unwrap();
+
+        mock.assert();
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::str::FromStr;
+
+/// A sensitive value (API token) that is never displayed by `Debug`, read from the
+/// configuration file as a plain string
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    /// The actual value, to be passed to the clients only
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(value.to_string()))
+    }
+}
+
+/// Read a token from the given file (`-` being the standard input), trimming whitespaces
+pub fn read_token(path: &str) -> Result<Secret, Error> {
+    let content = if path == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Unable to read the token from the standard input")?;
+        content
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Unable to read token file {}", path))?
+    };
+
+    let token = content.trim();
+    if token.is_empty() {
+        return Err(anyhow!("The token read from {} is empty", path));
+    }
+    Ok(Secret(token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_debug() {
+        let secret: Secret = "my-token".parse().unwrap();
+        assert_eq!(format!("{:?}", Some(secret.clone())), "Some(Secret(***))");
+        assert_eq!(secret.expose(), "my-token");
+    }
+
+    #[test]
+    fn token_file() {
+        let path = std::env::temp_dir().join("netbox2netshot-token-file");
+        fs::write(&path, "  my-token\n").unwrap();
+        let token = read_token(path.to_str().unwrap()).unwrap();
+        assert_eq!(token.expose(), "my-token");
+
+        fs::write(&path, "\n").unwrap();
+        assert!(read_token(path.to_str().unwrap()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+
+use crate::common::report::{Report, ReportEntry};
+use crate::DeviceRef;
+
+/// The hashes of both simplified inventories, telling a daemon cycle whether anything changed
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryHashes {
+    pub netbox: String,
+    pub netshot: String,
+}
+
+/// The 64-bit FNV-1a hash of the bytes, specified so that it stays the same across builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A hash of the simplified inventory, whatever the order its devices were fetched in
+fn inventory_hash(devices: &HashMap<IpAddr, DeviceRef>, disabled: &[IpAddr]) -> String {
+    let mut sorted: Vec<&DeviceRef> = devices.values().collect();
+    sorted.sort_by_key(|device| device.ip);
+    let mut disabled = disabled.to_vec();
+    disabled.sort();
+
+    let canonical = serde_json::to_vec(&(sorted, disabled))
+        .expect("the simplified inventory is always serializable");
+    format!("{:016x}", fnv1a(&canonical))
+}
+
+impl InventoryHashes {
+    /// Hash the simplified Netbox inventory, and the Netshot one with its disabled devices
+    pub fn new(
+        netbox: &HashMap<IpAddr, DeviceRef>,
+        netshot: &HashMap<IpAddr, DeviceRef>,
+        netshot_disabled: &[IpAddr],
+    ) -> Self {
+        Self {
+            netbox: inventory_hash(netbox, &[]),
+            netshot: inventory_hash(netshot, netshot_disabled),
+        }
+    }
+}
+
+/// The drift found by a run, persisted with `--state-file` so the next run can tell what changed
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunState {
+    pub timestamp: String,
+    /// The devices to register, hostname by IP
+    pub register: BTreeMap<String, String>,
+    /// The devices to disable (or delete), hostname by IP
+    pub disable: BTreeMap<String, String>,
+    /// The inventories the run started from, only kept when it pushed nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashes: Option<InventoryHashes>,
+}
+
+/// A device that started or stopped drifting since the previous run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateChange {
+    pub ip: String,
+    pub hostname: String,
+}
+
+/// What changed since the previous run
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StateDelta {
+    /// When the previous run happened, absent without a usable state file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_timestamp: Option<String>,
+    /// The devices to register that the previous run didn't report
+    pub new_register: Vec<StateChange>,
+    /// The devices the previous run reported to register that no longer are
+    pub resolved_register: Vec<StateChange>,
+    pub new_disable: Vec<StateChange>,
+    pub resolved_disable: Vec<StateChange>,
+}
+
+/// The entries of the first map missing from the second one, sorted by IP
+fn missing_from(
+    entries: &BTreeMap<String, String>,
+    other: &BTreeMap<String, String>,
+) -> Vec<StateChange> {
+    entries
+        .iter()
+        .filter(|(ip, _)| !other.contains_key(*ip))
+        .map(|(ip, hostname)| StateChange {
+            ip: ip.clone(),
+            hostname: hostname.clone(),
+        })
+        .collect()
+}
+
+impl RunState {
+    /// The drift of the run described by the report
+    pub fn from_report(report: &Report) -> Self {
+        let by_ip = |entries: &[ReportEntry]| {
+            entries
+                .iter()
+                .map(|entry| (entry.ip.clone(), entry.hostname.clone()))
+                .collect()
+        };
+        Self {
+            timestamp: report.timestamp.clone(),
+            register: by_ip(&report.devices_to_register),
+            disable: by_ip(&report.devices_to_disable),
+            hashes: None,
+        }
+    }
+
+    /// Keep the hashes of the inventories the run started from
+    pub fn with_hashes(mut self, hashes: Option<InventoryHashes>) -> Self {
+        self.hashes = hashes;
+        self
+    }
+
+    /// Load the state of the previous run, empty when the file is missing or unreadable
+    pub fn load(path: &str) -> Self {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) => {
+                log::debug!("No previous state in {}: {}", path, error);
+                return Self::default();
+            }
+        };
+        match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(error) => {
+                log::warn!("Ignoring the unreadable state file {}: {}", path, error);
+                Self::default()
+            }
+        }
+    }
+
+    /// Write the state to the given path, for the next run
+    pub fn store(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Unable to write the state file {}", path))
+    }
+
+    /// What changed between the previous state and this one
+    pub fn delta(&self, previous: &RunState) -> StateDelta {
+        StateDelta {
+            previous_timestamp: Some(previous.timestamp.clone()).filter(|t| !t.is_empty()),
+            new_register: missing_from(&self.register, &previous.register),
+            resolved_register: missing_from(&previous.register, &self.register),
+            new_disable: missing_from(&self.disable, &previous.disable),
+            resolved_disable: missing_from(&previous.disable, &self.disable),
+        }
+    }
+}
+
+impl StateDelta {
+    /// Log the changes, one line per device in debug
+    pub fn log(&self) {
+        let since = match &self.previous_timestamp {
+            Some(timestamp) => format!("the last run ({})", timestamp),
+            None => String::from("the last run (no previous state)"),
+        };
+        log::info!(
+            "Since {}: {} new and {} resolved devices to register, {} new and {} resolved devices to disable",
+            since,
+            self.new_register.len(),
+            self.resolved_register.len(),
+            self.new_disable.len(),
+            self.resolved_disable.len()
+        );
+        let lists = [
+            ("New device to register", &self.new_register),
+            ("No longer to register", &self.resolved_register),
+            ("New device to disable", &self.new_disable),
+            ("No longer to disable", &self.resolved_disable),
+        ];
+        for (label, changes) in lists.iter() {
+            for change in changes.iter() {
+                log::debug!("{}: {}({})", label, change.hostname, change.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(timestamp: &str, register: &[(&str, &str)], disable: &[(&str, &str)]) -> RunState {
+        let by_ip = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(ip, hostname)| (ip.to_string(), hostname.to_string()))
+                .collect()
+        };
+        RunState {
+            timestamp: timestamp.to_string(),
+            register: by_ip(register),
+            disable: by_ip(disable),
+            hashes: None,
+        }
+    }
+
+    fn state_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("netbox2netshot-state-{}.json", name))
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn delta_between_runs() {
+        let previous = state(
+            "2024-05-01T08:00:00Z",
+            &[("10.0.0.1", "sw1"), ("10.0.0.2", "sw2")],
+            &[("10.0.0.9", "old9")],
+        );
+        let current = state(
+            "2024-05-01T09:00:00Z",
+            &[("10.0.0.2", "sw2"), ("10.0.0.3", "sw3")],
+            &[("10.0.0.9", "old9")],
+        );
+
+        let delta = current.delta(&previous);
+
+        assert_eq!(
+            delta.previous_timestamp.as_deref(),
+            Some("2024-05-01T08:00:00Z")
+        );
+        assert_eq!(delta.new_register[0].hostname, "sw3");
+        assert_eq!(delta.new_register.len(), 1);
+        assert_eq!(delta.resolved_register[0].ip, "10.0.0.1");
+        assert_eq!(delta.resolved_register.len(), 1);
+        assert!(delta.new_disable.is_empty());
+        assert!(delta.resolved_disable.is_empty());
+    }
+
+    #[test]
+    fn missing_or_corrupt_state_is_empty() {
+        let path = state_path("corrupt");
+        let _ = fs::remove_file(&path);
+        assert_eq!(RunState::load(&path), RunState::default());
+
+        fs::write(&path, "{not json").unwrap();
+        assert_eq!(RunState::load(&path), RunState::default());
+
+        let current = state("2024-05-01T09:00:00Z", &[("10.0.0.1", "sw1")], &[]);
+        let delta = current.delta(&RunState::load(&path));
+        assert!(delta.previous_timestamp.is_none());
+        assert_eq!(delta.new_register.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stored_state_round_trip() {
+        let path = state_path("round-trip");
+        let current = state(
+            "2024-05-01T09:00:00Z",
+            &[("10.0.0.1", "sw1")],
+            &[("10.0.0.9", "old9")],
+        )
+        .with_hashes(Some(InventoryHashes {
+            netbox: String::from("0123456789abcdef"),
+            netshot: String::from("fedcba9876543210"),
+        }));
+
+        current.store(&path).unwrap();
+
+        assert_eq!(RunState::load(&path), current);
+        assert_eq!(
+            current.delta(&RunState::load(&path)),
+            StateDelta {
+                previous_timestamp: Some(current.timestamp.clone()),
+                ..Default::default()
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn inventory_hashes() {
+        let device = |ip: &str, hostname: &str| {
+            let ip: IpAddr = ip.parse().unwrap();
+            (
+                ip,
+                DeviceRef::new(ip, hostname.to_string(), crate::Source::Netshot),
+            )
+        };
+        let netbox: HashMap<IpAddr, DeviceRef> = HashMap::new();
+        let netshot: HashMap<IpAddr, DeviceRef> =
+            vec![device("10.0.0.1", "sw1"), device("10.0.0.2", "sw2")]
+                .into_iter()
+                .collect();
+        let reordered: HashMap<IpAddr, DeviceRef> =
+            vec![device("10.0.0.2", "sw2"), device("10.0.0.1", "sw1")]
+                .into_iter()
+                .collect();
+        let renamed: HashMap<IpAddr, DeviceRef> =
+            vec![device("10.0.0.1", "sw1"), device("10.0.0.2", "sw3")]
+                .into_iter()
+                .collect();
+        let disabled: Vec<IpAddr> = vec!["10.0.0.2".parse().unwrap()];
+
+        let hashes = InventoryHashes::new(&netbox, &netshot, &[]);
+
+        assert_eq!(hashes.netshot.len(), 16);
+        // Persisted in the state files, the hashes must not change from one build to another
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hashes.netbox, "9d73114e90492e2d");
+        assert_eq!(hashes, InventoryHashes::new(&netbox, &reordered, &[]));
+        assert_ne!(
+            hashes.netshot,
+            InventoryHashes::new(&netbox, &renamed, &[]).netshot
+        );
+        assert_ne!(
+            hashes.netshot,
+            InventoryHashes::new(&netbox, &netshot, &disabled).netshot
+        );
+        assert_eq!(
+            hashes.netbox,
+            InventoryHashes::new(&netbox, &renamed, &[]).netbox
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+/// The durations of the phases of a run, in execution order
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Run the given phase, logging and recording how long it took
+    pub fn measure<T, F: FnOnce() -> T>(&mut self, phase: &'static str, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Record the duration of a phase measured elsewhere
+    pub fn record(&mut self, phase: &'static str, duration: Duration) {
+        log::info!("Phase {} took {:.3}s", phase, duration.as_secs_f64());
+        self.phases.push((phase, duration));
+    }
+
+    /// Append the phases measured by another thread, already logged by it
+    pub fn extend(&mut self, other: Timings) {
+        self.phases.extend(other.phases);
+    }
+
+    /// The recorded phases and their durations
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    /// The sum of the recorded durations
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measured_phases() {
+        let mut timings = Timings::default();
+        assert_eq!(timings.measure("first", || 42), 42);
+        timings.record("second", Duration::from_secs(2));
+        let mut other = Timings::default();
+        other.record("third", Duration::from_secs(1));
+        timings.extend(other);
+
+        let names: Vec<&str> = timings.phases().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["first", "second", "third"]);
+        assert!(timings.total() >= Duration::from_secs(2));
+    }
+}
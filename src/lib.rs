@@ -0,0 +1,3455 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+
+use common::progress::Progress;
+use common::report::{ErrorEntry, Report, ReportEntry};
+use common::state::InventoryHashes;
+use common::timing::Timings;
+use rest::error::{ApiError, ApiResult};
+use rest::netbox::{self, NetboxClient};
+use rest::netshot::{self, NetshotClient, NewDevice};
+
+pub mod common;
+pub mod rest;
+
+/// The parameters of a synchronization run
+#[derive(Debug, Clone, Default)]
+pub struct SyncConfig {
+    pub netshot_domain_id: u32,
+    pub netshot_group_id: Option<u32>,
+    /// The querystrings selecting the Netbox devices, one request each, none selecting every device
+    pub netbox_devices_filters: Vec<String>,
+    pub netbox_vms_filter: Option<String>,
+    /// Fetch the Netbox devices and VMs with a single GraphQL query, the filters being GraphQL arguments
+    pub netbox_graphql: bool,
+    /// The Netbox field the management IPs are read from
+    pub netbox_ip_field: netbox::IpField,
+    /// The filters only select the recently updated Netbox objects, so the devices missing on
+    /// Netbox can't be told apart and nothing is disabled
+    pub incremental: bool,
+    pub check: bool,
+    pub concurrency: usize,
+    /// The number of devices sent per bulk registration request, 0 or 1 registering them one by one
+    pub register_batch_size: usize,
+    pub match_by_name: bool,
+    /// How the hostnames are compared by `match_by_name`
+    pub hostname_normalizer: common::HostnameNormalizer,
+    /// The management IPs left out of both inventories
+    pub ip_filter: common::filter::IpFilter,
+    pub snapshot_on_register: bool,
+    /// Wait for the scheduled snapshots to complete, all of them within this timeout
+    pub wait_snapshots: Option<Duration>,
+    /// The maximum number of devices that can be disabled in a single run
+    pub max_disable: Option<usize>,
+    /// The maximum percentage of the Netshot inventory that can be disabled in a single run
+    pub max_disable_percent: Option<u32>,
+    /// Disable the devices even above the thresholds
+    pub force: bool,
+    /// The minimum number of Netbox devices required to disable anything
+    pub min_netbox_devices: usize,
+    /// Enable the devices disabled on Netshot that are present on Netbox
+    pub reenable: bool,
+    /// Ignore the Netshot devices outside of the configured domains
+    pub scope_to_domain: bool,
+    /// Copy the Netbox tag slugs into the comments of the registered devices
+    pub copy_tags: bool,
+    /// Name the registered devices after their Netbox object instead of letting Netshot do it
+    pub register_name_template: Option<common::NameTemplate>,
+    /// Rename the Netshot devices named differently than the Netbox device with the same IP
+    pub update_names: bool,
+    /// What to do with the Netshot devices missing on Netbox
+    pub on_missing: OnMissing,
+    /// Only report the devices to disable (or delete), without pushing anything for them
+    pub no_disable: bool,
+    /// Only report the devices to register, without pushing anything for them
+    pub no_register: bool,
+    /// Stop pushing changes after the first failed one
+    pub fail_fast: bool,
+    /// The maximum number of changes pushed in a single run, the others being deferred
+    pub limit: Option<usize>,
+    /// No change is started after this instant, the remaining ones being deferred to the next run
+    pub deadline: Option<Instant>,
+    /// The number of pushed changes between two progress logs, 0 disabling them
+    pub progress_every: usize,
+    /// Reuse the Netshot devices fetched by a previous run within the cache TTL
+    pub netshot_cache: Option<common::cache::DiskCache>,
+    /// The domains to register the devices of the given Netbox sites into
+    pub site_domains: HashMap<String, u32>,
+    /// The domains to register the devices of the given Netbox tenants into, sites win
+    pub tenant_domains: HashMap<String, u32>,
+    /// The Netbox statuses of the devices to disable on Netshot even though present on Netbox
+    pub offline_statuses: Vec<String>,
+    /// The Netshot drivers of the registered devices of the given Netbox platform slugs
+    pub platform_drivers: HashMap<String, String>,
+    /// The inventory hashes of the previous daemon cycle: when both inventories still have them,
+    /// the comparison and the writes are skipped
+    pub previous_hashes: Option<InventoryHashes>,
+}
+
+impl SyncConfig {
+    /// The Netshot domain to register the given device into, by site, then tenant, then default
+    pub fn domain_for(&self, device: &DeviceRef) -> u32 {
+        let by_site = device
+            .site
+            .as_ref()
+            .and_then(|site| self.site_domains.get(site));
+        let by_tenant = device
+            .tenant
+            .as_ref()
+            .and_then(|tenant| self.tenant_domains.get(tenant));
+        *by_site.or(by_tenant).unwrap_or(&self.netshot_domain_id)
+    }
+
+    /// The registration of the given device, its tags joined into the comments with `copy_tags`,
+    /// named after the `register_name_template` and with the driver mapped from its platform
+    pub fn new_device(&self, device: &DeviceRef) -> NewDevice {
+        let comments = if self.copy_tags && !device.tags.is_empty() {
+            Some(device.tags.join(","))
+        } else {
+            None
+        };
+        let name = self.register_name_template.as_ref().and_then(|template| {
+            let name = template.render(device);
+            if name.is_none() {
+                log::debug!(
+                    "{} lacks a value of the name template, leaving its naming to Netshot",
+                    device
+                );
+            }
+            name
+        });
+        let driver = device
+            .platform
+            .as_ref()
+            .and_then(|platform| self.platform_drivers.get(platform))
+            .cloned();
+        NewDevice {
+            ip_address: device.ip.to_string(),
+            comments,
+            name,
+            driver,
+        }
+    }
+
+    /// The Netbox devices filters to query, a single empty one selecting every device
+    pub fn devices_filters(&self) -> Vec<&str> {
+        if self.netbox_devices_filters.is_empty() {
+            return vec![""];
+        }
+        self.netbox_devices_filters
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Every Netshot domain the devices can be registered into
+    pub fn domains(&self) -> BTreeSet<u32> {
+        let mut domains: BTreeSet<u32> = self.site_domains.values().copied().collect();
+        domains.extend(self.tenant_domains.values());
+        domains.insert(self.netshot_domain_id);
+        domains
+    }
+}
+
+/// What to do with the Netshot devices missing on Netbox
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnMissing {
+    /// Disable them, keeping their history
+    #[default]
+    Disable,
+    /// Delete them from Netshot
+    Delete,
+}
+
+impl OnMissing {
+    /// The action name, as used in the logs and the CSV/notification outputs
+    pub fn action(&self) -> &'static str {
+        match self {
+            OnMissing::Disable => "disable",
+            OnMissing::Delete => "delete",
+        }
+    }
+}
+
+impl FromStr for OnMissing {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "disable" => Ok(OnMissing::Disable),
+            "delete" => Ok(OnMissing::Delete),
+            _ => Err(anyhow!(
+                "Invalid action on missing devices {}, expected disable or delete",
+                value
+            )),
+        }
+    }
+}
+
+/// Where a device of the inventories comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// A Netbox DCIM device
+    Device,
+    /// A Netbox virtual machine
+    Vm,
+    /// A Netshot device
+    Netshot,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Device => write!(f, "device"),
+            Source::Vm => write!(f, "vm"),
+            Source::Netshot => write!(f, "netshot"),
+        }
+    }
+}
+
+/// A device of the simplified inventories, identified by its management IP
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct DeviceRef {
+    pub ip: IpAddr,
+    pub hostname: String,
+    pub source: Source,
+    /// The Netbox site name, if any
+    pub site: Option<String>,
+    /// The Netbox tenant name, if any
+    pub tenant: Option<String>,
+    /// The Netbox tag slugs
+    pub tags: Vec<String>,
+    /// The Netshot device ID, for the devices of the Netshot inventory
+    pub netshot_id: Option<u32>,
+    /// The Netbox status value, if any
+    pub status: Option<String>,
+    /// The Netbox platform slug, if any
+    pub platform: Option<String>,
+}
+
+impl DeviceRef {
+    pub fn new(ip: IpAddr, hostname: String, source: Source) -> Self {
+        Self {
+            ip,
+            hostname,
+            source,
+            site: None,
+            tenant: None,
+            tags: Vec::new(),
+            netshot_id: None,
+            status: None,
+            platform: None,
+        }
+    }
+
+    /// Set the Netbox site and tenant names of the device
+    pub fn with_location(mut self, site: Option<String>, tenant: Option<String>) -> Self {
+        self.site = site;
+        self.tenant = tenant;
+        self
+    }
+
+    /// Set the Netbox tag slugs of the device
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Set the Netbox status value of the device
+    pub fn with_status(mut self, status: Option<String>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Set the Netbox platform slug of the device
+    pub fn with_platform(mut self, platform: Option<String>) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Set the Netshot device ID
+    pub fn with_netshot_id(mut self, netshot_id: u32) -> Self {
+        self.netshot_id = Some(netshot_id);
+        self
+    }
+}
+
+impl fmt::Display for DeviceRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.hostname, self.ip)
+    }
+}
+
+/// A Netshot device whose name differs from the one of the Netbox device with the same IP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    /// The Netbox device, named as it should be on Netshot
+    pub device: DeviceRef,
+    pub netshot_id: u32,
+    pub netshot_name: String,
+}
+
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) -> {}",
+            self.netshot_name, self.device.ip, self.device.hostname
+        )
+    }
+}
+
+/// The changes required to bring Netshot in line with Netbox
+#[derive(Debug, Default)]
+pub struct Diff {
+    pub devices_to_register: Vec<DeviceRef>,
+    pub devices_to_disable: Vec<DeviceRef>,
+    pub devices_to_enable: Vec<DeviceRef>,
+    pub devices_to_rename: Vec<Rename>,
+    pub netbox_ip_collisions: usize,
+    /// Netbox objects left out for lack of a usable management address
+    pub netbox_skipped: usize,
+    /// Netshot devices left out of the comparison because another one has the same management IP
+    pub netshot_duplicate_ips: usize,
+    pub netshot_inventory_size: usize,
+    pub netbox_inventory_size: usize,
+    /// The devices skipped while building the inventories, and other non-fatal errors
+    pub errors: Vec<ErrorEntry>,
+    /// How long fetching and comparing the inventories took
+    pub timings: Timings,
+    /// The hashes of the simplified inventories the diff was computed from
+    pub inventory_hashes: InventoryHashes,
+    /// Both inventories matched the `previous_hashes`, nothing was compared
+    pub inventories_unchanged: bool,
+}
+
+impl Diff {
+    /// Is there any device to register, disable, enable or rename
+    pub fn has_changes(&self) -> bool {
+        !self.devices_to_register.is_empty()
+            || !self.devices_to_disable.is_empty()
+            || !self.devices_to_enable.is_empty()
+            || !self.devices_to_rename.is_empty()
+    }
+}
+
+/// The full inventories of both systems
+#[derive(Debug, Serialize)]
+pub struct Inventories {
+    pub netbox_devices: Vec<netbox::Device>,
+    pub netbox_vms: Vec<netbox::Device>,
+    pub netshot_devices: Vec<netshot::Device>,
+}
+
+/// A management IP of the simplified inventories, with its hostname on each side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InventoryRow {
+    pub ip: IpAddr,
+    pub netbox_hostname: Option<String>,
+    pub netshot_hostname: Option<String>,
+    pub in_netbox: bool,
+    pub in_netshot: bool,
+}
+
+/// Join the simplified inventories by IP, sorted by IP
+pub fn inventory_rows(
+    netbox_inventory: &HashMap<IpAddr, DeviceRef>,
+    netshot_inventory: &HashMap<IpAddr, DeviceRef>,
+) -> Vec<InventoryRow> {
+    let ips: BTreeSet<&IpAddr> = netbox_inventory
+        .keys()
+        .chain(netshot_inventory.keys())
+        .collect();
+    ips.into_iter()
+        .map(|ip| {
+            let netbox_device = netbox_inventory.get(ip);
+            let netshot_device = netshot_inventory.get(ip);
+            InventoryRow {
+                ip: *ip,
+                netbox_hostname: netbox_device.map(|device| device.hostname.clone()),
+                netshot_hostname: netshot_device.map(|device| device.hostname.clone()),
+                in_netbox: netbox_device.is_some(),
+                in_netshot: netshot_device.is_some(),
+            }
+        })
+        .collect()
+}
+
+/// The result of a synchronization run
+#[derive(Debug)]
+pub struct SyncOutcome {
+    pub devices_to_register: Vec<DeviceRef>,
+    pub devices_to_disable: Vec<DeviceRef>,
+    pub devices_to_enable: Vec<DeviceRef>,
+    pub devices_to_rename: Vec<Rename>,
+    /// Whether `devices_to_disable` were disabled or deleted
+    pub on_missing: OnMissing,
+    pub netbox_ip_collisions: usize,
+    pub netbox_skipped: usize,
+    pub netshot_duplicate_ips: usize,
+    pub failures: usize,
+    /// Every non-fatal error of the run, in the order they happened
+    pub errors: Vec<ErrorEntry>,
+    pub report: Report,
+    /// How long each phase of the run took, including the diff
+    pub timings: Timings,
+    pub inventory_hashes: InventoryHashes,
+    /// Both inventories matched the `previous_hashes`, the run was skipped
+    pub inventories_unchanged: bool,
+}
+
+impl SyncOutcome {
+    /// Are Netbox and Netshot out of sync (any device to register, disable, enable or rename)
+    pub fn has_drift(&self) -> bool {
+        !self.devices_to_register.is_empty()
+            || !self.devices_to_disable.is_empty()
+            || !self.devices_to_enable.is_empty()
+            || !self.devices_to_rename.is_empty()
+    }
+
+    /// Every change that failed to be pushed as an (action, report entry) pair
+    pub fn write_failures(&self) -> Vec<(&'static str, &ReportEntry)> {
+        let report = &self.report;
+        let lists = [
+            ("register", &report.devices_to_register),
+            ("snapshot", &report.snapshots),
+            (self.on_missing.action(), &report.devices_to_disable),
+            ("enable", &report.devices_to_enable),
+            ("rename", &report.devices_to_rename),
+        ];
+        lists
+            .iter()
+            .flat_map(|(action, entries)| entries.iter().map(move |entry| (*action, entry)))
+            .filter(|(_, entry)| entry.success == Some(false))
+            .collect()
+    }
+
+    /// Did the run leave nothing to retry: no change pushed, failed or deferred by the run timeout,
+    /// so that running it again on the same inventories would push nothing either
+    pub fn pushed_nothing(&self) -> bool {
+        let report = &self.report;
+        let lists = [
+            &report.devices_to_register,
+            &report.snapshots,
+            &report.devices_to_disable,
+            &report.devices_to_enable,
+            &report.devices_to_rename,
+        ];
+        !report.timed_out
+            && !report.disable_blocked
+            && lists
+                .iter()
+                .flat_map(|entries| entries.iter())
+                .all(|entry| entry.success.is_none() || entry.unchanged)
+    }
+
+    /// Record an error met after the synchronization itself, e.g. while notifying
+    pub fn add_error(&mut self, error: ErrorEntry) {
+        self.report.errors.push(error.clone());
+        self.errors.push(error);
+    }
+
+    /// Every computed change as an (action, device) pair, renamed devices carrying their new name
+    pub fn actions(&self) -> Vec<(&'static str, &DeviceRef)> {
+        let register = self.devices_to_register.iter().map(|d| ("register", d));
+        let missing = self.on_missing.action();
+        let disable = self.devices_to_disable.iter().map(|d| (missing, d));
+        let enable = self.devices_to_enable.iter().map(|d| ("enable", d));
+        let rename = self.devices_to_rename.iter().map(|r| ("rename", &r.device));
+        register
+            .chain(disable)
+            .chain(enable)
+            .chain(rename)
+            .collect()
+    }
+}
+
+/// Stop the writes after the first failure when failing fast, or once the run deadline passed
+struct WriteGuard {
+    fail_fast: bool,
+    failed: AtomicBool,
+    deadline: Option<Instant>,
+    timed_out: AtomicBool,
+}
+
+impl WriteGuard {
+    fn new(fail_fast: bool, deadline: Option<Instant>) -> Self {
+        Self {
+            fail_fast,
+            failed: AtomicBool::new(false),
+            deadline,
+            timed_out: AtomicBool::new(false),
+        }
+    }
+
+    /// Has the deadline passed, warning the first time it's noticed
+    fn is_expired(&self) -> bool {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                if !self.timed_out.swap(true, Ordering::SeqCst) {
+                    log::warn!(
+                        "Run timeout exceeded, not starting the remaining changes, the ones in flight are finished"
+                    );
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Did the deadline stop any write
+    fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Are the remaining writes to be skipped
+    fn is_stopped(&self) -> bool {
+        (self.fail_fast && self.failed.load(Ordering::SeqCst)) || self.is_expired()
+    }
+
+    /// The outcome of the writes skipped once stopped
+    fn stop_error(&self) -> ApiError {
+        if self.fail_fast && self.failed.load(Ordering::SeqCst) {
+            ApiError::Aborted
+        } else {
+            ApiError::Deferred
+        }
+    }
+
+    /// Record a failed write
+    fn fail(&self) {
+        if !self.failed.swap(true, Ordering::SeqCst) && self.fail_fast {
+            log::error!("A change failed, not pushing the remaining ones");
+        }
+    }
+
+    /// Run the write unless an earlier one failed when failing fast or the deadline passed
+    fn run<T>(&self, write: impl FnOnce() -> ApiResult<T>) -> ApiResult<T> {
+        if self.is_stopped() {
+            return Err(self.stop_error());
+        }
+        let result = write();
+        if result.is_err() {
+            self.fail();
+        }
+        result
+    }
+}
+
+/// Log every failure of a write phase and a summary of its successes/failures
+fn log_write_results<T>(phase: &str, results: &[(DeviceRef, ApiResult<T>)]) {
+    let mut failures = 0;
+    let mut deferred = 0;
+    for (device, result) in results {
+        if let Err(ApiError::Deferred) = result {
+            deferred += 1;
+        } else if let Err(error) = result {
+            log::warn!(
+                "{} failure ({}) for {}: {}",
+                phase,
+                error.category(),
+                device,
+                error
+            );
+            failures += 1;
+        }
+    }
+    log::info!(
+        "{} done: {} succeeded, {} failed",
+        phase,
+        results.len() - failures - deferred,
+        failures
+    );
+    if deferred > 0 {
+        log::warn!(
+            "{}: {} changes deferred to the next run by the run timeout",
+            phase,
+            deferred
+        );
+    }
+}
+
+/// The result of a registration run from a list of IPs
+#[derive(Debug, Default)]
+pub struct RegistrationOutcome {
+    pub added: usize,
+    pub skipped: usize,
+    pub failures: usize,
+}
+
+/// Fetch the detailed Netbox devices and, if a VM filter is configured, the Netbox VMs concurrently
+fn netbox_inventory(
+    config: &SyncConfig,
+    netbox_client: &NetboxClient,
+    timings: &mut Timings,
+) -> Result<(Vec<netbox::Device>, Vec<netbox::Device>), Error> {
+    let filters = config.devices_filters();
+    if config.netbox_graphql {
+        log::info!("Getting devices and VMS lists from Netbox GraphQL");
+        return Ok(timings.measure("netbox_fetch", || {
+            // The VMs are only fetched along with the devices of the first filter
+            let (devices, vms) = netbox_client
+                .get_inventory_graphql(filters[0], config.netbox_vms_filter.as_deref())?;
+            let mut devices_per_filter = vec![(filters[0], devices)];
+            for filter in &filters[1..] {
+                let (devices, _) = netbox_client.get_inventory_graphql(filter, None)?;
+                devices_per_filter.push((filter, devices));
+            }
+            ApiResult::Ok((merge_netbox_devices(devices_per_filter), vms))
+        })?);
+    }
+    let get_devices = || -> ApiResult<Vec<netbox::Device>> {
+        let devices_per_filter = filters
+            .iter()
+            .map(|filter| Ok((*filter, netbox_client.get_devices(filter)?)))
+            .collect::<ApiResult<Vec<_>>>()?;
+        Ok(merge_netbox_devices(devices_per_filter))
+    };
+
+    let vms_filter = match &config.netbox_vms_filter {
+        Some(vms_filter) => vms_filter,
+        None => {
+            log::info!("Getting devices list from Netbox");
+            let netbox_devices = timings.measure("netbox_fetch", get_devices)?;
+            return Ok((netbox_devices, Vec::new()));
+        }
+    };
+
+    log::info!("Getting devices and VMS lists from Netbox");
+    let timed = |fetch: &dyn Fn() -> ApiResult<Vec<netbox::Device>>| {
+        let start = Instant::now();
+        let result = fetch();
+        (result, start.elapsed())
+    };
+    let ((netbox_devices, devices_duration), (netbox_vms, vms_duration)) = thread::scope(|scope| {
+        let vms = scope.spawn(|| timed(&|| netbox_client.get_vms(vms_filter)));
+        let devices = timed(&get_devices);
+        (devices, vms.join().expect("the Netbox VMs fetch panicked"))
+    });
+    timings.record("netbox_fetch", devices_duration);
+    timings.record("vm_fetch", vms_duration);
+
+    Ok((netbox_devices?, netbox_vms?))
+}
+
+/// Merge the Netbox devices selected by each filter, keeping a single copy of the devices
+/// selected by several ones
+fn merge_netbox_devices(
+    devices_per_filter: Vec<(&str, Vec<netbox::Device>)>,
+) -> Vec<netbox::Device> {
+    let several = devices_per_filter.len() > 1;
+    let mut seen = HashSet::new();
+    let mut merged = Vec::new();
+    for (filter, devices) in devices_per_filter {
+        if several {
+            log::info!(
+                "Netbox filter \"{}\" selected {} devices",
+                filter,
+                devices.len()
+            );
+        }
+        merged.extend(devices.into_iter().filter(|device| seen.insert(device.id)));
+    }
+    merged
+}
+
+/// The simplified Netshot inventory, keyed by IP
+#[derive(Debug, Default)]
+struct NetshotInventory {
+    devices: HashMap<IpAddr, DeviceRef>,
+    /// The disabled devices of the inventory
+    disabled: Vec<IpAddr>,
+    /// Netshot devices skipped because another one already uses their management IP
+    duplicate_ips: usize,
+}
+
+/// Fetch the Netshot devices of every configured domain, only their own with `scope_to_domain`
+fn fetch_netshot_devices(
+    config: &SyncConfig,
+    netshot_client: &NetshotClient,
+) -> Result<Vec<netshot::Device>, Error> {
+    let mut netshot_devices = Vec::new();
+    for domain_id in config.domains() {
+        log::info!("Getting devices list from Netshot domain {}", domain_id);
+        let mut devices = if config.scope_to_domain {
+            netshot_client.get_devices_in_domain(domain_id)?
+        } else {
+            netshot_client.get_devices(domain_id)?
+        };
+        netshot_devices.append(&mut devices);
+    }
+    Ok(netshot_devices)
+}
+
+/// Build the simplified Netshot inventory
+fn netshot_inventory(
+    config: &SyncConfig,
+    netshot_client: &NetshotClient,
+    errors: &mut Vec<ErrorEntry>,
+) -> Result<NetshotInventory, Error> {
+    // The cached devices are only reused for the same domains and scoping
+    let cache_key = format!("{:?}/{}", config.domains(), config.scope_to_domain);
+    let cached = config
+        .netshot_cache
+        .as_ref()
+        .and_then(|cache| cache.load::<Vec<netshot::Device>>(&cache_key));
+
+    let netshot_devices = match cached {
+        Some(devices) => {
+            log::info!("Using {} cached Netshot devices", devices.len());
+            devices
+        }
+        None => {
+            let netshot_devices = fetch_netshot_devices(config, netshot_client)?;
+            if let Some(cache) = &config.netshot_cache {
+                if let Err(error) = cache.store(&cache_key, &netshot_devices) {
+                    log::warn!("{:#}", error);
+                    errors.push(ErrorEntry::new("netshot_cache", format!("{:#}", error)));
+                }
+            }
+            netshot_devices
+        }
+    };
+
+    log::debug!("Building netshot devices simplified inventory");
+    let mut inventory = NetshotInventory::default();
+    for device in &netshot_devices {
+        let ip = match device.management_ip() {
+            Ok(ip) => ip,
+            Err(error) => {
+                log::warn!("Netshot device {} skipped: {}", device.name, error);
+                errors.push(ErrorEntry::for_hostname(
+                    "netshot_inventory",
+                    &device.name,
+                    error,
+                ));
+                continue;
+            }
+        };
+        if let Some(existing) = inventory.devices.get(&ip) {
+            log::warn!(
+                "{} and {} share the same management IP {} on Netshot, keeping {}",
+                existing.hostname,
+                device.name,
+                ip,
+                existing.hostname
+            );
+            inventory.duplicate_ips += 1;
+            continue;
+        }
+        if device.is_disabled() {
+            inventory.disabled.push(ip);
+        }
+        inventory.devices.insert(
+            ip,
+            DeviceRef::new(ip, device.name.clone(), Source::Netshot).with_netshot_id(device.id),
+        );
+    }
+
+    Ok(inventory)
+}
+
+/// The simplified Netbox inventory, keyed by IP, built object by object as the pages are fetched
+#[derive(Debug, Default)]
+struct NetboxInventory {
+    /// The field the management IPs are read from
+    ip_field: netbox::IpField,
+    devices: HashMap<IpAddr, DeviceRef>,
+    ip_collisions: usize,
+    /// The Netbox IDs already added, overlapping filters selecting some objects several times
+    seen: HashSet<u32>,
+    /// The number of objects skipped for lack of a usable management address
+    skipped: usize,
+    /// The objects skipped for lack of a usable IP
+    errors: Vec<ErrorEntry>,
+}
+
+impl NetboxInventory {
+    /// An empty inventory reading the management IPs from the given field
+    fn new(ip_field: netbox::IpField) -> Self {
+        Self {
+            ip_field,
+            ..Default::default()
+        }
+    }
+
+    /// Add a Netbox object, skipping it when it has no usable primary IP
+    fn add(&mut self, device: netbox::Device, source: Source) {
+        if !self.seen.insert(device.id) {
+            return;
+        }
+        let hostname = device.name.clone().unwrap_or(device.id.to_string());
+        let ip = match device.management_address(self.ip_field) {
+            Ok(ip) => ip,
+            Err(error) => {
+                log::warn!(
+                    "Device {} skipped, no usable management address ({}): {}",
+                    hostname,
+                    error.category(),
+                    error
+                );
+                self.skipped += 1;
+                self.errors.push(ErrorEntry::for_hostname(
+                    "netbox_inventory",
+                    &hostname,
+                    error,
+                ));
+                return;
+            }
+        };
+
+        let status = device.status;
+        let site = device.site.map(|site| site.name);
+        let tenant = device.tenant.map(|tenant| tenant.name);
+        let platform = device
+            .platform
+            .map(|platform| platform.slug.unwrap_or(platform.name));
+        let tags = device
+            .tags
+            .into_iter()
+            .map(|tag| tag.slug.unwrap_or(tag.name))
+            .collect();
+        self.insert(
+            DeviceRef::new(ip, hostname, source)
+                .with_location(site, tenant)
+                .with_tags(tags)
+                .with_status(status)
+                .with_platform(platform),
+        );
+    }
+
+    /// Insert a simplified object unless another one already uses its IP
+    fn insert(&mut self, device: DeviceRef) {
+        match self.devices.get(&device.ip) {
+            Some(existing) => {
+                log::warn!(
+                    "{} and {} share the same IP {} on Netbox, keeping {}",
+                    existing.hostname,
+                    device.hostname,
+                    device.ip,
+                    existing.hostname
+                );
+                self.ip_collisions += 1;
+            }
+            None => {
+                self.devices.insert(device.ip, device);
+            }
+        }
+    }
+
+    /// Add the objects of another inventory, the ones already there winning the IP collisions
+    fn merge(&mut self, other: NetboxInventory) {
+        self.ip_collisions += other.ip_collisions;
+        self.skipped += other.skipped;
+        self.errors.extend(other.errors);
+        let mut devices: Vec<DeviceRef> = other.devices.into_values().collect();
+        devices.sort_by_key(|device| device.ip);
+        for device in devices {
+            self.insert(device);
+        }
+    }
+}
+
+/// Fetch the simplified Netbox inventory, the devices and, if a VM filter is configured, the VMs
+/// concurrently. The REST pages are folded into it as they come, so the detailed objects are
+/// never all held at once
+fn fetch_netbox_inventory(
+    config: &SyncConfig,
+    netbox_client: &NetboxClient,
+    timings: &mut Timings,
+) -> Result<NetboxInventory, Error> {
+    // Devices are added before the VMs so they always win on IP collisions
+    if config.netbox_graphql {
+        let (netbox_devices, netbox_vms) = netbox_inventory(config, netbox_client, timings)?;
+        let mut inventory = NetboxInventory::new(config.netbox_ip_field);
+        for device in netbox_devices {
+            inventory.add(device, Source::Device);
+        }
+        let mut vms_inventory = NetboxInventory::new(config.netbox_ip_field);
+        for vm in netbox_vms {
+            vms_inventory.add(vm, Source::Vm);
+        }
+        inventory.merge(vms_inventory);
+        return Ok(inventory);
+    }
+
+    let filters = config.devices_filters();
+    let fetch_devices = || -> ApiResult<NetboxInventory> {
+        let mut inventory = NetboxInventory::new(config.netbox_ip_field);
+        for filter in &filters {
+            let count = netbox_client
+                .for_each_device(filter, |device| inventory.add(device, Source::Device))?;
+            if filters.len() > 1 {
+                log::info!("Netbox filter \"{}\" selected {} devices", filter, count);
+            }
+        }
+        Ok(inventory)
+    };
+
+    let vms_filter = match &config.netbox_vms_filter {
+        Some(vms_filter) => vms_filter,
+        None => {
+            log::info!("Getting devices list from Netbox");
+            return Ok(timings.measure("netbox_fetch", fetch_devices)?);
+        }
+    };
+
+    log::info!("Getting devices and VMS lists from Netbox");
+    let fetch_vms = || -> ApiResult<NetboxInventory> {
+        let mut inventory = NetboxInventory::new(config.netbox_ip_field);
+        netbox_client.for_each_vm(vms_filter, |vm| inventory.add(vm, Source::Vm))?;
+        Ok(inventory)
+    };
+    let timed = |fetch: &dyn Fn() -> ApiResult<NetboxInventory>| {
+        let start = Instant::now();
+        let result = fetch();
+        (result, start.elapsed())
+    };
+    let ((devices, devices_duration), (vms, vms_duration)) = thread::scope(|scope| {
+        let vms = scope.spawn(|| timed(&fetch_vms));
+        let devices = timed(&fetch_devices);
+        (devices, vms.join().expect("the Netbox VMs fetch panicked"))
+    });
+    timings.record("netbox_fetch", devices_duration);
+    timings.record("vm_fetch", vms_duration);
+
+    let mut inventory = devices?;
+    inventory.merge(vms?);
+    Ok(inventory)
+}
+
+/// Fetch the simplified inventories of all the Netbox sources and merge them, the first sources
+/// winning the IP collisions between them
+fn fetch_netbox_sources(
+    config: &SyncConfig,
+    netbox_clients: &[NetboxClient],
+    timings: &mut Timings,
+) -> Result<NetboxInventory, Error> {
+    if let [netbox_client] = netbox_clients {
+        return fetch_netbox_inventory(config, netbox_client, timings);
+    }
+
+    let mut inventory = NetboxInventory::new(config.netbox_ip_field);
+    let mut sources: HashMap<IpAddr, &str> = HashMap::new();
+    for netbox_client in netbox_clients {
+        let source = fetch_netbox_inventory(config, netbox_client, timings)?;
+        log::info!(
+            "Netbox {} returned {} devices",
+            netbox_client.url,
+            source.devices.len()
+        );
+        inventory.ip_collisions += source.ip_collisions;
+        inventory.skipped += source.skipped;
+        inventory.errors.extend(source.errors);
+
+        let mut devices: Vec<DeviceRef> = source.devices.into_values().collect();
+        devices.sort_by_key(|device| device.ip);
+        for device in devices {
+            match inventory.devices.get(&device.ip) {
+                Some(existing) => {
+                    let source = sources[&device.ip];
+                    log::warn!(
+                        "{} of {} and {} of {} share the same IP {}, keeping {}",
+                        existing.hostname,
+                        source,
+                        device.hostname,
+                        netbox_client.url,
+                        device.ip,
+                        existing.hostname
+                    );
+                    inventory.errors.push(ErrorEntry::for_hostname(
+                        "netbox_sources",
+                        &device.hostname,
+                        format!(
+                            "IP {} already used by {} of {}",
+                            device.ip, existing.hostname, source
+                        ),
+                    ));
+                    inventory.ip_collisions += 1;
+                }
+                None => {
+                    sources.insert(device.ip, &netbox_client.url);
+                    inventory.devices.insert(device.ip, device);
+                }
+            }
+        }
+    }
+    Ok(inventory)
+}
+
+/// Register the devices in bulk requests, grouped by target domain
+fn register_in_batches(
+    config: &SyncConfig,
+    netshot_client: &NetshotClient,
+    guard: &WriteGuard,
+    progress: &Progress,
+    devices: &[DeviceRef],
+) -> Vec<(DeviceRef, ApiResult<netshot::NewDeviceCreatedPayload>)> {
+    let mut devices_by_domain: BTreeMap<u32, Vec<DeviceRef>> = BTreeMap::new();
+    for device in devices {
+        devices_by_domain
+            .entry(config.domain_for(device))
+            .or_default()
+            .push(device.clone());
+    }
+
+    let batches: Vec<(u32, Vec<DeviceRef>)> = devices_by_domain
+        .into_iter()
+        .flat_map(|(domain_id, devices)| {
+            devices
+                .chunks(config.register_batch_size)
+                .map(|batch| (domain_id, batch.to_vec()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let results = common::run_parallel(batches, config.concurrency, |(domain_id, batch)| {
+        if guard.is_stopped() {
+            return batch
+                .iter()
+                .map(|device| (device.ip.to_string(), Err(guard.stop_error())))
+                .collect();
+        }
+        let new_devices: Vec<NewDevice> = batch
+            .iter()
+            .map(|device| config.new_device(device))
+            .collect();
+        let results = netshot_client.register_devices(
+            &new_devices,
+            *domain_id,
+            config.netshot_group_id,
+            config.register_batch_size,
+        );
+        if results.iter().any(|(_, result)| result.is_err()) {
+            guard.fail();
+        }
+        progress.add(batch.len());
+        results
+    });
+
+    let mut registrations = Vec::new();
+    for ((domain_id, batch), batch_results) in results {
+        for (device, (_, registration)) in batch.into_iter().zip(batch_results) {
+            if registration.is_ok() {
+                log::info!("{} registered into domain {}", device, domain_id);
+            }
+            registrations.push((device, registration));
+        }
+    }
+    registrations
+}
+
+/// Make sure the number of devices to disable stays under the configured thresholds
+fn check_disable_threshold(
+    config: &SyncConfig,
+    to_disable: usize,
+    inventory_size: usize,
+) -> Result<(), Error> {
+    if let Some(max_disable) = config.max_disable {
+        if to_disable > max_disable {
+            return Err(anyhow!(
+                "{} devices to {}, more than the maximum of {}",
+                to_disable,
+                config.on_missing.action(),
+                max_disable
+            ));
+        }
+    }
+    if let Some(max_disable_percent) = config.max_disable_percent {
+        if to_disable * 100 > max_disable_percent as usize * inventory_size {
+            return Err(anyhow!(
+                "{} devices to {} out of {}, more than the maximum of {}%",
+                to_disable,
+                config.on_missing.action(),
+                inventory_size,
+                max_disable_percent
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Register the given IPs on Netshot, skipping the ones it already knows, without looking at Netbox
+pub fn register_from_list(
+    config: &SyncConfig,
+    netshot_client: &NetshotClient,
+    ips: Vec<IpAddr>,
+) -> Result<RegistrationOutcome, Error> {
+    let concurrency = config.concurrency.max(1);
+    let lookups = common::run_parallel(ips, concurrency, |ip| netshot_client.get_device_by_ip(*ip));
+
+    let mut outcome = RegistrationOutcome::default();
+    let mut devices_to_register: Vec<DeviceRef> = Vec::new();
+    for (ip, lookup) in lookups {
+        match lookup? {
+            Some(device) => {
+                log::debug!(
+                    "{}({}) already present on Netshot, skipping it",
+                    device.name,
+                    ip
+                );
+                outcome.skipped += 1;
+            }
+            None => devices_to_register.push(DeviceRef::new(ip, ip.to_string(), Source::Device)),
+        }
+    }
+
+    if config.check {
+        for device in &devices_to_register {
+            log::info!("{} would be registered on Netshot", device.ip);
+        }
+        outcome.added = devices_to_register.len();
+    } else {
+        let domain_id = config.netshot_domain_id;
+        let group_id = config.netshot_group_id;
+        let registrations = common::run_parallel(devices_to_register, concurrency, |device| {
+            netshot_client.register_device_in_group(device.ip.to_string(), domain_id, group_id)
+        });
+        log_write_results("Registration", &registrations);
+        outcome.failures = registrations.iter().filter(|(_, r)| r.is_err()).count();
+        // Registered in the meantime, e.g. by a concurrent run
+        let already_registered = registrations
+            .iter()
+            .filter(|(_, r)| matches!(r, Ok(payload) if payload.is_already_registered()))
+            .count();
+        outcome.skipped += already_registered;
+        outcome.added = registrations.len() - outcome.failures - already_registered;
+    }
+
+    log::info!(
+        "{} devices added, {} skipped (already on Netshot), {} failed",
+        outcome.added,
+        outcome.skipped,
+        outcome.failures
+    );
+
+    Ok(outcome)
+}
+
+/// Compare the simplified inventories by IP, returning the Netbox devices missing on Netshot
+/// (to register) and the Netshot devices missing on Netbox (to disable), both sorted by IP
+pub fn compare_inventories(
+    netbox_inventory: &HashMap<IpAddr, DeviceRef>,
+    netshot_inventory: &HashMap<IpAddr, DeviceRef>,
+) -> (Vec<DeviceRef>, Vec<DeviceRef>) {
+    let netbox_ips: HashSet<&IpAddr> = netbox_inventory.keys().collect();
+    let netshot_ips: HashSet<&IpAddr> = netshot_inventory.keys().collect();
+
+    for ip in netbox_ips.intersection(&netshot_ips) {
+        log::debug!("{} is present on both", netbox_inventory[*ip]);
+    }
+
+    let mut devices_to_register: Vec<DeviceRef> = netbox_ips
+        .difference(&netshot_ips)
+        .map(|ip| netbox_inventory[*ip].clone())
+        .collect();
+    devices_to_register.sort_by_key(|device| device.ip);
+    for device in &devices_to_register {
+        log::debug!("{} missing from Netshot", device);
+    }
+
+    let mut devices_to_disable: Vec<DeviceRef> = netshot_ips
+        .difference(&netbox_ips)
+        .map(|ip| netshot_inventory[*ip].clone())
+        .collect();
+    devices_to_disable.sort_by_key(|device| device.ip);
+    for device in &devices_to_disable {
+        log::debug!("{} to be disabled (missing on Netbox)", device);
+    }
+
+    (devices_to_register, devices_to_disable)
+}
+
+/// The devices present on both sides by IP whose Netshot name differs from the Netbox one, sorted by IP
+pub fn find_renames(
+    netbox_inventory: &HashMap<IpAddr, DeviceRef>,
+    netshot_inventory: &HashMap<IpAddr, DeviceRef>,
+) -> Vec<Rename> {
+    let mut renames: Vec<Rename> = netbox_inventory
+        .iter()
+        .filter_map(|(ip, device)| {
+            let netshot_device = netshot_inventory.get(ip)?;
+            if netshot_device.hostname == device.hostname {
+                return None;
+            }
+            Some(Rename {
+                device: device.clone(),
+                netshot_id: netshot_device.netshot_id?,
+                netshot_name: netshot_device.hostname.clone(),
+            })
+        })
+        .collect();
+    renames.sort_by_key(|rename| rename.device.ip);
+    renames
+}
+
+/// The number of Netbox devices and VMs among the given ones, as a suffix of the summary logs
+fn source_breakdown(devices: Vec<&DeviceRef>) -> String {
+    let vms = devices
+        .iter()
+        .filter(|device| device.source == Source::Vm)
+        .count();
+    format!(" ({} devices, {} VMs)", devices.len() - vms, vms)
+}
+
+/// Compute the changes between the simplified inventories, once the excluded IPs are left out:
+/// the devices to register, to disable (or delete), to enable and to rename.
+///
+/// Pure comparison without any request, the inventory sizes being set but not the collisions,
+/// errors and timings
+pub fn diff_inventories(
+    config: &SyncConfig,
+    mut netbox_simplified_devices: HashMap<IpAddr, DeviceRef>,
+    mut netshot_simplified_inventory: HashMap<IpAddr, DeviceRef>,
+    mut netshot_disabled_devices: Vec<IpAddr>,
+) -> Diff {
+    config
+        .ip_filter
+        .apply(&mut netbox_simplified_devices, "Netbox");
+    config
+        .ip_filter
+        .apply(&mut netshot_simplified_inventory, "Netshot");
+    netshot_disabled_devices.retain(|ip| netshot_simplified_inventory.contains_key(ip));
+
+    let netbox_inventory_size = netbox_simplified_devices.len();
+    let netshot_inventory_size = netshot_simplified_inventory.len();
+
+    // The offline devices are left out of the comparison: never registered nor enabled, and
+    // disabled when still enabled on Netshot
+    let mut offline_devices: Vec<DeviceRef> = Vec::new();
+    if !config.offline_statuses.is_empty() {
+        let offline_ips: Vec<IpAddr> = netbox_simplified_devices
+            .values()
+            .filter(|device| match &device.status {
+                Some(status) => config.offline_statuses.contains(status),
+                None => false,
+            })
+            .map(|device| device.ip)
+            .collect();
+        for ip in &offline_ips {
+            let netbox_device = netbox_simplified_devices.remove(ip).unwrap();
+            if let Some(device) = netshot_simplified_inventory.remove(ip) {
+                if netshot_disabled_devices.contains(ip) {
+                    log::debug!("{} offline on Netbox and already disabled", device);
+                } else {
+                    log::debug!("{} offline on Netbox, to be disabled", device);
+                    offline_devices.push(device);
+                }
+            } else {
+                log::debug!("{} offline on Netbox, not registering it", netbox_device);
+            }
+        }
+        netshot_disabled_devices.retain(|ip| !offline_ips.contains(ip));
+    }
+
+    log::debug!(
+        "Simplified inventories: Netbox({}), Netshot({})",
+        netbox_inventory_size,
+        netshot_inventory_size
+    );
+
+    log::debug!("Comparing inventories");
+
+    let (mut devices_to_register, mut devices_to_disable) =
+        compare_inventories(&netbox_simplified_devices, &netshot_simplified_inventory);
+
+    let mut devices_to_enable: Vec<DeviceRef> = Vec::new();
+    for ip in &netshot_disabled_devices {
+        if let Some(netbox_device) = netbox_simplified_devices.get(ip) {
+            let device = &netshot_simplified_inventory[ip];
+            log::debug!("{} disabled on Netshot but present on Netbox", device);
+            // Counted by the source of the Netbox object, device or VM
+            devices_to_enable.push(DeviceRef {
+                source: netbox_device.source,
+                ..device.clone()
+            });
+        }
+    }
+    devices_to_enable.sort_by_key(|device| device.ip);
+    if !config.reenable && !devices_to_enable.is_empty() {
+        log::info!(
+            "{} devices are disabled on Netshot but present on Netbox, use --reenable to enable them",
+            devices_to_enable.len()
+        );
+        devices_to_enable.clear();
+    }
+
+    let mut devices_to_rename = Vec::new();
+    if config.update_names {
+        devices_to_rename = find_renames(&netbox_simplified_devices, &netshot_simplified_inventory);
+        for rename in &devices_to_rename {
+            log::debug!("{} renamed on Netbox", rename);
+        }
+    }
+
+    if config.match_by_name {
+        let normalizer = &config.hostname_normalizer;
+        let matches = common::match_by_name(
+            normalizer,
+            &mut devices_to_register,
+            &mut devices_to_disable,
+        );
+        for (netbox_device, netshot_device) in &matches {
+            log::info!(
+                "{} matched by name ({}) with {}, leaving it untouched",
+                netbox_device,
+                normalizer.normalize(&netbox_device.hostname),
+                netshot_device
+            );
+        }
+        log::info!("Matched {} devices by name", matches.len());
+    }
+
+    if config.incremental && !devices_to_disable.is_empty() {
+        log::info!(
+            "Incremental run, the {} Netshot devices missing from the updated Netbox objects are left untouched",
+            devices_to_disable.len()
+        );
+        devices_to_disable.clear();
+    }
+
+    // Only worth breaking down when the VMs are synchronized too
+    let by_source = |devices: Vec<&DeviceRef>| match config.netbox_vms_filter {
+        Some(_) => source_breakdown(devices),
+        None => String::new(),
+    };
+    log::info!(
+        "Found {} devices missing on Netshot, to be added{}",
+        devices_to_register.len(),
+        by_source(devices_to_register.iter().collect())
+    );
+    log::info!(
+        "Found {} devices missing on Netbox, to be {}d",
+        devices_to_disable.len(),
+        config.on_missing.action()
+    );
+    log::info!(
+        "Found {} devices disabled on Netshot but present on Netbox, to be enabled{}",
+        devices_to_enable.len(),
+        by_source(devices_to_enable.iter().collect())
+    );
+    if config.update_names {
+        log::info!(
+            "Found {} devices named differently on Netshot, to be renamed{}",
+            devices_to_rename.len(),
+            by_source(
+                devices_to_rename
+                    .iter()
+                    .map(|rename| &rename.device)
+                    .collect()
+            )
+        );
+    }
+
+    if !config.offline_statuses.is_empty() {
+        log::info!(
+            "Found {} devices offline on Netbox but enabled on Netshot, to be {}d",
+            offline_devices.len(),
+            config.on_missing.action()
+        );
+        devices_to_disable.extend(offline_devices);
+        devices_to_disable.sort_by_key(|device| device.ip);
+    }
+
+    Diff {
+        devices_to_register,
+        devices_to_disable,
+        devices_to_enable,
+        devices_to_rename,
+        netshot_inventory_size,
+        netbox_inventory_size,
+        ..Default::default()
+    }
+}
+
+/// Compare the Netbox and Netshot inventories and compute the changes to push to Netshot
+pub fn run_diff(
+    config: &SyncConfig,
+    netbox_clients: &[NetboxClient],
+    netshot_client: &NetshotClient,
+) -> Result<Diff, Error> {
+    let mut timings = Timings::default();
+    let mut errors = Vec::new();
+    // Both systems are fetched concurrently, their logs naming the system they come from
+    let (netshot_inventory, (netbox_inventory, netbox_timings)) = thread::scope(|scope| {
+        let netbox = scope.spawn(|| {
+            let mut netbox_timings = Timings::default();
+            let inventory = fetch_netbox_sources(config, netbox_clients, &mut netbox_timings);
+            (inventory, netbox_timings)
+        });
+        let netshot_inventory = timings.measure("netshot_fetch", || {
+            netshot_inventory(config, netshot_client, &mut errors)
+        });
+        (
+            netshot_inventory,
+            netbox.join().expect("the Netbox fetch panicked"),
+        )
+    });
+    timings.extend(netbox_timings);
+    let netshot_inventory = netshot_inventory?;
+    let netbox_inventory = netbox_inventory?;
+    let comparison_start = Instant::now();
+    errors.extend(netbox_inventory.errors);
+
+    let inventory_hashes = InventoryHashes::new(
+        &netbox_inventory.devices,
+        &netshot_inventory.devices,
+        &netshot_inventory.disabled,
+    );
+    if config.previous_hashes.as_ref() == Some(&inventory_hashes) {
+        log::info!("Neither inventory changed since the previous run: no changes, skipping");
+        return Ok(Diff {
+            netbox_inventory_size: netbox_inventory.devices.len(),
+            netshot_inventory_size: netshot_inventory.devices.len(),
+            errors,
+            timings,
+            inventory_hashes,
+            inventories_unchanged: true,
+            ..Default::default()
+        });
+    }
+
+    let diff = diff_inventories(
+        config,
+        netbox_inventory.devices,
+        netshot_inventory.devices,
+        netshot_inventory.disabled,
+    );
+    if netbox_inventory.ip_collisions > 0 {
+        log::warn!(
+            "Found {} IP collisions between Netbox objects",
+            netbox_inventory.ip_collisions
+        );
+    }
+    if netbox_inventory.skipped > 0 {
+        log::warn!(
+            "Skipped {} Netbox objects without a usable management address",
+            netbox_inventory.skipped
+        );
+    }
+    if netshot_inventory.duplicate_ips > 0 {
+        log::warn!(
+            "Found {} duplicate management IPs between Netshot devices",
+            netshot_inventory.duplicate_ips
+        );
+    }
+
+    timings.record("comparison", comparison_start.elapsed());
+
+    Ok(Diff {
+        netbox_ip_collisions: netbox_inventory.ip_collisions,
+        netbox_skipped: netbox_inventory.skipped,
+        netshot_duplicate_ips: netshot_inventory.duplicate_ips,
+        errors,
+        timings,
+        inventory_hashes,
+        ..diff
+    })
+}
+
+/// Fetch the full inventories of both systems, without comparing them, the objects of all the
+/// Netbox sources being listed one after the other
+pub fn run_export(
+    config: &SyncConfig,
+    netbox_clients: &[NetboxClient],
+    netshot_client: &NetshotClient,
+) -> Result<Inventories, Error> {
+    let mut timings = Timings::default();
+    let mut netbox_devices = Vec::new();
+    let mut netbox_vms = Vec::new();
+    for netbox_client in netbox_clients {
+        let (mut devices, mut vms) = netbox_inventory(config, netbox_client, &mut timings)?;
+        netbox_devices.append(&mut devices);
+        netbox_vms.append(&mut vms);
+    }
+    let netshot_devices = timings.measure("netshot_fetch", || {
+        fetch_netshot_devices(config, netshot_client)
+    })?;
+
+    Ok(Inventories {
+        netbox_devices,
+        netbox_vms,
+        netshot_devices,
+    })
+}
+
+/// Fetch and simplify the inventories of both systems, then join them by IP without comparing them
+pub fn run_simplified_export(
+    config: &SyncConfig,
+    netbox_clients: &[NetboxClient],
+    netshot_client: &NetshotClient,
+) -> Result<Vec<InventoryRow>, Error> {
+    let mut timings = Timings::default();
+    // The skipped devices are already logged, the export has no summary to add them to
+    let mut errors = Vec::new();
+    let mut netshot_simplified_inventory = timings
+        .measure("netshot_fetch", || {
+            netshot_inventory(config, netshot_client, &mut errors)
+        })?
+        .devices;
+    let mut netbox_simplified_devices =
+        fetch_netbox_sources(config, netbox_clients, &mut timings)?.devices;
+
+    config
+        .ip_filter
+        .apply(&mut netbox_simplified_devices, "Netbox");
+    config
+        .ip_filter
+        .apply(&mut netshot_simplified_inventory, "Netshot");
+
+    Ok(inventory_rows(
+        &netbox_simplified_devices,
+        &netshot_simplified_inventory,
+    ))
+}
+
+/// Compare the Netbox and Netshot inventories and push the required changes to Netshot
+pub fn run_sync(
+    config: &SyncConfig,
+    netbox_clients: &[NetboxClient],
+    netshot_client: &NetshotClient,
+) -> Result<SyncOutcome, Error> {
+    let diff = run_diff(config, netbox_clients, netshot_client)?;
+    apply_diff(config, diff, netshot_client)
+}
+
+/// Push the changes of a previously computed diff to Netshot (or only plan them in check mode)
+pub fn apply_diff(
+    config: &SyncConfig,
+    diff: Diff,
+    netshot_client: &NetshotClient,
+) -> Result<SyncOutcome, Error> {
+    let Diff {
+        devices_to_register,
+        devices_to_disable,
+        devices_to_enable,
+        devices_to_rename,
+        netbox_ip_collisions,
+        netbox_skipped,
+        netshot_duplicate_ips,
+        netshot_inventory_size,
+        netbox_inventory_size,
+        mut errors,
+        mut timings,
+        inventory_hashes,
+        inventories_unchanged,
+    } = diff;
+
+    if config.no_disable && !devices_to_disable.is_empty() {
+        log::info!(
+            "Not {} the {} devices missing on Netbox, --no-disable only reports them",
+            match config.on_missing {
+                OnMissing::Disable => "disabling",
+                OnMissing::Delete => "deleting",
+            },
+            devices_to_disable.len()
+        );
+    }
+    if config.no_register && !devices_to_register.is_empty() {
+        log::info!(
+            "Not registering the {} devices missing on Netshot, --no-register only reports them",
+            devices_to_register.len()
+        );
+    }
+
+    if let Err(error) = check_disable_threshold(
+        config,
+        if config.no_disable {
+            0
+        } else {
+            devices_to_disable.len()
+        },
+        netshot_inventory_size,
+    ) {
+        if config.force {
+            log::warn!("{}, forcing it", error);
+        } else if config.check {
+            log::warn!("{}, the run would be aborted", error);
+        } else {
+            log::error!("{}, aborting before pushing any change", error);
+            return Err(error.context("Refusing to proceed, use --force to override"));
+        }
+    }
+
+    // An (almost) empty Netbox inventory is a misconfiguration rather than an empty network,
+    // unless it only holds the recently updated objects
+    let disable_blocked = !config.no_disable
+        && !config.incremental
+        && netbox_inventory_size < config.min_netbox_devices;
+    if disable_blocked {
+        log::error!(
+            "Netbox returned {} devices, fewer than the minimum of {}, not disabling anything",
+            netbox_inventory_size,
+            config.min_netbox_devices
+        );
+    }
+
+    let mut report = Report::new(config.check)?;
+    report.netbox_ip_collisions = netbox_ip_collisions;
+    report.netbox_skipped = netbox_skipped;
+    report.netshot_duplicate_ips = netshot_duplicate_ips;
+
+    if !config.check {
+        let concurrency = config.concurrency.max(1);
+        let group_id = config.netshot_group_id;
+        let guard = WriteGuard::new(config.fail_fast, config.deadline);
+        log::debug!("Applying changes using {} workers", concurrency);
+
+        // The changes are pushed in order, registrations first and each list sorted by IP
+        let mut budget = config.limit.unwrap_or(usize::MAX);
+        let mut take = |changes: usize| {
+            let taken = changes.min(budget);
+            budget -= taken;
+            taken
+        };
+        // The registrations and disables left out by the flags are only reported
+        let register_planned = if config.no_register {
+            0
+        } else {
+            devices_to_register.len()
+        };
+        let disable_planned = if disable_blocked || config.no_disable {
+            0
+        } else {
+            devices_to_disable.len()
+        };
+        let register_count = take(register_planned);
+        let disable_count = take(disable_planned);
+        let enable_count = take(devices_to_enable.len());
+        let rename_count = take(devices_to_rename.len());
+        if let Some(limit) = config.limit {
+            let planned = register_planned
+                + disable_planned
+                + devices_to_enable.len()
+                + devices_to_rename.len();
+            let applied = register_count + disable_count + enable_count + rename_count;
+            if planned > applied {
+                log::warn!(
+                    "Limited to {} changes per run, {} changes deferred to the next runs",
+                    limit,
+                    planned - applied
+                );
+            }
+        }
+
+        let every = config.progress_every;
+        let registrations = timings.measure("registration", || {
+            let progress = Progress::new("Registered", register_count, every);
+            if config.register_batch_size > 1 {
+                return register_in_batches(
+                    config,
+                    netshot_client,
+                    &guard,
+                    &progress,
+                    &devices_to_register[..register_count],
+                );
+            }
+            common::run_parallel(
+                devices_to_register[..register_count].to_vec(),
+                concurrency,
+                |device| {
+                    let domain_id = config.domain_for(device);
+                    let registration = guard.run(|| {
+                        netshot_client.register_new_device(
+                            &config.new_device(device),
+                            domain_id,
+                            group_id,
+                        )
+                    });
+                    match &registration {
+                        Ok(payload) if payload.is_already_registered() => {
+                            log::info!("{} was already registered", device)
+                        }
+                        Ok(_) => log::info!("{} registered into domain {}", device, domain_id),
+                        Err(_) => {}
+                    }
+                    progress.add(1);
+                    registration
+                },
+            )
+        });
+        log_write_results("Registration", &registrations);
+
+        if config.snapshot_on_register {
+            let mut registered_devices: Vec<(DeviceRef, u32)> = Vec::new();
+            for (device, result) in &registrations {
+                if let Ok(registration) = result {
+                    if registration.is_already_registered() {
+                        continue;
+                    }
+                    match registration.known_device_id() {
+                        Some(device_id) => registered_devices.push((device.clone(), device_id)),
+                        None => log::warn!(
+                            "Netshot did not return the ID of {}, not scheduling a snapshot",
+                            device
+                        ),
+                    }
+                }
+            }
+            let snapshots = timings.measure("snapshot", || {
+                common::run_parallel(registered_devices, concurrency, |(_, id)| {
+                    guard.run(|| netshot_client.trigger_snapshot(*id))
+                })
+            });
+            let snapshots: Vec<(DeviceRef, ApiResult<_>)> = snapshots
+                .into_iter()
+                .map(|((device, _), result)| (device, result))
+                .collect();
+            log_write_results("Snapshot", &snapshots);
+
+            if let Some(timeout) = config.wait_snapshots {
+                let tasks: Vec<(DeviceRef, u32)> = snapshots
+                    .iter()
+                    .filter_map(|(device, result)| {
+                        result.as_ref().ok().map(|task| (device.clone(), task.id))
+                    })
+                    .collect();
+                log::info!(
+                    "Waiting up to {}s for {} snapshots to complete",
+                    timeout.as_secs(),
+                    tasks.len()
+                );
+                let deadline = match config.deadline {
+                    Some(run_deadline) => run_deadline.min(Instant::now() + timeout),
+                    None => Instant::now() + timeout,
+                };
+                let completions = timings.measure("snapshot_wait", || {
+                    common::run_parallel(tasks, concurrency, |(_, task_id)| {
+                        netshot_client.wait_for_task(*task_id, deadline)
+                    })
+                });
+                let completions: Vec<(DeviceRef, ApiResult<_>)> = completions
+                    .into_iter()
+                    .map(|((device, _), result)| (device, result))
+                    .collect();
+                log_write_results("Snapshot completion", &completions);
+                report.snapshots = completions
+                    .iter()
+                    .map(|(device, result)| ReportEntry::applied(device, result))
+                    .collect();
+            }
+        }
+
+        let mut disables = Vec::new();
+        if !disable_blocked && !config.no_disable {
+            let action = config.on_missing.action();
+            disables = timings.measure(action, || {
+                let progress = match config.on_missing {
+                    OnMissing::Disable => Progress::new("Disabled", disable_count, every),
+                    OnMissing::Delete => Progress::new("Deleted", disable_count, every),
+                };
+                common::run_parallel(
+                    devices_to_disable[..disable_count].to_vec(),
+                    concurrency,
+                    |device| {
+                        // Whether the device was already disabled, e.g. by a concurrent run
+                        let result = guard.run(|| match config.on_missing {
+                            OnMissing::Disable => netshot_client
+                                .disable_device(device.ip.to_string())
+                                .map(|update| update.is_none()),
+                            OnMissing::Delete => {
+                                let device_id = device.netshot_id.ok_or(ApiError::NotFound)?;
+                                netshot_client.delete_device(device_id).map(|_| false)
+                            }
+                        });
+                        progress.add(1);
+                        result
+                    },
+                )
+            });
+            match config.on_missing {
+                OnMissing::Disable => log_write_results("Disable", &disables),
+                OnMissing::Delete => log_write_results("Delete", &disables),
+            }
+        }
+
+        let enables = timings.measure("enable", || {
+            let progress = Progress::new("Enabled", enable_count, every);
+            common::run_parallel(
+                devices_to_enable[..enable_count].to_vec(),
+                concurrency,
+                |device| {
+                    let result = guard.run(|| {
+                        netshot_client
+                            .enable_device(device.ip.to_string())
+                            .map(|update| update.is_none())
+                    });
+                    progress.add(1);
+                    result
+                },
+            )
+        });
+        log_write_results("Enable", &enables);
+
+        let renames = timings.measure("rename", || {
+            let progress = Progress::new("Renamed", rename_count, every);
+            common::run_parallel(
+                devices_to_rename[..rename_count].to_vec(),
+                concurrency,
+                |rename| {
+                    let result = guard.run(|| {
+                        netshot_client
+                            .update_device_name(rename.netshot_id, rename.device.hostname.clone())
+                    });
+                    if result.is_ok() {
+                        log::info!("Renamed {}", rename);
+                    }
+                    progress.add(1);
+                    result
+                },
+            )
+        });
+        let renames: Vec<(DeviceRef, ApiResult<_>)> = renames
+            .into_iter()
+            .map(|(rename, result)| (rename.device, result))
+            .collect();
+        log_write_results("Rename", &renames);
+
+        // The cached Netshot inventory no longer reflects the pushed changes
+        let pushed = registrations.len() + disables.len() + enables.len() + renames.len();
+        if let (Some(cache), true) = (&config.netshot_cache, pushed > 0) {
+            if let Err(error) = cache.invalidate() {
+                log::warn!("{:#}", error);
+                errors.push(ErrorEntry::new("netshot_cache", format!("{:#}", error)));
+            }
+        }
+
+        report.timed_out = guard.timed_out();
+
+        // The deferred changes stay in the report, as planned ones
+        report.devices_to_register = registrations
+            .iter()
+            .map(|(device, result)| {
+                let unchanged = matches!(result, Ok(payload) if payload.is_already_registered());
+                ReportEntry::applied(device, result).with_unchanged(unchanged)
+            })
+            .chain(
+                devices_to_register[register_count..]
+                    .iter()
+                    .map(ReportEntry::planned),
+            )
+            .collect();
+        report.devices_to_disable = disables
+            .iter()
+            .map(|(device, result)| {
+                ReportEntry::applied(device, result).with_unchanged(matches!(result, Ok(true)))
+            })
+            .chain(
+                devices_to_disable[disable_count..]
+                    .iter()
+                    .map(ReportEntry::planned),
+            )
+            .collect();
+        report.devices_to_enable = enables
+            .iter()
+            .map(|(device, result)| {
+                ReportEntry::applied(device, result).with_unchanged(matches!(result, Ok(true)))
+            })
+            .chain(
+                devices_to_enable[enable_count..]
+                    .iter()
+                    .map(ReportEntry::planned),
+            )
+            .collect();
+        report.devices_to_rename = renames
+            .iter()
+            .map(|(device, result)| ReportEntry::applied(device, result))
+            .chain(
+                devices_to_rename[rename_count..]
+                    .iter()
+                    .map(|rename| ReportEntry::planned(&rename.device)),
+            )
+            .collect();
+    } else {
+        for rename in &devices_to_rename {
+            log::info!("{} would be renamed", rename);
+        }
+        report.devices_to_register = devices_to_register
+            .iter()
+            .map(ReportEntry::planned)
+            .collect();
+        report.devices_to_disable = devices_to_disable
+            .iter()
+            .map(ReportEntry::planned)
+            .collect();
+        report.devices_to_enable = devices_to_enable.iter().map(ReportEntry::planned).collect();
+        report.devices_to_rename = devices_to_rename
+            .iter()
+            .map(|rename| ReportEntry::planned(&rename.device))
+            .collect();
+    }
+
+    report.update_counts();
+    report.skipped = inventories_unchanged;
+    if report.counts.unchanged > 0 {
+        log::info!(
+            "{} devices were already in the desired state on Netshot",
+            report.counts.unchanged
+        );
+    }
+
+    // The changes already pushed are still reported, the run failing afterwards
+    if disable_blocked && !config.check {
+        report.disable_blocked = true;
+        errors.push(ErrorEntry::new(
+            config.on_missing.action(),
+            format!(
+                "Netbox returned {} devices, fewer than the minimum of {}, the {} step was skipped",
+                netbox_inventory_size,
+                config.min_netbox_devices,
+                config.on_missing.action()
+            ),
+        ));
+    }
+
+    let mut outcome = SyncOutcome {
+        devices_to_register,
+        devices_to_disable,
+        devices_to_enable,
+        devices_to_rename,
+        on_missing: config.on_missing,
+        netbox_ip_collisions,
+        netbox_skipped,
+        netshot_duplicate_ips,
+        failures: report.counts.failures,
+        errors: Vec::new(),
+        report,
+        timings,
+        inventory_hashes,
+        inventories_unchanged,
+    };
+    errors.extend(
+        outcome
+            .write_failures()
+            .into_iter()
+            .map(|(action, entry)| ErrorEntry::from_entry(action, entry)),
+    );
+    for error in errors {
+        outcome.add_error(error);
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flexi_logger::{AdaptiveFormat, Logger};
+
+    #[ctor::ctor]
+    fn enable_logging() {
+        let _ = Logger::try_with_str("debug")
+            .unwrap()
+            .adaptive_format_for_stderr(AdaptiveFormat::Detailed)
+            .start();
+    }
+
+    fn inventory(devices: &[(&str, &str, Source)]) -> HashMap<IpAddr, DeviceRef> {
+        devices
+            .iter()
+            .map(|(ip, hostname, source)| {
+                let ip: IpAddr = ip.parse().unwrap();
+                (ip, DeviceRef::new(ip, hostname.to_string(), *source))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare_disjoint_and_shared_inventories() {
+        let netbox = inventory(&[
+            ("10.0.0.3", "core-3", Source::Device),
+            ("10.0.0.1", "core-1", Source::Device),
+            ("10.0.0.2", "vm-2", Source::Vm),
+        ]);
+        let netshot = inventory(&[
+            ("10.0.0.1", "core-1", Source::Netshot),
+            ("10.0.0.9", "old-9", Source::Netshot),
+            ("10.0.0.8", "old-8", Source::Netshot),
+        ]);
+
+        let (to_register, to_disable) = compare_inventories(&netbox, &netshot);
+
+        let registered: Vec<(String, Source)> = to_register
+            .iter()
+            .map(|device| (device.ip.to_string(), device.source))
+            .collect();
+        assert_eq!(
+            registered,
+            vec![
+                ("10.0.0.2".to_string(), Source::Vm),
+                ("10.0.0.3".to_string(), Source::Device)
+            ]
+        );
+        let disabled: Vec<&str> = to_disable
+            .iter()
+            .map(|device| device.hostname.as_str())
+            .collect();
+        assert_eq!(disabled, vec!["old-8", "old-9"]);
+        assert!(to_disable
+            .iter()
+            .all(|device| device.source == Source::Netshot));
+    }
+
+    #[test]
+    fn compare_empty_inventories() {
+        let netbox = inventory(&[("10.0.0.1", "core-1", Source::Device)]);
+        let empty = HashMap::new();
+
+        let (to_register, to_disable) = compare_inventories(&netbox, &empty);
+        assert_eq!(to_register.len(), 1);
+        assert!(to_disable.is_empty());
+
+        let (to_register, to_disable) = compare_inventories(&empty, &netbox);
+        assert!(to_register.is_empty());
+        assert_eq!(to_disable[0].hostname, "core-1");
+
+        let (to_register, to_disable) = compare_inventories(&netbox, &netbox);
+        assert!(to_register.is_empty() && to_disable.is_empty());
+    }
+
+    #[test]
+    fn diff_inventories_cases() {
+        struct Case {
+            name: &'static str,
+            config: SyncConfig,
+            netbox: &'static [(&'static str, &'static str)],
+            netshot: &'static [(&'static str, &'static str)],
+            disabled: &'static [&'static str],
+            register: &'static [&'static str],
+            disable: &'static [&'static str],
+            enable: &'static [&'static str],
+        }
+
+        let excluding = SyncConfig {
+            ip_filter: common::filter::IpFilter {
+                exclude_ips: vec!["10.0.0.9".parse().unwrap()],
+                exclude_subnets: vec!["10.1.0.0/16".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cases = vec![
+            Case {
+                name: "empty Netbox",
+                config: SyncConfig::default(),
+                netbox: &[],
+                netshot: &[("10.0.0.1", "core-1"), ("10.0.0.2", "core-2")],
+                disabled: &[],
+                register: &[],
+                disable: &["10.0.0.1", "10.0.0.2"],
+                enable: &[],
+            },
+            Case {
+                name: "empty Netshot",
+                config: SyncConfig::default(),
+                netbox: &[("10.0.0.2", "core-2"), ("10.0.0.1", "core-1")],
+                netshot: &[],
+                disabled: &[],
+                register: &["10.0.0.1", "10.0.0.2"],
+                disable: &[],
+                enable: &[],
+            },
+            Case {
+                name: "overlapping",
+                config: SyncConfig::default(),
+                netbox: &[("10.0.0.1", "core-1"), ("10.0.0.2", "core-2")],
+                netshot: &[("10.0.0.2", "core-2"), ("10.0.0.3", "core-3")],
+                disabled: &[],
+                register: &["10.0.0.1"],
+                disable: &["10.0.0.3"],
+                enable: &[],
+            },
+            Case {
+                name: "name moved without name matching",
+                config: SyncConfig::default(),
+                netbox: &[("10.0.0.5", "core-1")],
+                netshot: &[("10.0.0.1", "core-1.example.org")],
+                disabled: &[],
+                register: &["10.0.0.5"],
+                disable: &["10.0.0.1"],
+                enable: &[],
+            },
+            Case {
+                name: "name moved with name matching",
+                config: SyncConfig {
+                    match_by_name: true,
+                    ..Default::default()
+                },
+                netbox: &[("10.0.0.5", "core-1"), ("10.0.0.6", "core-2")],
+                netshot: &[("10.0.0.1", "core-1.example.org")],
+                disabled: &[],
+                register: &["10.0.0.6"],
+                disable: &[],
+                enable: &[],
+            },
+            Case {
+                name: "excluded",
+                config: excluding,
+                netbox: &[("10.0.0.1", "core-1"), ("10.1.0.1", "lab-1")],
+                netshot: &[("10.0.0.9", "old-9"), ("10.1.0.2", "lab-2")],
+                disabled: &[],
+                register: &["10.0.0.1"],
+                disable: &[],
+                enable: &[],
+            },
+            Case {
+                name: "incremental",
+                config: SyncConfig {
+                    incremental: true,
+                    ..Default::default()
+                },
+                netbox: &[("10.0.0.1", "core-1"), ("10.0.0.2", "core-2")],
+                netshot: &[("10.0.0.2", "core-2"), ("10.0.0.3", "core-3")],
+                disabled: &[],
+                register: &["10.0.0.1"],
+                disable: &[],
+                enable: &[],
+            },
+            Case {
+                name: "disabled on Netshot",
+                config: SyncConfig {
+                    reenable: true,
+                    ..Default::default()
+                },
+                netbox: &[("10.0.0.1", "core-1")],
+                netshot: &[("10.0.0.1", "core-1"), ("10.0.0.2", "core-2")],
+                disabled: &["10.0.0.1", "10.0.0.2"],
+                register: &[],
+                disable: &["10.0.0.2"],
+                enable: &["10.0.0.1"],
+            },
+        ];
+
+        let ips = |devices: &[DeviceRef]| -> Vec<String> {
+            devices.iter().map(|device| device.ip.to_string()).collect()
+        };
+        for case in cases {
+            let netbox: Vec<_> = case
+                .netbox
+                .iter()
+                .map(|(ip, hostname)| (*ip, *hostname, Source::Device))
+                .collect();
+            let netshot: Vec<_> = case
+                .netshot
+                .iter()
+                .map(|(ip, hostname)| (*ip, *hostname, Source::Netshot))
+                .collect();
+            let disabled = case.disabled.iter().map(|ip| ip.parse().unwrap()).collect();
+
+            let diff = diff_inventories(
+                &case.config,
+                inventory(&netbox),
+                inventory(&netshot),
+                disabled,
+            );
+
+            assert_eq!(
+                ips(&diff.devices_to_register),
+                case.register,
+                "{}",
+                case.name
+            );
+            assert_eq!(ips(&diff.devices_to_disable), case.disable, "{}", case.name);
+            assert_eq!(ips(&diff.devices_to_enable), case.enable, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn changes_by_source() {
+        let netbox = inventory(&[
+            ("10.0.0.1", "core-1", Source::Device),
+            ("10.0.0.2", "vm-2", Source::Vm),
+            ("10.0.0.3", "vm-3", Source::Vm),
+        ]);
+        let netshot = inventory(&[("10.0.0.2", "vm-2", Source::Netshot)]);
+        let config = SyncConfig {
+            netbox_vms_filter: Some(String::new()),
+            reenable: true,
+            ..Default::default()
+        };
+
+        let diff = diff_inventories(&config, netbox, netshot, vec!["10.0.0.2".parse().unwrap()]);
+
+        assert_eq!(diff.devices_to_enable[0].hostname, "vm-2");
+        assert_eq!(diff.devices_to_enable[0].source, Source::Vm);
+        assert_eq!(
+            source_breakdown(diff.devices_to_register.iter().collect()),
+            " (1 devices, 1 VMs)"
+        );
+    }
+
+    #[test]
+    fn offline_devices_disabled() {
+        let mut netbox = inventory(&[
+            ("10.0.0.1", "core-1", Source::Device),
+            ("10.0.0.2", "old-2", Source::Device),
+            ("10.0.0.3", "old-3", Source::Device),
+            ("10.0.0.4", "new-4", Source::Device),
+        ]);
+        for (ip, status) in [
+            ("10.0.0.2", "offline"),
+            ("10.0.0.3", "decommissioning"),
+            ("10.0.0.4", "offline"),
+        ]
+        .iter()
+        {
+            netbox.get_mut(&ip.parse().unwrap()).unwrap().status = Some(status.to_string());
+        }
+        let netshot = inventory(&[
+            ("10.0.0.1", "core-1", Source::Netshot),
+            ("10.0.0.2", "old-2", Source::Netshot),
+            ("10.0.0.3", "old-3", Source::Netshot),
+            ("10.0.0.9", "gone-9", Source::Netshot),
+        ]);
+        let disabled = vec!["10.0.0.3".parse().unwrap()];
+        let config = SyncConfig {
+            offline_statuses: vec![String::from("offline"), String::from("decommissioning")],
+            reenable: true,
+            ..Default::default()
+        };
+
+        let diff = diff_inventories(&config, netbox.clone(), netshot.clone(), disabled.clone());
+
+        let ips = |devices: &[DeviceRef]| -> Vec<String> {
+            devices.iter().map(|device| device.ip.to_string()).collect()
+        };
+        assert!(diff.devices_to_register.is_empty());
+        assert_eq!(ips(&diff.devices_to_disable), ["10.0.0.2", "10.0.0.9"]);
+        assert!(diff.devices_to_enable.is_empty());
+        assert_eq!(diff.netbox_inventory_size, 4);
+        assert_eq!(diff.netshot_inventory_size, 4);
+
+        let diff = diff_inventories(&SyncConfig::default(), netbox, netshot, disabled);
+        assert_eq!(ips(&diff.devices_to_register), ["10.0.0.4"]);
+        assert_eq!(ips(&diff.devices_to_disable), ["10.0.0.9"]);
+    }
+
+    #[test]
+    fn inventory_rows_side_by_side() {
+        let netbox = inventory(&[
+            ("10.0.0.2", "sw2", Source::Device),
+            ("10.0.0.1", "core-1", Source::Device),
+        ]);
+        let netshot = inventory(&[
+            ("10.0.0.1", "core-1.example.org", Source::Netshot),
+            ("10.0.0.3", "old-3", Source::Netshot),
+        ]);
+
+        let rows = inventory_rows(&netbox, &netshot);
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].netbox_hostname.as_deref(), Some("core-1"));
+        assert_eq!(
+            rows[0].netshot_hostname.as_deref(),
+            Some("core-1.example.org")
+        );
+        assert!(rows[0].in_netbox && rows[0].in_netshot);
+        assert!(rows[1].in_netbox && !rows[1].in_netshot);
+        assert_eq!(rows[2].ip.to_string(), "10.0.0.3");
+        assert!(rows[2].netbox_hostname.is_none() && rows[2].in_netshot);
+    }
+
+    #[test]
+    fn check_mode_sync() {
+        let url = mockito::server_url();
+
+        let _netbox_page1 = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "0".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page1.json")
+            .create();
+        let _netbox_page2 = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "100".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page2.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+        let writes = mockito::mock("POST", "/api/devices").expect(0).create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            check: true,
+            ..Default::default()
+        };
+
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+
+        assert_eq!(
+            outcome.devices_to_register,
+            vec![DeviceRef::new(
+                "1.2.3.5".parse().unwrap(),
+                "test-device-2".to_string(),
+                Source::Device
+            )]
+        );
+        assert!(outcome.devices_to_disable.is_empty());
+        assert!(outcome.devices_to_enable.is_empty());
+        assert_eq!(outcome.report.counts.register, 1);
+        assert!(outcome.has_drift());
+        writes.assert();
+    }
+
+    #[test]
+    fn merge_devices_of_several_filters() {
+        let url = mockito::server_url();
+
+        let site = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::UrlEncoded("site".into(), "a".into()))
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let tenant = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::UrlEncoded("tenant".into(), "b".into()))
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body("[]")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            netbox_devices_filters: vec!["site=a".to_string(), "tenant=b".to_string()],
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+
+        site.assert();
+        tenant.assert();
+        assert_eq!(diff.netbox_inventory_size, 1);
+        assert_eq!(diff.devices_to_register.len(), 1);
+    }
+
+    #[test]
+    fn unchanged_inventories_skip_the_run() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::UrlEncoded("site".into(), "hashed".into()))
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body("[]")
+            .create();
+        let registration = mockito::mock("POST", "/api/devices").expect(0).create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let mut config = SyncConfig {
+            netshot_domain_id: 1,
+            netbox_devices_filters: vec!["site=hashed".to_string()],
+            check: true,
+            ..Default::default()
+        };
+
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+        assert!(!outcome.inventories_unchanged);
+        assert_eq!(outcome.devices_to_register.len(), 1);
+        assert!(outcome.pushed_nothing());
+
+        config.check = false;
+        config.previous_hashes = Some(outcome.inventory_hashes.clone());
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+
+        registration.assert();
+        assert!(outcome.inventories_unchanged);
+        assert!(outcome.report.skipped);
+        assert!(!outcome.has_drift());
+
+        config.previous_hashes = Some(InventoryHashes {
+            netshot: String::from("changed"),
+            ..outcome.inventory_hashes
+        });
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert!(!diff.inventories_unchanged);
+        assert_eq!(diff.devices_to_register.len(), 1);
+    }
+
+    /// A Netbox devices page of the given size, the devices being numbered from `first`
+    fn devices_page(first: u32, size: u32, total: u32) -> String {
+        let results: Vec<serde_json::Value> = (first..first + size)
+            .map(|id| {
+                serde_json::json!({
+                    "id": id,
+                    "name": format!("sw{}", id),
+                    "primary_ip4": {
+                        "id": id,
+                        "family": 4,
+                        "address": format!("10.{}.{}.{}/32", id >> 16, (id >> 8) & 255, id & 255),
+                    },
+                })
+            })
+            .collect();
+        let next = first + size - 1 < total;
+        serde_json::json!({
+            "count": total,
+            "next": next.then(|| format!("http://netbox.invalid/api/dcim/devices/?offset={}", first + size - 1)),
+            "previous": null,
+            "results": results,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn devices_win_ip_collisions() {
+        let object = |id: u32, name: &str| -> netbox::Device {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": name,
+                "primary_ip4": {"id": id, "family": 4, "address": "10.0.0.1/32"},
+                "primary_ip6": null,
+            }))
+            .unwrap()
+        };
+
+        let mut inventory = NetboxInventory::default();
+        inventory.add(object(1, "sw1"), Source::Device);
+        inventory.add(object(1, "sw1"), Source::Device);
+        let mut vms_inventory = NetboxInventory::default();
+        vms_inventory.add(object(1, "vm1"), Source::Vm);
+        inventory.merge(vms_inventory);
+
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(inventory.devices.len(), 1);
+        assert_eq!(inventory.devices[&ip].hostname, "sw1");
+        assert_eq!(inventory.ip_collisions, 1);
+    }
+
+    #[test]
+    fn merge_netbox_sources() {
+        let url = mockito::server_url();
+        let page = |devices: &[(u32, &str, &str)]| {
+            let results: Vec<_> = devices
+                .iter()
+                .map(|(id, name, address)| {
+                    serde_json::json!({
+                        "id": id,
+                        "name": name,
+                        "primary_ip4": {"id": id, "family": 4, "address": address},
+                    })
+                })
+                .collect();
+            serde_json::json!({"count": results.len(), "next": null, "results": results})
+                .to_string()
+        };
+        // The Netbox IDs overlap between the instances, they are only unique within one
+        let _region1 = mockito::mock("GET", "/region1/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body(page(&[
+                (1, "sw1", "10.0.0.1/32"),
+                (2, "sw2", "10.0.0.2/32"),
+            ]))
+            .create();
+        let _region2 = mockito::mock("GET", "/region2/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body(page(&[
+                (1, "sw3", "10.0.0.3/32"),
+                (2, "sw2b", "10.0.0.2/32"),
+            ]))
+            .create();
+
+        let netbox_clients: Vec<_> = ["region1", "region2"]
+            .iter()
+            .map(|region| NetboxClient::new_anonymous(format!("{}/{}", url, region), None).unwrap())
+            .collect();
+        let inventory = fetch_netbox_sources(
+            &SyncConfig::default(),
+            &netbox_clients,
+            &mut Timings::default(),
+        )
+        .unwrap();
+
+        let hostnames: HashSet<&str> = inventory
+            .devices
+            .values()
+            .map(|device| device.hostname.as_str())
+            .collect();
+        assert_eq!(hostnames, HashSet::from(["sw1", "sw2", "sw3"]));
+        assert_eq!(inventory.ip_collisions, 1);
+        assert_eq!(inventory.errors.len(), 1);
+        assert_eq!(inventory.errors[0].phase, "netbox_sources");
+        assert_eq!(inventory.errors[0].hostname.as_deref(), Some("sw2b"));
+        assert!(inventory.errors[0].message.contains("sw2 of"));
+    }
+
+    #[test]
+    fn ip_field_selection() {
+        let object = || -> netbox::Device {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": "sw1",
+                "primary_ip4": {"id": 1, "family": 4, "address": "10.0.0.1/32"},
+                "primary_ip6": {"id": 2, "family": 6, "address": "2001:db8::1/128"},
+                "primary_ip": {"id": 3, "family": 4, "address": "10.0.0.3/32"},
+            }))
+            .unwrap()
+        };
+        let ip_for = |field: netbox::IpField| {
+            let mut inventory = NetboxInventory::new(field);
+            inventory.add(object(), Source::Device);
+            inventory.devices.into_keys().next().unwrap().to_string()
+        };
+
+        assert_eq!(ip_for(netbox::IpField::PrimaryIp4), "10.0.0.1");
+        assert_eq!(ip_for(netbox::IpField::PrimaryIp6), "2001:db8::1");
+        assert_eq!(ip_for(netbox::IpField::PrimaryIp), "10.0.0.3");
+
+        let mut inventory = NetboxInventory::new(netbox::IpField::PrimaryIp);
+        inventory.add(
+            serde_json::from_value(serde_json::json!({
+                "id": 2,
+                "name": "sw2",
+                "primary_ip4": {"id": 4, "family": 4, "address": "10.0.0.2/32"},
+                "primary_ip6": null,
+            }))
+            .unwrap(),
+            Source::Device,
+        );
+        assert!(inventory.devices.is_empty());
+        assert_eq!(inventory.errors[0].message, "no primary IP address");
+        assert_eq!(inventory.skipped, 1);
+    }
+
+    #[test]
+    fn devices_without_address_skipped() {
+        let mut inventory = NetboxInventory::new(netbox::IpField::PrimaryIp4);
+        for device in [
+            serde_json::json!({"id": 1, "name": "sw1", "primary_ip4": null, "primary_ip6": null}),
+            serde_json::json!({"id": 2, "name": "sw2", "primary_ip4": null,
+                "primary_ip6": {"id": 2, "family": 6, "address": "2001:db8::2/128"}}),
+            serde_json::json!({"id": 3, "name": "sw3", "primary_ip6": null,
+                "primary_ip4": {"id": 3, "family": 4, "address": "10.0.0.3/abc"}}),
+        ]
+        .iter()
+        {
+            inventory.add(
+                serde_json::from_value(device.clone()).unwrap(),
+                Source::Device,
+            );
+        }
+
+        assert_eq!(inventory.devices.len(), 1);
+        assert_eq!(inventory.skipped, 2);
+        assert_eq!(inventory.errors.len(), 2);
+        assert_eq!(inventory.errors[0].hostname.as_deref(), Some("sw1"));
+        assert_eq!(
+            inventory.errors[0].message,
+            "no primary IPv4 address, nor an IPv6 one to fall back to"
+        );
+        assert_eq!(
+            inventory.errors[1].message,
+            "Invalid prefix length in address 10.0.0.3/abc"
+        );
+    }
+
+    #[test]
+    fn large_paginated_inventory() {
+        let url = mockito::server_url();
+
+        let pages: Vec<mockito::Mock> = [(1, 1000), (1001, 1000), (2001, 500)]
+            .iter()
+            .map(|(first, size)| {
+                mockito::mock("GET", "/api/dcim/devices/")
+                    .match_query(mockito::Matcher::UrlEncoded(
+                        "offset".into(),
+                        (first - 1).to_string(),
+                    ))
+                    .with_body(devices_page(*first, *size, 2500))
+                    .create()
+            })
+            .collect();
+
+        let netbox_client = NetboxClient::new_anonymous(url, None)
+            .unwrap()
+            .with_page_size(1000);
+        let config = SyncConfig::default();
+
+        let inventory =
+            fetch_netbox_inventory(&config, &netbox_client, &mut Timings::default()).unwrap();
+
+        for page in &pages {
+            page.assert();
+        }
+        assert_eq!(inventory.devices.len(), 2500);
+        assert_eq!(inventory.ip_collisions, 0);
+        let last: IpAddr = "10.0.9.196".parse().unwrap();
+        assert_eq!(inventory.devices[&last].hostname, "sw2500");
+    }
+
+    #[test]
+    fn skipped_devices_in_errors() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_device_without_primary_ip.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            check: true,
+            ..Default::default()
+        };
+
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(outcome.netbox_skipped, 1);
+        assert_eq!(outcome.report.netbox_skipped, 1);
+        let error = &outcome.report.errors[0];
+        assert_eq!(error.phase, "netbox_inventory");
+        assert_eq!(
+            error.hostname.as_deref(),
+            Some("test-device-without-primary-ip")
+        );
+        assert!(error.ip.is_none());
+    }
+
+    #[test]
+    fn canonical_addresses_without_drift() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/canonicalization_devices.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/canonicalization_devices.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+
+        assert_eq!(diff.netbox_inventory_size, 2);
+        assert_eq!(diff.netshot_inventory_size, 2);
+        assert!(!diff.has_changes(), "unexpected drift: {:?}", diff);
+    }
+
+    #[test]
+    fn domain_by_site_then_tenant() {
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            site_domains: vec![("DC1".to_string(), 2)].into_iter().collect(),
+            tenant_domains: vec![("customer-a".to_string(), 3)].into_iter().collect(),
+            ..Default::default()
+        };
+        let device = |site: Option<&str>, tenant: Option<&str>| {
+            DeviceRef::new(
+                "1.2.3.4".parse().unwrap(),
+                "sw1".to_string(),
+                Source::Device,
+            )
+            .with_location(site.map(String::from), tenant.map(String::from))
+        };
+
+        assert_eq!(
+            config.domain_for(&device(Some("DC1"), Some("customer-a"))),
+            2
+        );
+        assert_eq!(
+            config.domain_for(&device(Some("DC2"), Some("customer-a"))),
+            3
+        );
+        assert_eq!(config.domain_for(&device(Some("DC2"), None)), 1);
+        assert_eq!(config.domain_for(&device(None, None)), 1);
+        assert_eq!(
+            config.domains().into_iter().collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn tags_copied_into_comments() {
+        let device = DeviceRef::new(
+            "1.2.3.4".parse().unwrap(),
+            "sw1".to_string(),
+            Source::Device,
+        )
+        .with_tags(vec!["core".to_string(), "critical".to_string()]);
+
+        let config = SyncConfig::default();
+        assert_eq!(config.new_device(&device).comments, None);
+
+        let config = SyncConfig {
+            copy_tags: true,
+            ..Default::default()
+        };
+        let new_device = config.new_device(&device);
+        assert_eq!(new_device.ip_address, "1.2.3.4");
+        assert_eq!(new_device.comments.unwrap(), "core,critical");
+        let untagged = device.with_tags(Vec::new());
+        assert_eq!(config.new_device(&untagged).comments, None);
+    }
+
+    #[test]
+    fn registration_names_from_template() {
+        let device = DeviceRef::new(
+            "1.2.3.4".parse().unwrap(),
+            "sw1".to_string(),
+            Source::Device,
+        );
+
+        let config = SyncConfig::default();
+        assert_eq!(config.new_device(&device).name, None);
+
+        let config = SyncConfig {
+            register_name_template: Some("{site}-{name}".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(config.new_device(&device).name, None);
+        let device = device.with_location(Some("dc1".to_string()), None);
+        assert_eq!(config.new_device(&device).name.as_deref(), Some("dc1-sw1"));
+    }
+
+    #[test]
+    fn registration_drivers_from_platforms() {
+        let device = DeviceRef::new(
+            "1.2.3.4".parse().unwrap(),
+            "sw1".to_string(),
+            Source::Device,
+        )
+        .with_platform(Some("ios".to_string()));
+
+        let config = SyncConfig::default();
+        assert_eq!(config.new_device(&device).driver, None);
+
+        let config = SyncConfig {
+            platform_drivers: vec![("ios".to_string(), "CiscoIOS12".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.new_device(&device).driver.as_deref(),
+            Some("CiscoIOS12")
+        );
+        let device = device.with_platform(Some("junos".to_string()));
+        assert_eq!(config.new_device(&device).driver, None);
+    }
+
+    #[test]
+    fn wait_for_registration_snapshots() {
+        let url = mockito::server_url();
+
+        let _registration = mockito::mock("POST", "/api/devices")
+            .with_body(r#"{"id": 504, "status": "SCHEDULED", "deviceId": 2318}"#)
+            .create();
+        let _snapshot = mockito::mock("POST", "/api/tasks")
+            .with_body_from_file("tests/data/netshot/snapshot_task.json")
+            .create();
+        let poll = mockito::mock("GET", "/api/tasks/505")
+            .with_body(r#"{"id": 505, "status": "FAILURE"}"#)
+            .expect(1)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            snapshot_on_register: true,
+            wait_snapshots: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let diff = Diff {
+            devices_to_register: vec![DeviceRef::new(
+                "1.2.3.4".parse().unwrap(),
+                "sw1".to_string(),
+                Source::Device,
+            )],
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        poll.assert();
+        assert_eq!(outcome.report.counts.snapshot, 1);
+        assert_eq!(outcome.report.snapshots[0].success, Some(false));
+        assert_eq!(outcome.failures, 1);
+    }
+
+    #[test]
+    fn bulk_registration_per_domain() {
+        let url = mockito::server_url();
+
+        let bulk = mockito::mock("POST", "/api/devices/bulk")
+            .with_body_from_file("tests/data/netshot/bulk_registration.json")
+            .expect(2)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            register_batch_size: 50,
+            site_domains: vec![("DC1".to_string(), 2)].into_iter().collect(),
+            ..Default::default()
+        };
+        let device = |ip: &str, site: Option<&str>| {
+            DeviceRef::new(ip.parse().unwrap(), ip.to_string(), Source::Device)
+                .with_location(site.map(String::from), None)
+        };
+        let diff = Diff {
+            devices_to_register: vec![device("1.2.3.4", Some("DC1")), device("1.2.3.5", None)],
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        bulk.assert();
+        assert_eq!(outcome.report.counts.register, 2);
+        assert_eq!(outcome.failures, 1);
+    }
+
+    #[test]
+    fn fail_fast_stops_the_writes() {
+        let url = mockito::server_url();
+
+        let registration = mockito::mock("POST", "/api/devices")
+            .with_status(400)
+            .with_body(r#"{"errorMsg": "Invalid domain", "errorCode": 1}"#)
+            .expect(1)
+            .create();
+        let searches = mockito::mock("POST", "/api/devices/search")
+            .expect(0)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            fail_fast: true,
+            ..Default::default()
+        };
+        let device =
+            |ip: &str, source: Source| DeviceRef::new(ip.parse().unwrap(), ip.to_string(), source);
+        let diff = Diff {
+            devices_to_register: vec![
+                device("1.2.3.4", Source::Device),
+                device("1.2.3.5", Source::Device),
+            ],
+            devices_to_disable: vec![device("1.2.3.6", Source::Netshot)],
+            netbox_inventory_size: 2,
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        registration.assert();
+        searches.assert();
+        let failures = outcome.write_failures();
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures[0].1.error_category, Some("rejected"));
+        assert_eq!(failures[1].1.error_category, Some("aborted"));
+        assert_eq!(failures[2].0, "disable");
+        assert_eq!(outcome.errors.len(), 3);
+        assert_eq!(outcome.report.errors[0].phase, "register");
+        assert_eq!(outcome.report.errors[0].ip.as_deref(), Some("1.2.3.4"));
+    }
+
+    #[test]
+    fn run_timeout_defers_the_writes() {
+        let url = mockito::server_url();
+
+        let registration = mockito::mock("POST", "/api/devices").expect(0).create();
+        let searches = mockito::mock("POST", "/api/devices/search")
+            .expect(0)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        let device =
+            |ip: &str, source: Source| DeviceRef::new(ip.parse().unwrap(), ip.to_string(), source);
+        let diff = Diff {
+            devices_to_register: vec![
+                device("1.2.3.4", Source::Device),
+                device("1.2.3.5", Source::Device),
+            ],
+            devices_to_disable: vec![device("1.2.3.6", Source::Netshot)],
+            netbox_inventory_size: 2,
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        registration.assert();
+        searches.assert();
+        assert!(outcome.report.timed_out);
+        assert_eq!(outcome.report.counts.deferred, 3);
+        assert!(outcome.write_failures().is_empty());
+        assert!(outcome.errors.is_empty());
+        assert!(outcome.report.devices_to_register[0].success.is_none());
+        assert!(!outcome.pushed_nothing());
+    }
+
+    #[test]
+    fn limit_defers_changes() {
+        let url = mockito::server_url();
+
+        let registration = mockito::mock("POST", "/api/devices")
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(2)
+            .create();
+        let deletions = mockito::mock("DELETE", mockito::Matcher::Any)
+            .expect(0)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            on_missing: OnMissing::Delete,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let device = |ip: &str, source: Source| {
+            DeviceRef::new(ip.parse().unwrap(), ip.to_string(), source).with_netshot_id(1)
+        };
+        let diff = Diff {
+            devices_to_register: vec![
+                device("1.2.3.4", Source::Device),
+                device("1.2.3.5", Source::Device),
+            ],
+            devices_to_disable: vec![device("1.2.3.6", Source::Netshot)],
+            netbox_inventory_size: 2,
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        registration.assert();
+        deletions.assert();
+        assert_eq!(outcome.devices_to_disable.len(), 1);
+        assert_eq!(outcome.report.counts.register, 2);
+        assert_eq!(outcome.report.counts.disable, 1);
+        assert_eq!(outcome.report.counts.deferred, 1);
+        assert_eq!(outcome.report.devices_to_disable[0].success, None);
+        assert!(outcome.errors.is_empty());
+        assert!(!outcome.pushed_nothing());
+    }
+
+    #[test]
+    fn no_disable_only_reports() {
+        let url = mockito::server_url();
+
+        let registration = mockito::mock("POST", "/api/devices")
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(1)
+            .create();
+        let deletions = mockito::mock("DELETE", mockito::Matcher::Any)
+            .expect(0)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            on_missing: OnMissing::Delete,
+            no_disable: true,
+            max_disable: Some(0),
+            min_netbox_devices: 10,
+            ..Default::default()
+        };
+        let device = |ip: &str, source: Source| {
+            DeviceRef::new(ip.parse().unwrap(), ip.to_string(), source).with_netshot_id(1)
+        };
+        let diff = Diff {
+            devices_to_register: vec![device("1.2.3.4", Source::Device)],
+            devices_to_disable: vec![device("1.2.3.6", Source::Netshot)],
+            netbox_inventory_size: 1,
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        registration.assert();
+        deletions.assert();
+        assert_eq!(outcome.devices_to_disable.len(), 1);
+        assert_eq!(outcome.report.counts.disable, 1);
+        assert_eq!(outcome.report.devices_to_disable[0].success, None);
+        assert_eq!(outcome.report.devices_to_register[0].success, Some(true));
+        assert!(outcome.errors.is_empty());
+    }
+
+    #[test]
+    fn no_register_only_reports() {
+        let url = mockito::server_url();
+
+        let registration = mockito::mock("POST", "/api/devices").expect(0).create();
+        let deletion = mockito::mock("DELETE", "/api/devices/1")
+            .with_status(200)
+            .expect(1)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            on_missing: OnMissing::Delete,
+            no_register: true,
+            ..Default::default()
+        };
+        let device = |ip: &str, source: Source| {
+            DeviceRef::new(ip.parse().unwrap(), ip.to_string(), source).with_netshot_id(1)
+        };
+        let diff = Diff {
+            devices_to_register: vec![device("1.2.3.4", Source::Device)],
+            devices_to_disable: vec![device("1.2.3.6", Source::Netshot)],
+            netbox_inventory_size: 1,
+            ..Default::default()
+        };
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+
+        registration.assert();
+        deletion.assert();
+        assert_eq!(outcome.report.devices_to_register[0].success, None);
+        assert_eq!(outcome.report.devices_to_disable[0].success, Some(true));
+    }
+
+    #[test]
+    fn reenable_returning_devices() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_disabled_device.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let mut config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert!(!diff.has_changes());
+
+        config.reenable = true;
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert_eq!(diff.devices_to_enable.len(), 1);
+        assert_eq!(diff.devices_to_enable[0].hostname, "test-device");
+        assert!(diff.devices_to_register.is_empty());
+        assert!(diff.devices_to_disable.is_empty());
+    }
+
+    #[test]
+    fn renames_of_shared_ips() {
+        let netbox = inventory(&[
+            ("10.0.0.1", "core-1", Source::Device),
+            ("10.0.0.2", "core-2-new", Source::Device),
+            ("10.0.0.3", "edge-1", Source::Device),
+        ]);
+        let mut netshot = inventory(&[
+            ("10.0.0.1", "core-1", Source::Netshot),
+            ("10.0.0.2", "core-2", Source::Netshot),
+        ]);
+        for (id, device) in netshot.values_mut().enumerate() {
+            device.netshot_id = Some(id as u32);
+        }
+
+        let renames = find_renames(&netbox, &netshot);
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames[0].netshot_name, "core-2");
+        assert_eq!(renames[0].device.hostname, "core-2-new");
+        assert_eq!(renames[0].to_string(), "core-2(10.0.0.2) -> core-2-new");
+    }
+
+    #[test]
+    fn update_renamed_devices() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/renamed_device.json")
+            .create();
+        let rename = mockito::mock("PUT", "/api/devices/1")
+            .match_body(r#"{"name":"test-device"}"#)
+            .with_body(r#"{"status": "INPRODUCTION"}"#)
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let mut config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert!(!diff.has_changes());
+
+        config.update_names = true;
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert_eq!(diff.devices_to_rename.len(), 1);
+        assert_eq!(diff.devices_to_rename[0].netshot_id, 1);
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+        rename.assert();
+        assert_eq!(outcome.report.counts.rename, 1);
+        assert_eq!(outcome.report.counts.register, 0);
+        assert_eq!(outcome.failures, 0);
+    }
+
+    #[test]
+    fn scoped_netshot_inventory() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/multi_domain_devices.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let mut config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert_eq!(diff.netshot_inventory_size, 3);
+        assert_eq!(diff.devices_to_disable.len(), 2);
+
+        config.scope_to_domain = true;
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert_eq!(diff.netshot_inventory_size, 2);
+        assert_eq!(diff.devices_to_disable.len(), 1);
+        assert_eq!(diff.devices_to_disable[0].hostname, "test-device-2");
+    }
+
+    #[test]
+    fn duplicate_netshot_ips() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/duplicate_ip_devices.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            check: true,
+            ..Default::default()
+        };
+
+        let diff = run_diff(&config, &netbox_clients, &netshot_client).unwrap();
+        assert_eq!(diff.netshot_duplicate_ips, 1);
+        assert_eq!(diff.netshot_inventory_size, 2);
+        assert_eq!(diff.devices_to_disable.len(), 1);
+        assert_eq!(diff.devices_to_disable[0].hostname, "test-device-3");
+
+        let outcome = apply_diff(&config, diff, &netshot_client).unwrap();
+        assert_eq!(outcome.netshot_duplicate_ips, 1);
+        assert_eq!(outcome.report.netshot_duplicate_ips, 1);
+    }
+
+    #[test]
+    fn disable_thresholds() {
+        let config = SyncConfig {
+            max_disable: Some(2),
+            max_disable_percent: Some(50),
+            ..Default::default()
+        };
+        assert!(check_disable_threshold(&config, 2, 4).is_ok());
+        assert!(check_disable_threshold(&config, 3, 10).is_err());
+        assert!(check_disable_threshold(&config, 2, 3).is_err());
+        assert!(check_disable_threshold(&SyncConfig::default(), 100, 100).is_ok());
+    }
+
+    #[test]
+    fn sync_aborts_above_max_disable() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/ping.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+        let searches = mockito::mock("POST", "/api/devices/search")
+            .expect(0)
+            .create();
+        let writes = mockito::mock("PUT", mockito::Matcher::Any)
+            .expect(0)
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            max_disable: Some(0),
+            ..Default::default()
+        };
+
+        let error = run_sync(&config, &netbox_clients, &netshot_client).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("1 devices to disable"));
+        searches.assert();
+        writes.assert();
+    }
+
+    #[test]
+    fn delete_missing_devices() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/ping.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+        let disables = mockito::mock("PUT", mockito::Matcher::Any)
+            .expect(0)
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let mut config = SyncConfig {
+            netshot_domain_id: 1,
+            on_missing: OnMissing::Delete,
+            max_disable: Some(0),
+            ..Default::default()
+        };
+
+        let error = run_sync(&config, &netbox_clients, &netshot_client).unwrap_err();
+        assert!(format!("{:#}", error).contains("1 devices to delete"));
+
+        let deletion = mockito::mock("DELETE", "/api/devices/1")
+            .with_status(204)
+            .create();
+        config.max_disable = None;
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+
+        deletion.assert();
+        disables.assert();
+        assert_eq!(outcome.actions()[0].0, "delete");
+        assert_eq!(outcome.report.counts.disable, 1);
+        assert_eq!(outcome.failures, 0);
+    }
+
+    #[test]
+    fn sync_skips_disable_below_min_netbox_devices() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_ipv6_only_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+        let registration = mockito::mock("POST", "/api/devices")
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(1)
+            .create();
+        let searches = mockito::mock("POST", "/api/devices/search")
+            .expect(0)
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            min_netbox_devices: 2,
+            ..Default::default()
+        };
+
+        let outcome = run_sync(&config, &netbox_clients, &netshot_client).unwrap();
+
+        registration.assert();
+        searches.assert();
+        assert!(outcome.report.disable_blocked);
+        assert_eq!(outcome.report.devices_to_register.len(), 1);
+        assert_eq!(outcome.report.devices_to_register[0].success, Some(true));
+        assert_eq!(outcome.report.devices_to_disable[0].success, None);
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(outcome.errors[0].phase, "disable");
+        assert!(outcome.errors[0]
+            .message
+            .contains("fewer than the minimum of 2"));
+        assert!(!outcome.pushed_nothing());
+    }
+
+    #[test]
+    fn export_inventories() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+
+        let inventories = run_export(&config, &netbox_clients, &netshot_client).unwrap();
+
+        assert_eq!(inventories.netbox_devices.len(), 1);
+        assert!(inventories.netbox_vms.is_empty());
+        assert_eq!(inventories.netshot_devices.len(), 1);
+    }
+
+    #[test]
+    fn export_inventories_of_every_domain() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body(r#"{"count": 0, "next": null, "previous": null, "results": []}"#)
+            .create();
+        let domains: Vec<mockito::Mock> = ["1", "2", "3"]
+            .iter()
+            .map(|domain| {
+                mockito::mock("GET", "/api/devices")
+                    .match_query(mockito::Matcher::UrlEncoded(
+                        "group".into(),
+                        domain.to_string(),
+                    ))
+                    .with_body_from_file("tests/data/netshot/single_good_device.json")
+                    .expect(1)
+                    .create()
+            })
+            .collect();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            site_domains: vec![("DC1".to_string(), 2)].into_iter().collect(),
+            tenant_domains: vec![("customer-a".to_string(), 3)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let inventories = run_export(&config, &netbox_clients, &netshot_client).unwrap();
+
+        for domain in &domains {
+            domain.assert();
+        }
+        assert_eq!(inventories.netshot_devices.len(), 3);
+    }
+
+    #[test]
+    fn export_inventories_with_vms() {
+        let url = mockito::server_url();
+
+        let _netbox = mockito::mock("GET", "/api/dcim/devices/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+        let _vms = mockito::mock("GET", "/api/virtualization/virtual-machines/")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_device_without_primary_ip.json")
+            .create();
+        let _netshot = mockito::mock("GET", "/api/devices")
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+
+        let netbox_clients = [NetboxClient::new_anonymous(url.clone(), None).unwrap()];
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            netbox_vms_filter: Some(String::new()),
+            ..Default::default()
+        };
+
+        let inventories = run_export(&config, &netbox_clients, &netshot_client).unwrap();
+
+        assert_eq!(inventories.netbox_devices.len(), 1);
+        assert_eq!(inventories.netbox_vms.len(), 1);
+        assert!(inventories.netbox_vms[0]
+            .management_ip(netbox::IpField::PrimaryIp4)
+            .is_none());
+    }
+
+    #[test]
+    fn register_from_list_skips_known_devices() {
+        let url = mockito::server_url();
+
+        let _known = mockito::mock("POST", "/api/devices/search")
+            .match_body(r#"{"query":"[IP] IS 1.2.3.4"}"#)
+            .with_body_from_file("tests/data/netshot/search.json")
+            .create();
+        let _unknown = mockito::mock("POST", "/api/devices/search")
+            .match_body(r#"{"query":"[IP] IS 1.2.3.5"}"#)
+            .with_body_from_file("tests/data/netshot/search_empty.json")
+            .create();
+        let registration = mockito::mock("POST", "/api/devices")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"ipAddress":"1.2.3.5"}"#.to_string(),
+            ))
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(1)
+            .create();
+
+        let netshot_client = NetshotClient::new(url, String::new(), &Default::default()).unwrap();
+        let config = SyncConfig {
+            netshot_domain_id: 1,
+            ..Default::default()
+        };
+        let ips = vec!["1.2.3.4".parse().unwrap(), "1.2.3.5".parse().unwrap()];
+
+        let outcome = register_from_list(&config, &netshot_client, ips).unwrap();
+
+        assert_eq!(outcome.added, 1);
+        assert_eq!(outcome.skipped, 1);
+        assert_eq!(outcome.failures, 0);
+        registration.assert();
+    }
+}
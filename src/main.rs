@@ -1,255 +1,1835 @@
+use anyhow::{anyhow, Context, Error, Result};
+use flexi_logger::{Cleanup, Criterion, Duplicate, FileSpec, Logger, Naming};
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Serialize;
 use std::collections::HashMap;
-
-use anyhow::{Error, Result};
-use flexi_logger::{Duplicate, FileSpec, Logger};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-use rest::{netbox, netshot};
+use netbox2netshot::common::cache::DiskCache;
+use netbox2netshot::common::config::{self, FileConfig};
+use netbox2netshot::common::csv;
+use netbox2netshot::common::daemon::{self, Shutdown};
+use netbox2netshot::common::filter::IpFilter;
+use netbox2netshot::common::http::{HttpClientOptions, ProxyUrl};
+use netbox2netshot::common::logging::{self, LogFormat};
+use netbox2netshot::common::metrics::Metrics;
+use netbox2netshot::common::notify::{Notification, Notifier};
+use netbox2netshot::common::plan;
+use netbox2netshot::common::prompt;
+use netbox2netshot::common::rate::RateLimiter;
+use netbox2netshot::common::report::ErrorEntry;
+use netbox2netshot::common::retry::RetryPolicy;
+use netbox2netshot::common::secret::{self, Secret};
+use netbox2netshot::common::state::RunState;
+use netbox2netshot::common::{self, HostnameNormalizer, NameTemplate};
+use netbox2netshot::rest::error::ApiResult;
+use netbox2netshot::rest::helpers::AuthScheme;
+use netbox2netshot::rest::{netbox, netshot};
+use netbox2netshot::{Diff, OnMissing, RegistrationOutcome, SyncConfig, SyncOutcome};
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(
+    name = "netbox2netshot",
+    about = "Synchronization tool between netbox and netshot",
+    version = VERSION
+)]
+struct Opt {
+    #[structopt(
+        long,
+        help = "A TOML configuration file, CLI flags and environment variables take precedence over it",
+        env = "NETBOX2NETSHOT_CONFIG"
+    )]
+    config: Option<String>,
+
+    // Handled by `run` before the parsing, only declared for the help
+    #[allow(dead_code)]
+    #[structopt(
+        long,
+        help = "Print a commented configuration file template with every supported key and exit"
+    )]
+    generate_config: bool,
+
+    #[structopt(short, long, help = "Enable debug/verbose mode")]
+    debug: bool,
+
+    #[structopt(
+        long,
+        help = "RUST_LOG-style log directives overriding --debug, e.g. info,netbox2netshot::rest=debug",
+        env
+    )]
+    log_spec: Option<String>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Only log to the log directory, without copying the logs to the console"
+    )]
+    quiet: bool,
+
+    #[structopt(
+        long,
+        help = "Disable the colors of the console logs, also disabled by a non-empty NO_COLOR"
+    )]
+    no_color: bool,
+
+    #[structopt(long, help = "The directory to log to", default_value = "logs", env)]
+    log_directory: String,
+
+    #[structopt(
+        long,
+        help = "Rotate the log file once it reaches this size in MB, a single growing file is used otherwise",
+        env
+    )]
+    log_max_size_mb: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "The number of rotated log files to keep with --log-max-size-mb",
+        default_value = "10",
+        env
+    )]
+    log_keep_files: usize,
+
+    #[structopt(
+        long,
+        help = "The format of the log lines, in the files and on the console: text or json",
+        default_value = "text",
+        env
+    )]
+    log_format: LogFormat,
+
+    #[structopt(
+        long,
+        help = "Log the progress of the Netbox fetches and of the pushed changes every this many objects, 0 to disable",
+        default_value = "500",
+        env
+    )]
+    progress_every: usize,
+
+    #[structopt(long, help = "The Netshot API URL", env)]
+    netshot_url: String,
+
+    #[structopt(
+        long,
+        help = "The path the Netshot REST API is mounted under, appended to the Netshot URL",
+        default_value = "/api",
+        env
+    )]
+    netshot_api_base: String,
+
+    #[structopt(
+        long,
+        help = "The TLS certificate to use to authenticate to Netshot (PKCS12 format)",
+        env
+    )]
+    netshot_tls_client_certificate: Option<String>,
+
+    #[structopt(long, help = "The optional password for the netshot PKCS12 file", env)]
+    netshot_tls_client_certificate_password: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The PEM TLS certificate to use to authenticate to Netshot, instead of PKCS12",
+        env,
+        conflicts_with = "netshot-tls-client-certificate",
+        requires = "netshot-tls-client-key-pem"
+    )]
+    netshot_tls_client_cert_pem: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The PEM (PKCS8) private key of the Netshot TLS certificate",
+        env,
+        conflicts_with = "netshot-tls-client-certificate",
+        requires = "netshot-tls-client-cert-pem"
+    )]
+    netshot_tls_client_key_pem: Option<String>,
+
+    #[structopt(
+        long,
+        help = "A PEM file of CA certificates to trust for Netshot, on top of the system ones",
+        env
+    )]
+    netshot_ca_cert: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The Netshot token",
+        env,
+        hide_env_values = true,
+        required_unless = "netshot-token-file",
+        conflicts_with = "netshot-token-file"
+    )]
+    netshot_token: Option<Secret>,
+
+    #[structopt(
+        long,
+        help = "A file to read the Netshot token from, - for the standard input",
+        env
+    )]
+    netshot_token_file: Option<String>,
+
+    #[structopt(
+        long,
+        help = "How to send the Netshot token: token (X-Netshot-API-Token header) or bearer",
+        default_value = "token",
+        env
+    )]
+    netshot_auth_scheme: AuthScheme,
+
+    #[structopt(
+        long,
+        help = "The domain ID to use when importing a new device",
+        env,
+        required_unless = "netshot-domain-name"
+    )]
+    netshot_domain_id: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "The name of the domain to use when importing a new device, --netshot-domain-id wins over it",
+        env
+    )]
+    netshot_domain_name: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The device group ID to assign newly registered devices to",
+        env
+    )]
+    netshot_group_id: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "HTTP(s) proxy to use to connect to Netshot, credentials given as user:password@",
+        env
+    )]
+    netshot_proxy: Option<ProxyUrl>,
+
+    #[structopt(
+        long,
+        help = "The Netbox API URL, can be repeated to merge the inventories of several Netbox instances",
+        env,
+        number_of_values = 1
+    )]
+    netbox_url: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "The TLS certificate to use to authenticate to Netbox (PKCS12 format)",
+        env
+    )]
+    netbox_tls_client_certificate: Option<String>,
+
+    #[structopt(long, help = "The optional password for the netbox PKCS12 file", env)]
+    netbox_tls_client_certificate_password: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The PEM TLS certificate to use to authenticate to Netbox, instead of PKCS12",
+        env,
+        conflicts_with = "netbox-tls-client-certificate",
+        requires = "netbox-tls-client-key-pem"
+    )]
+    netbox_tls_client_cert_pem: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The PEM (PKCS8) private key of the Netbox TLS certificate",
+        env,
+        conflicts_with = "netbox-tls-client-certificate",
+        requires = "netbox-tls-client-cert-pem"
+    )]
+    netbox_tls_client_key_pem: Option<String>,
+
+    #[structopt(
+        long,
+        help = "A PEM file of CA certificates to trust for Netbox, on top of the system ones",
+        env
+    )]
+    netbox_ca_cert: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The Netbox token, repeated along with --netbox-url, the Nth token being used for the Nth URL",
+        env,
+        hide_env_values = true,
+        number_of_values = 1,
+        conflicts_with = "netbox-token-file"
+    )]
+    netbox_token: Vec<Secret>,
+
+    #[structopt(
+        long,
+        help = "A file to read the Netbox token from, - for the standard input",
+        env
+    )]
+    netbox_token_file: Option<String>,
+
+    #[structopt(
+        long,
+        help = "How to send the Netbox token: token (Authorization: Token header) or bearer",
+        default_value = "token",
+        env
+    )]
+    netbox_auth_scheme: AuthScheme,
+
+    #[structopt(
+        long,
+        default_value = "",
+        help = "The querystring to use to select the devices from netbox, can be repeated to merge the devices of several querystrings",
+        number_of_values = 1,
+        env
+    )]
+    netbox_devices_filter: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "The querystring to use to select the VM from netbox",
+        env
+    )]
+    netbox_vms_filter: Option<String>,
+
+    #[structopt(
+        long,
+        help = "A Netbox status to select the devices and VMs with, can be repeated, active by default, empty to select any",
+        number_of_values = 1
+    )]
+    netbox_status: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "A Netbox role slug to select the devices with, can be repeated to select any of them",
+        number_of_values = 1
+    )]
+    netbox_role: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "A Netbox device type ID to select the devices with, can be repeated to select any of them",
+        number_of_values = 1
+    )]
+    netbox_device_type: Vec<u32>,
+
+    #[structopt(
+        long,
+        help = "Disable on Netshot the devices present on Netbox with an offline status, see --offline-status"
+    )]
+    disable_offline: bool,
+
+    #[structopt(
+        long,
+        help = "A Netbox status disabled with --disable-offline, can be repeated, offline and decommissioning by default",
+        number_of_values = 1
+    )]
+    offline_status: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "The number of objects to request per Netbox page, capped by the Netbox MAX_PAGE_SIZE",
+        default_value = "1000",
+        env
+    )]
+    netbox_page_size: u32,
+
+    #[structopt(
+        long,
+        help = "Fetch the Netbox devices and VMs with a single GraphQL query, the filters being GraphQL arguments"
+    )]
+    netbox_graphql: bool,
+
+    #[structopt(
+        long,
+        help = "Paginate the Netbox REST API with a cursor instead of an offset, faster on large tables (Netbox 4.3+)"
+    )]
+    netbox_cursor_pagination: bool,
+
+    #[structopt(
+        long,
+        help = "The Netbox field the management IPs are read from: primary_ip4, primary_ip6 or primary_ip",
+        default_value = "primary_ip4",
+        env
+    )]
+    netbox_ip_field: netbox::IpField,
+
+    #[structopt(
+        long,
+        help = "Only fetch the Netbox objects updated since a duration (e.g. 12h, 7d) or an RFC 3339 timestamp or date, without disabling anything",
+        env
+    )]
+    since: Option<netbox::Since>,
+
+    #[structopt(
+        long,
+        help = "HTTP(s) proxy to use to connect to Netbox, credentials given as user:password@",
+        env
+    )]
+    netbox_proxy: Option<ProxyUrl>,
+
+    #[structopt(
+        long,
+        help = "The User-Agent header sent to Netbox, Netshot and the webhook, netbox2netshot/<version> by default",
+        env
+    )]
+    user_agent: Option<String>,
+
+    #[structopt(
+        long,
+        help = "The maximum time in seconds to establish HTTP connections",
+        default_value = "10",
+        env
+    )]
+    http_connect_timeout_secs: u64,
+
+    #[structopt(
+        long,
+        help = "The maximum time in seconds to wait for an HTTP response once connected",
+        default_value = "60",
+        env
+    )]
+    http_read_timeout_secs: u64,
+
+    #[structopt(
+        long,
+        help = "DANGEROUS: accept invalid TLS certificates from Netbox and Netshot, for lab environments only"
+    )]
+    danger_insecure_tls: bool,
+
+    #[structopt(
+        long,
+        help = "Don't ask Netbox and Netshot for gzip-compressed responses"
+    )]
+    no_compression: bool,
+
+    #[structopt(
+        long,
+        help = "The number of retries on connection errors and 5xx responses",
+        default_value = "3",
+        env
+    )]
+    http_retries: u32,
+
+    #[structopt(
+        long,
+        help = "The base delay in milliseconds of the exponential retry backoff",
+        default_value = "200",
+        env
+    )]
+    http_retry_base_ms: u64,
+
+    #[structopt(
+        long,
+        help = "The maximum wait in seconds honored when a server rate-limits us (429 Retry-After)",
+        default_value = "60",
+        env
+    )]
+    max_rate_wait_secs: u64,
+
+    #[structopt(
+        long,
+        help = "The maximum number of requests per second sent to Netbox and Netshot altogether, unlimited by default",
+        env
+    )]
+    max_rps: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Also retry non-idempotent requests such as device registrations"
+    )]
+    retry_writes: bool,
+
+    #[structopt(
+        long,
+        help = "A domain suffix to strip from the hostnames before comparing them, can be repeated",
+        number_of_values = 1
+    )]
+    hostname_strip_suffix: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "A regex whose first capture group is the canonical name of a hostname",
+        env
+    )]
+    hostname_regex: Option<Regex>,
+
+    #[structopt(
+        long,
+        help = "A management IP to leave out of the synchronization, can be repeated",
+        number_of_values = 1
+    )]
+    exclude_ip: Vec<IpAddr>,
+
+    #[structopt(
+        long,
+        help = "A subnet (CIDR) whose management IPs are left out of the synchronization, can be repeated",
+        number_of_values = 1
+    )]
+    exclude_subnet: Vec<IpNet>,
+
+    #[structopt(
+        long,
+        help = "Only synchronize the management IPs within this subnet (CIDR), can be repeated",
+        number_of_values = 1
+    )]
+    only_subnet: Vec<IpNet>,
+
+    #[structopt(skip)]
+    site_domains: HashMap<String, u32>,
+
+    #[structopt(skip)]
+    netbox_sources: Vec<config::NetboxSource>,
+
+    #[structopt(skip)]
+    tenant_domains: HashMap<String, u32>,
+
+    #[structopt(skip)]
+    platform_drivers: HashMap<String, String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+// The subcommands, `sync` being the default when none is given. Parsed once, so the size of
+// `SyncOpt` doesn't matter (structopt can't box it anyway)
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt, Clone)]
+enum Command {
+    /// Push the changes required to bring Netshot in line with Netbox
+    Sync(SyncOpt),
+    /// Print the changes required to bring Netshot in line with Netbox and exit
+    Diff(DiffOpt),
+    /// Dump the full inventories of both systems as JSON
+    Export(ExportOpt),
+    /// Check the connection settings, the tokens and the domains without fetching inventories
+    Validate,
+    /// List the domains defined in Netshot
+    ListDomains(ListOpt),
+    /// List the device groups defined in Netshot
+    ListGroups(ListOpt),
+}
+
+#[derive(Debug, StructOpt, Clone)]
+struct SyncOpt {
+    #[structopt(short, long, help = "Check mode, will not push any change to Netshot")]
+    check: bool,
+
+    #[structopt(
+        long,
+        help = "Match the devices whose IP differs between Netbox and Netshot by hostname"
+    )]
+    match_by_name: bool,
+
+    #[structopt(long, help = "Write a JSON report of the run to the given path", env)]
+    report: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Write the computed changes as CSV to the given path",
+        env
+    )]
+    csv: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Write the plan of the computed changes (+/- lines) to the given path, printed in check mode otherwise",
+        env
+    )]
+    dry_run_diff: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Write Prometheus textfile metrics of the run to the given path",
+        env
+    )]
+    metrics_file: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Keep the drift found by each run in the given file, to report what changed since the previous run",
+        env
+    )]
+    state_file: Option<String>,
+
+    #[structopt(
+        long,
+        help = "A webhook URL (Slack/Teams compatible) to post a summary to after each run, using the Netshot proxy",
+        env,
+        hide_env_values = true
+    )]
+    notify_webhook: Option<Secret>,
+
+    #[structopt(
+        long,
+        help = "In check mode, exit with code 2 when there are changes to push to Netshot"
+    )]
+    fail_on_drift: bool,
+
+    #[structopt(
+        long,
+        help = "The number of parallel requests to use when pushing changes to Netshot",
+        default_value = "4",
+        env
+    )]
+    concurrency: usize,
+
+    #[structopt(
+        long,
+        help = "The number of devices to register per bulk request, 1 to register them one by one",
+        default_value = "50",
+        env
+    )]
+    register_batch_size: usize,
+
+    #[structopt(
+        long,
+        help = "Schedule a Netshot snapshot of every newly registered device"
+    )]
+    snapshot_on_register: bool,
+
+    #[structopt(
+        long,
+        help = "Wait for the snapshots of --snapshot-on-register to complete and report their outcome",
+        requires = "snapshot-on-register"
+    )]
+    wait_snapshots: bool,
+
+    #[structopt(
+        long,
+        help = "How long to wait for all the snapshots with --wait-snapshots, in seconds",
+        default_value = "300",
+        env
+    )]
+    snapshot_timeout_secs: u64,
+
+    #[structopt(
+        long,
+        help = "Run as a daemon, synchronizing again every given number of seconds",
+        env
+    )]
+    interval_secs: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "The maximum random delay in seconds added to each daemon interval",
+        default_value = "0",
+        env
+    )]
+    jitter_secs: u64,
+
+    #[structopt(
+        long,
+        help = "Cache the Netshot inventory in the given file, reused between runs within --netshot-cache-ttl-secs",
+        env
+    )]
+    netshot_cache: Option<String>,
+
+    #[structopt(
+        long,
+        help = "How long the cached Netshot inventory is reused, in seconds",
+        default_value = "3600",
+        env
+    )]
+    netshot_cache_ttl_secs: u64,
+
+    #[structopt(long, help = "Discard the cached Netshot inventory and fetch it again")]
+    no_cache: bool,
+
+    #[structopt(
+        long,
+        help = "Register the IPs listed in the given file (one per line) instead of synchronizing with Netbox",
+        env,
+        conflicts_with = "interval-secs"
+    )]
+    register_from_file: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Abort before pushing any change when more devices than this are to be disabled",
+        env
+    )]
+    max_disable: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Abort before pushing any change when more than this percentage of the Netshot devices are to be disabled",
+        env
+    )]
+    max_disable_percent: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Disable the devices even when above --max-disable or --max-disable-percent"
+    )]
+    force: bool,
+
+    #[structopt(
+        long,
+        help = "Do not disable any device when Netbox returns fewer devices than this",
+        default_value = "1",
+        env
+    )]
+    min_netbox_devices: usize,
+
+    #[structopt(
+        long,
+        help = "What to do with the Netshot devices missing on Netbox: disable or delete",
+        default_value = "disable",
+        env
+    )]
+    on_missing: OnMissing,
+
+    #[structopt(
+        long,
+        help = "Never disable (or delete) the devices missing on Netbox, only report them"
+    )]
+    no_disable: bool,
+
+    #[structopt(
+        long,
+        help = "Never register the devices missing on Netshot, only report them"
+    )]
+    no_register: bool,
+
+    #[structopt(
+        long,
+        help = "Enable the devices disabled on Netshot that are present again on Netbox"
+    )]
+    reenable: bool,
 
-mod common;
-mod rest;
+    #[structopt(
+        long,
+        help = "Ignore the Netshot devices that belong to other domains than the configured ones"
+    )]
+    scope_to_domain: bool,
 
-#[derive(Debug, StructOpt, Clone)]
-#[structopt(
-    name = "netbox2netshot",
-    about = "Synchronization tool between netbox and netshot"
-)]
-struct Opt {
-    #[structopt(short, long, help = "Enable debug/verbose mode")]
-    debug: bool,
+    #[structopt(
+        long,
+        help = "Copy the Netbox tag slugs, comma-separated, into the comments of the registered devices"
+    )]
+    copy_tags: bool,
 
-    #[structopt(long, help = "The directory to log to", default_value = "logs", env)]
-    log_directory: String,
+    #[structopt(
+        long,
+        help = "Name the registered devices after this template of the Netbox {name}, {site} and {tenant}, e.g. {site}-{name}",
+        env
+    )]
+    register_name_template: Option<NameTemplate>,
 
-    #[structopt(long, help = "The Netshot API URL", env)]
-    netshot_url: String,
+    #[structopt(
+        long,
+        help = "Rename the Netshot devices named differently than the Netbox device with the same IP"
+    )]
+    update_names: bool,
 
     #[structopt(
         long,
-        help = "The TLS certificate to use to authenticate to Netshot (PKCS12 format)",
+        help = "Push at most this many changes per run, deferring the others to the next runs",
         env
     )]
-    netshot_tls_client_certificate: Option<String>,
+    limit: Option<usize>,
 
-    #[structopt(long, help = "The optional password for the netshot PKCS12 file", env)]
-    netshot_tls_client_certificate_password: Option<String>,
+    #[structopt(
+        long,
+        help = "Stop starting new changes once the run has lasted this many seconds, deferring the others to the next runs",
+        env
+    )]
+    run_timeout_secs: Option<u64>,
 
-    #[structopt(long, help = "The Netshot token", env, hide_env_values = true)]
-    netshot_token: String,
+    #[structopt(
+        long,
+        help = "Stop pushing changes after the first failed one",
+        conflicts_with = "ignore-write-errors"
+    )]
+    fail_fast: bool,
 
-    #[structopt(long, help = "The domain ID to use when importing a new device", env)]
-    netshot_domain_id: u32,
+    #[structopt(
+        long,
+        help = "Exit successfully even when some changes failed to be pushed"
+    )]
+    ignore_write_errors: bool,
 
-    #[structopt(long, help = "HTTP(s) proxy to use to connect to Netshot", env)]
-    netshot_proxy: Option<String>,
+    #[structopt(
+        long,
+        help = "List the changes and ask for a confirmation before pushing them",
+        conflicts_with = "interval-secs"
+    )]
+    interactive: bool,
 
-    #[structopt(long, help = "The Netbox API URL", env)]
-    netbox_url: String,
+    #[structopt(
+        short,
+        long,
+        help = "Confirm the changes of --interactive without prompting, required outside of a terminal"
+    )]
+    yes: bool,
+}
 
+#[derive(Debug, StructOpt, Clone)]
+struct DiffOpt {
     #[structopt(
         long,
-        help = "The TLS certificate to use to authenticate to Netbox (PKCS12 format)",
-        env
+        help = "Match the devices whose IP differs between Netbox and Netshot by hostname"
     )]
-    netbox_tls_client_certificate: Option<String>,
+    match_by_name: bool,
 
-    #[structopt(long, help = "The optional password for the netbox PKCS12 file", env)]
-    netbox_tls_client_certificate_password: Option<String>,
+    #[structopt(
+        long,
+        help = "List the devices disabled on Netshot that are present again on Netbox"
+    )]
+    reenable: bool,
 
-    #[structopt(long, help = "The Netbox token", env, hide_env_values = true)]
-    netbox_token: Option<String>,
+    #[structopt(
+        long,
+        help = "Ignore the Netshot devices that belong to other domains than the configured ones"
+    )]
+    scope_to_domain: bool,
+}
 
+#[derive(Debug, StructOpt, Clone)]
+struct ExportOpt {
     #[structopt(
+        short,
         long,
-        default_value = "",
-        help = "The querystring to use to select the devices from netbox",
-        env
+        help = "Write the inventories to the given path instead of the standard output"
     )]
-    netbox_devices_filter: String,
+    output: Option<String>,
 
     #[structopt(
         long,
-        help = "The querystring to use to select the VM from netbox",
-        env
+        help = "raw (full API objects as JSON), json or csv (simplified inventories side by side)",
+        default_value = "raw"
     )]
-    netbox_vms_filter: Option<String>,
+    format: ExportFormat,
+}
 
-    #[structopt(long, help = "HTTP(s) proxy to use to connect to Netbox", env)]
-    netbox_proxy: Option<String>,
+/// The output format of the `export` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Raw,
+    Json,
+    Csv,
+}
 
-    #[structopt(short, long, help = "Check mode, will not push any change to Netshot")]
-    check: bool,
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "raw" => Ok(ExportFormat::Raw),
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(anyhow!(
+                "Invalid export format {}, expected raw, json or csv",
+                value
+            )),
+        }
+    }
 }
 
+#[derive(Debug, StructOpt, Clone)]
+struct ListOpt {
+    #[structopt(long, help = "Print the list as JSON instead of a table")]
+    json: bool,
+}
+
+/// The crate version with the git commit and the build date, set by the build script
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("NETBOX2NETSHOT_GIT_COMMIT"),
+    " ",
+    env!("NETBOX2NETSHOT_BUILD_DATE"),
+    ")"
+);
+
+/// Exit code of a successful run
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code of a run that failed with an error
+const EXIT_FAILURE: i32 = 1;
+/// Exit code of a check mode run that found drift with `--fail-on-drift`
+const EXIT_DRIFT: i32 = 2;
+/// Exit code of a run where some changes failed to be pushed to Netshot
+const EXIT_WRITE_FAILURE: i32 = 3;
+/// Exit code of a run stopped by `--run-timeout-secs` before pushing every change
+const EXIT_TIMEOUT: i32 = 4;
+
 /// Main application entrypoint
-fn main() -> Result<(), Error> {
-    let opt: Opt = Opt::from_args();
+fn main() {
+    let code = match run() {
+        Ok(code) => code,
+        Err(error) => {
+            log::error!("{:#}", error);
+            eprintln!("Error: {:?}", error);
+            EXIT_FAILURE
+        }
+    };
+    std::process::exit(code);
+}
+
+/// The rate limiter shared by all the HTTP clients of the process, none without `--max-rps`
+fn rate_limiter(opt: &Opt) -> Option<Arc<RateLimiter>> {
+    opt.max_rps
+        .filter(|max_rps| *max_rps > 0)
+        .map(|max_rps| Arc::new(RateLimiter::new(max_rps)))
+}
+
+/// The retry policy of the HTTP clients, all of them sharing the given rate limiter
+fn retry_policy(opt: &Opt, rate_limiter: Option<&Arc<RateLimiter>>) -> RetryPolicy {
+    RetryPolicy::new(opt.http_retries, opt.http_retry_base_ms, opt.retry_writes)
+        .with_max_rate_wait(opt.max_rate_wait_secs)
+        .with_rate_limiter(rate_limiter.cloned())
+}
+
+/// Explain a failed ping, naming the system and the URL tried along with the likely causes
+fn check_ping(system: &str, url: &str, ping: ApiResult<()>) -> Result<(), Error> {
+    ping.map_err(|error| {
+        anyhow!(
+            "Unable to reach {} at {}: {} ({})",
+            system,
+            url,
+            error,
+            error.hint()
+        )
+    })
+}
+
+/// Connect to every Netbox source and to Netshot, pinging Netshot while the Netbox sources are
+/// pinged one after the other
+fn clients(
+    opt: &Opt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<(Vec<netbox::NetboxClient>, netshot::NetshotClient), Error> {
+    let netbox_clients = build_netbox_clients(opt, rate_limiter)?;
+    let netshot_client = build_netshot_client(opt, rate_limiter)?;
+    let (netbox_pings, netshot_ping) = thread::scope(|scope| {
+        let netshot_ping = scope.spawn(|| netshot_client.ping());
+        let netbox_pings: Vec<ApiResult<()>> = netbox_clients
+            .iter()
+            .map(|netbox_client| netbox_client.ping())
+            .collect();
+        (
+            netbox_pings,
+            netshot_ping.join().expect("the Netshot ping panicked"),
+        )
+    });
+    for (netbox_client, ping) in netbox_clients.iter().zip(netbox_pings) {
+        check_ping("Netbox", &netbox_client.url, ping)?;
+    }
+    check_ping("Netshot", &netshot_client.url, netshot_ping)?;
+    Ok((netbox_clients, netshot_client))
+}
+
+/// The URL and token of each Netbox source: the `--netbox-url` ones, paired by position with the
+/// `--netbox-token` ones (or the token file for the first one), then the configuration file ones
+fn netbox_sources(opt: &Opt) -> Result<Vec<(String, Option<Secret>)>, Error> {
+    if opt.netbox_token.len() > opt.netbox_url.len() {
+        return Err(anyhow!(
+            "{} Netbox tokens given for {} Netbox URLs, each --netbox-token must match a --netbox-url",
+            opt.netbox_token.len(),
+            opt.netbox_url.len()
+        ));
+    }
+    let mut sources = Vec::new();
+    for (index, url) in opt.netbox_url.iter().enumerate() {
+        let token = match (&opt.netbox_token_file, index) {
+            (Some(path), 0) => Some(secret::read_token(path)?),
+            _ => opt.netbox_token.get(index).cloned(),
+        };
+        sources.push((url.clone(), token));
+    }
+    for source in &opt.netbox_sources {
+        let token = match (&source.token_file, &source.token) {
+            (Some(path), _) => Some(secret::read_token(path)?),
+            (None, token) => token.clone(),
+        };
+        sources.push((source.url.clone(), token));
+    }
+    if sources.is_empty() {
+        return Err(anyhow!("The Netbox URL (--netbox-url) is required"));
+    }
+    Ok(sources)
+}
+
+/// Create the clients of the Netbox sources, without sending any request
+fn build_netbox_clients(
+    opt: &Opt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<Vec<netbox::NetboxClient>, Error> {
+    let netbox_options = HttpClientOptions {
+        proxy: opt.netbox_proxy.clone(),
+        tls_client_certificate: opt.netbox_tls_client_certificate.clone(),
+        tls_client_certificate_password: opt.netbox_tls_client_certificate_password.clone(),
+        tls_client_certificate_pem: opt.netbox_tls_client_cert_pem.clone(),
+        tls_client_key_pem: opt.netbox_tls_client_key_pem.clone(),
+        ca_certificate: opt.netbox_ca_cert.clone(),
+        danger_accept_invalid_certs: opt.danger_insecure_tls,
+        compression: !opt.no_compression,
+        auth_scheme: opt.netbox_auth_scheme,
+        ..Default::default()
+    }
+    .timeouts(opt.http_connect_timeout_secs, opt.http_read_timeout_secs)
+    .user_agent(opt.user_agent.clone());
+    if opt.netbox_page_size == 0 {
+        return Err(anyhow!("The Netbox page size must be positive"));
+    }
+    if opt.netbox_graphql && opt.netbox_ip_field == netbox::IpField::PrimaryIp {
+        return Err(anyhow!(
+            "The Netbox GraphQL API doesn't expose primary_ip, use primary_ip4 or primary_ip6"
+        ));
+    }
+    if opt.netbox_graphql && opt.since.is_some() {
+        return Err(anyhow!(
+            "--since relies on the last_updated REST filter, it can't be used with --netbox-graphql"
+        ));
+    }
+    if opt.netbox_graphql && !(opt.netbox_role.is_empty() && opt.netbox_device_type.is_empty()) {
+        return Err(anyhow!(
+            "--netbox-role and --netbox-device-type are REST filters, they can't be used with --netbox-graphql"
+        ));
+    }
+    netbox_sources(opt)?
+        .into_iter()
+        .map(|(url, token)| {
+            let token = token.map(|token| token.expose().to_string());
+            Ok(netbox::NetboxClient::new(url, token, &netbox_options)?
+                .with_retry_policy(retry_policy(opt, rate_limiter))
+                .with_page_size(opt.netbox_page_size)
+                .with_cursor_pagination(opt.netbox_cursor_pagination)
+                .with_progress_every(opt.progress_every))
+        })
+        .collect()
+}
+
+/// Connect to Netshot
+fn netshot_client(
+    opt: &Opt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<netshot::NetshotClient, Error> {
+    let netshot_client = build_netshot_client(opt, rate_limiter)?;
+    check_ping("Netshot", &netshot_client.url, netshot_client.ping())?;
+    Ok(netshot_client)
+}
+
+/// Create the Netshot client, without sending any request
+fn build_netshot_client(
+    opt: &Opt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<netshot::NetshotClient, Error> {
+    let netshot_token = match (&opt.netshot_token_file, &opt.netshot_token) {
+        (Some(path), _) => secret::read_token(path)?,
+        (None, Some(token)) => token.clone(),
+        (None, None) => return Err(anyhow!("The Netshot token is required")),
+    };
+    let netshot_options = HttpClientOptions {
+        proxy: opt.netshot_proxy.clone(),
+        tls_client_certificate: opt.netshot_tls_client_certificate.clone(),
+        tls_client_certificate_password: opt.netshot_tls_client_certificate_password.clone(),
+        tls_client_certificate_pem: opt.netshot_tls_client_cert_pem.clone(),
+        tls_client_key_pem: opt.netshot_tls_client_key_pem.clone(),
+        ca_certificate: opt.netshot_ca_cert.clone(),
+        danger_accept_invalid_certs: opt.danger_insecure_tls,
+        compression: !opt.no_compression,
+        auth_scheme: opt.netshot_auth_scheme,
+        ..Default::default()
+    }
+    .timeouts(opt.http_connect_timeout_secs, opt.http_read_timeout_secs)
+    .user_agent(opt.user_agent.clone());
+    let netshot_client = netshot::NetshotClient::new(
+        opt.netshot_url.clone(),
+        netshot_token.expose().to_string(),
+        &netshot_options,
+    )?
+    .with_retry_policy(retry_policy(opt, rate_limiter))
+    .with_api_base(&opt.netshot_api_base);
+    netshot_client.check_api_url()?;
+    Ok(netshot_client)
+}
+
+/// Resolve `--netshot-domain-name` to a domain ID when no `--netshot-domain-id` is given, then
+/// check that every configured domain exists, rather than failing on each registration
+fn resolve_domains(opt: &mut Opt, rate_limiter: Option<&Arc<RateLimiter>>) -> Result<(), Error> {
+    if opt.netshot_domain_id.is_none() && opt.netshot_domain_name.is_none() {
+        return Ok(());
+    }
+    let domains = build_netshot_client(opt, rate_limiter)?.get_domains()?;
+
+    match (opt.netshot_domain_id, &opt.netshot_domain_name) {
+        (None, Some(name)) => {
+            let domain_id = netshot::find_domain_id(&domains, name)?;
+            log::info!("Resolved the Netshot domain {} to ID {}", name, domain_id);
+            opt.netshot_domain_id = Some(domain_id);
+        }
+        (Some(domain_id), Some(name)) => {
+            log::info!(
+                "Using the Netshot domain ID {} rather than the domain name {}",
+                domain_id,
+                name
+            );
+        }
+        (_, None) => {}
+    }
+
+    let configured = SyncConfig {
+        netshot_domain_id: opt.netshot_domain_id.unwrap_or_default(),
+        site_domains: opt.site_domains.clone(),
+        tenant_domains: opt.tenant_domains.clone(),
+        ..Default::default()
+    };
+    let domain_ids: Vec<u32> = configured.domains().into_iter().collect();
+    netshot::check_domain_ids(&domains, &domain_ids)
+}
+
+/// Reject the malformed Netbox querystring filters and warn about the suspicious ones,
+/// the GraphQL arguments being left to Netbox
+fn check_netbox_filters(opt: &Opt) -> Result<(), Error> {
+    if opt.netbox_graphql {
+        return Ok(());
+    }
+    for filter in opt
+        .netbox_devices_filter
+        .iter()
+        .chain(&opt.netbox_vms_filter)
+    {
+        let warnings = netbox::check_filter(filter)
+            .with_context(|| format!("Invalid Netbox filter \"{}\"", filter))?;
+        for warning in warnings {
+            log::warn!("Netbox filter \"{}\": {}", filter, warning);
+        }
+    }
+    Ok(())
+}
+
+/// The synchronization parameters shared by every subcommand, `--since` being resolved against
+/// the current time
+fn sync_config(opt: &Opt) -> Result<SyncConfig, Error> {
+    let since = match &opt.since {
+        Some(since) => {
+            let timestamp = since.timestamp()?;
+            log::info!(
+                "Incremental run, only fetching the Netbox objects updated since {}",
+                timestamp
+            );
+            Some(timestamp)
+        }
+        None => None,
+    };
+    let offline_statuses = match (opt.disable_offline, opt.offline_status.is_empty()) {
+        (false, _) => Vec::new(),
+        (true, true) => netbox::DEFAULT_OFFLINE_STATUSES
+            .iter()
+            .map(|status| status.to_string())
+            .collect(),
+        (true, false) => opt.offline_status.clone(),
+    };
+    let statuses = if offline_statuses.is_empty() {
+        opt.netbox_status.clone()
+    } else {
+        netbox::with_offline_statuses(&opt.netbox_status, &offline_statuses)
+    };
+    let filter = |filter: &String| {
+        let filter = netbox::with_status_filter(filter, &statuses, opt.netbox_graphql);
+        match &since {
+            Some(timestamp) => netbox::with_since_filter(&filter, timestamp),
+            None => filter,
+        }
+    };
+    Ok(SyncConfig {
+        netshot_domain_id: opt
+            .netshot_domain_id
+            .expect("the Netshot domain name is resolved at startup"),
+        netshot_group_id: opt.netshot_group_id,
+        netbox_devices_filters: opt
+            .netbox_devices_filter
+            .iter()
+            .map(|devices_filter| {
+                filter(&netbox::with_device_selectors(
+                    devices_filter,
+                    &opt.netbox_role,
+                    &opt.netbox_device_type,
+                ))
+            })
+            .collect(),
+        netbox_vms_filter: opt.netbox_vms_filter.as_ref().map(filter),
+        netbox_graphql: opt.netbox_graphql,
+        netbox_ip_field: opt.netbox_ip_field,
+        incremental: since.is_some(),
+        offline_statuses,
+        progress_every: opt.progress_every,
+        site_domains: opt.site_domains.clone(),
+        tenant_domains: opt.tenant_domains.clone(),
+        platform_drivers: opt.platform_drivers.clone(),
+        hostname_normalizer: HostnameNormalizer {
+            strip_suffixes: opt.hostname_strip_suffix.clone(),
+            regex: opt.hostname_regex.clone(),
+        },
+        ip_filter: IpFilter {
+            exclude_ips: opt.exclude_ip.clone(),
+            exclude_subnets: opt.exclude_subnet.clone(),
+            only_subnets: opt.only_subnet.clone(),
+        },
+        ..Default::default()
+    })
+}
+
+/// The on-disk cache of the Netshot inventory, if enabled
+fn netshot_cache(sync_opt: &SyncOpt) -> Option<DiskCache> {
+    sync_opt
+        .netshot_cache
+        .as_ref()
+        .map(|path| DiskCache::new(path, Duration::from_secs(sync_opt.netshot_cache_ttl_secs)))
+}
+
+/// Connect to both systems and run the synchronization
+fn sync(
+    opt: &Opt,
+    sync_opt: &SyncOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+    etag_cache: &Arc<netshot::EtagCache>,
+) -> Result<SyncOutcome, Error> {
+    let deadline = sync_opt
+        .run_timeout_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let (netbox_clients, netshot_client) = clients(opt, rate_limiter)?;
+    let netshot_client = netshot_client.with_etag_cache(Arc::clone(etag_cache));
+    // A daemon cycle is skipped when neither inventory changed since the one of the state file
+    let previous_hashes = match (&sync_opt.interval_secs, &sync_opt.state_file) {
+        (Some(_), Some(path)) => RunState::load(path).hashes,
+        _ => None,
+    };
+
+    let sync_config = SyncConfig {
+        check: sync_opt.check,
+        concurrency: sync_opt.concurrency,
+        register_batch_size: sync_opt.register_batch_size,
+        match_by_name: sync_opt.match_by_name,
+        snapshot_on_register: sync_opt.snapshot_on_register,
+        wait_snapshots: Some(Duration::from_secs(sync_opt.snapshot_timeout_secs))
+            .filter(|_| sync_opt.wait_snapshots),
+        max_disable: sync_opt.max_disable,
+        max_disable_percent: sync_opt.max_disable_percent,
+        force: sync_opt.force,
+        min_netbox_devices: sync_opt.min_netbox_devices,
+        reenable: sync_opt.reenable,
+        scope_to_domain: sync_opt.scope_to_domain,
+        copy_tags: sync_opt.copy_tags,
+        register_name_template: sync_opt.register_name_template.clone(),
+        update_names: sync_opt.update_names,
+        on_missing: sync_opt.on_missing,
+        no_disable: sync_opt.no_disable,
+        no_register: sync_opt.no_register,
+        fail_fast: sync_opt.fail_fast,
+        limit: sync_opt.limit,
+        deadline,
+        netshot_cache: netshot_cache(sync_opt),
+        previous_hashes,
+        ..sync_config(opt)?
+    };
+    let diff = netbox2netshot::run_diff(&sync_config, &netbox_clients, &netshot_client)?;
+    if sync_opt.interactive && !sync_opt.check && diff.has_changes() {
+        confirm_changes(&diff, &sync_config, sync_opt.yes)?;
+    }
+    netbox2netshot::apply_diff(&sync_config, diff, &netshot_client)
+}
+
+/// Print the changes about to be pushed and ask for a confirmation, unless already given with `--yes`
+fn confirm_changes(diff: &Diff, config: &SyncConfig, yes: bool) -> Result<(), Error> {
+    if yes {
+        log::info!("Changes confirmed with --yes");
+        return Ok(());
+    }
+    if !prompt::is_interactive() {
+        return Err(anyhow!(
+            "Not running in a terminal, refusing to push the changes without --yes"
+        ));
+    }
+
+    let missing = format!("{}d", config.on_missing.action());
+    let lists = [
+        ("registered", &diff.devices_to_register, config.no_register),
+        (
+            missing.as_str(),
+            &diff.devices_to_disable,
+            config.no_disable,
+        ),
+        ("enabled", &diff.devices_to_enable, false),
+    ];
+    for (action, devices, skipped) in lists.iter() {
+        if devices.is_empty() || *skipped {
+            continue;
+        }
+        println!("{} devices are about to be {}:", devices.len(), action);
+        for device in devices.iter() {
+            println!("  {}", device);
+        }
+    }
+    if !diff.devices_to_rename.is_empty() {
+        println!(
+            "{} devices are about to be renamed:",
+            diff.devices_to_rename.len()
+        );
+        for rename in &diff.devices_to_rename {
+            println!("  {}", rename);
+        }
+    }
+
+    let stdin = std::io::stdin();
+    if !prompt::confirm("Proceed?", &mut stdin.lock(), &mut std::io::stdout())? {
+        return Err(anyhow!(
+            "Changes not confirmed, nothing was pushed to Netshot"
+        ));
+    }
+    Ok(())
+}
+
+/// Register the IPs listed in the given file on Netshot, without looking at Netbox
+fn register_from_file(
+    opt: &Opt,
+    sync_opt: &SyncOpt,
+    path: &str,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<RegistrationOutcome, Error> {
+    let ips = common::read_ip_list(path)?;
+    log::info!("Read {} IPs from {}", ips.len(), path);
+
+    let netshot_client = netshot_client(opt, rate_limiter)?;
+
+    let sync_config = SyncConfig {
+        check: sync_opt.check,
+        concurrency: sync_opt.concurrency,
+        ..sync_config(opt)?
+    };
+    netbox2netshot::register_from_list(&sync_config, &netshot_client, ips)
+}
+
+/// Run the application, returning the process exit code
+fn run() -> Result<i32, Error> {
+    // Handled before parsing, the template being needed before the mandatory options are known
+    if std::env::args().any(|arg| arg == "--generate-config") {
+        print!("{}", config::TEMPLATE);
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let file_config = match config::find_config_path(std::env::args()) {
+        Some(path) => {
+            let file_config = FileConfig::load(&path)?;
+            file_config.apply_to_env()?;
+            Some(file_config)
+        }
+        None => None,
+    };
+
+    let mut opt: Opt = Opt::from_args();
+    let mut command = match opt.command.take() {
+        Some(command) => command,
+        None => Command::Sync(SyncOpt::from_iter(&["sync"])),
+    };
+    if let Some(file_config) = &file_config {
+        opt.debug |= file_config.debug;
+        opt.quiet |= file_config.quiet;
+        opt.no_color |= file_config.no_color;
+        opt.retry_writes |= file_config.retry_writes;
+        opt.netbox_graphql |= file_config.netbox_graphql;
+        opt.netbox_cursor_pagination |= file_config.netbox_cursor_pagination;
+        opt.danger_insecure_tls |= file_config.danger_insecure_tls;
+        opt.no_compression |= file_config.no_compression;
+        opt.disable_offline |= file_config.disable_offline;
+        opt.site_domains = file_config.site_domains.clone();
+        opt.netbox_sources = file_config.netbox_sources.clone();
+        opt.tenant_domains = file_config.tenant_domains.clone();
+        opt.platform_drivers = file_config.platform_drivers.clone();
+        if opt.netbox_status.is_empty() {
+            opt.netbox_status = file_config.netbox_status.clone();
+        }
+        if opt.netbox_role.is_empty() {
+            opt.netbox_role = file_config.netbox_role.clone();
+        }
+        if opt.netbox_device_type.is_empty() {
+            opt.netbox_device_type = file_config.netbox_device_type.clone();
+        }
+        if opt.offline_status.is_empty() {
+            opt.offline_status = file_config.offline_status.clone();
+        }
+        if opt.hostname_strip_suffix.is_empty() {
+            opt.hostname_strip_suffix = file_config.hostname_strip_suffix.clone();
+        }
+        if opt.exclude_ip.is_empty() {
+            opt.exclude_ip = file_config.exclude_ip.clone();
+        }
+        if opt.exclude_subnet.is_empty() {
+            opt.exclude_subnet = file_config.exclude_subnet.clone();
+        }
+        if opt.only_subnet.is_empty() {
+            opt.only_subnet = file_config.only_subnet.clone();
+        }
+        match &mut command {
+            Command::Sync(sync_opt) => {
+                sync_opt.check |= file_config.check;
+                sync_opt.match_by_name |= file_config.match_by_name;
+                sync_opt.fail_on_drift |= file_config.fail_on_drift;
+                sync_opt.snapshot_on_register |= file_config.snapshot_on_register;
+                sync_opt.wait_snapshots |= file_config.wait_snapshots;
+                sync_opt.force |= file_config.force;
+                sync_opt.interactive |= file_config.interactive;
+                sync_opt.yes |= file_config.yes;
+                sync_opt.reenable |= file_config.reenable;
+                sync_opt.scope_to_domain |= file_config.scope_to_domain;
+                sync_opt.copy_tags |= file_config.copy_tags;
+                sync_opt.update_names |= file_config.update_names;
+                sync_opt.fail_fast |= file_config.fail_fast;
+                sync_opt.no_disable |= file_config.no_disable;
+                sync_opt.no_register |= file_config.no_register;
+                sync_opt.ignore_write_errors |= file_config.ignore_write_errors;
+                sync_opt.no_cache |= file_config.no_cache;
+            }
+            Command::Diff(diff_opt) => {
+                diff_opt.match_by_name |= file_config.match_by_name;
+                diff_opt.reenable |= file_config.reenable;
+                diff_opt.scope_to_domain |= file_config.scope_to_domain;
+            }
+            Command::Export(_)
+            | Command::Validate
+            | Command::ListDomains(_)
+            | Command::ListGroups(_) => {}
+        }
+    }
+    opt.command = Some(command.clone());
+
     let mut logging_level = "info";
     let mut duplicate_level = Duplicate::Info;
     if opt.debug {
         logging_level = "debug";
         duplicate_level = Duplicate::Debug;
     }
+    // The directives already select what is logged, the console gets all of it
+    if let Some(log_spec) = &opt.log_spec {
+        logging_level = log_spec;
+        duplicate_level = Duplicate::All;
+    }
+    if opt.quiet {
+        duplicate_level = Duplicate::None;
+    }
+    let no_color = opt.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
 
-    Logger::try_with_str(logging_level)?
-        .log_to_file(FileSpec::default().directory(opt.clone().log_directory))
-        .duplicate_to_stdout(duplicate_level)
-        .start()
-        .unwrap();
+    // The diff and export subcommands print their result on stdout, keep it free of logs
+    let logger = Logger::try_with_str(logging_level)?
+        .log_to_file(FileSpec::default().directory(opt.clone().log_directory));
+    let logger = match opt.log_max_size_mb {
+        Some(max_size_mb) => logger.rotate(
+            Criterion::Size(max_size_mb.max(1) * 1024 * 1024),
+            Naming::Numbers,
+            Cleanup::KeepLogFiles(opt.log_keep_files),
+        ),
+        None => logger,
+    };
+    let logger = match command {
+        Command::Sync(_) => logger.duplicate_to_stdout(duplicate_level),
+        _ => logger.duplicate_to_stderr(duplicate_level),
+    };
+    let logger = if opt.log_format == LogFormat::Json {
+        logger.format(logging::json_format)
+    } else if no_color {
+        logger
+            .format_for_stdout(flexi_logger::default_format)
+            .format_for_stderr(flexi_logger::default_format)
+    } else {
+        logger
+    };
+    logger.start().unwrap();
 
     log::info!("Logger initialized with level {}", logging_level);
+    log::info!("netbox2netshot {}", VERSION);
+    if let Some(path) = &opt.config {
+        log::info!("Using configuration file {}", path);
+    }
     log::debug!("CLI Parameters : {:#?}", opt);
+    if opt.danger_insecure_tls {
+        log::warn!("**************************************************************************");
+        log::warn!("* --danger-insecure-tls is enabled: TLS certificates are NOT verified,   *");
+        log::warn!("* anyone on the network path can intercept the API tokens. Lab use only. *");
+        log::warn!("**************************************************************************");
+    }
 
-    let netbox_client = netbox::NetboxClient::new(
-        opt.netbox_url,
-        opt.netbox_token,
-        opt.netbox_proxy,
-        opt.netbox_tls_client_certificate,
-        opt.netbox_tls_client_certificate_password,
-    )?;
-    netbox_client.ping()?;
+    // Every client of the process shares the same --max-rps budget
+    let rate_limiter = rate_limiter(&opt);
 
-    let netshot_client = netshot::NetshotClient::new(
-        opt.netshot_url,
-        opt.netshot_token,
-        opt.netshot_proxy,
-        opt.netshot_tls_client_certificate,
-        opt.netshot_tls_client_certificate_password,
-    )?;
-    netshot_client.ping()?;
-
-    log::info!("Getting devices list from Netshot");
-    let netshot_devices = netshot_client.get_devices(opt.netshot_domain_id)?;
-
-    let netshot_disabled_devices: Vec<&netshot::Device> = netshot_devices
-        .iter()
-        .filter(|dev| &dev.status == "DISABLED")
-        .collect();
+    // Listing the domains or validating them must not depend on a resolvable domain name
+    match &command {
+        Command::Validate | Command::ListDomains(_) | Command::ListGroups(_) => {}
+        _ => {
+            check_netbox_filters(&opt)?;
+            resolve_domains(&mut opt, rate_limiter.as_ref())?
+        }
+    }
 
-    log::debug!("Building netshot devices simplified inventory");
-    let netshot_simplified_inventory: HashMap<&String, &String> = netshot_devices
-        .iter()
-        .map(|dev| (&dev.management_address.ip, &dev.name))
-        .collect();
+    let rate_limiter = rate_limiter.as_ref();
+    match &command {
+        Command::Sync(sync_opt) => run_sync_command(&opt, sync_opt, rate_limiter),
+        Command::Diff(diff_opt) => run_diff_command(&opt, diff_opt, rate_limiter),
+        Command::Export(export_opt) => run_export_command(&opt, export_opt, rate_limiter),
+        Command::Validate => run_validate_command(&opt, rate_limiter),
+        Command::ListDomains(list_opt) => run_list_domains_command(&opt, list_opt, rate_limiter),
+        Command::ListGroups(list_opt) => run_list_groups_command(&opt, list_opt, rate_limiter),
+    }
+}
 
-    log::info!("Getting devices list from Netbox");
-    let mut netbox_devices = netbox_client.get_devices(&opt.netbox_devices_filter)?;
+/// The `sync` subcommand: a single run, a daemon or a registration from a file
+fn run_sync_command(
+    opt: &Opt,
+    sync_opt: &SyncOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<i32, Error> {
+    if let Some(path) = &sync_opt.register_from_file {
+        let outcome = register_from_file(opt, sync_opt, path, rate_limiter)?;
+        println!(
+            "Added {} devices, skipped {} already on Netshot, {} failed",
+            outcome.added, outcome.skipped, outcome.failures
+        );
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let notifier = match &sync_opt.notify_webhook {
+        Some(url) => {
+            let options = HttpClientOptions::with_proxy(opt.netshot_proxy.clone())
+                .timeouts(opt.http_connect_timeout_secs, opt.http_read_timeout_secs)
+                .user_agent(opt.user_agent.clone());
+            Some(Notifier::new(url.expose().to_string(), &options)?)
+        }
+        None => None,
+    };
 
-    if opt.netbox_vms_filter.is_some() {
-        log::info!("Getting VMS list rom Netbox");
-        let mut vms = netbox_client.get_vms(&opt.netbox_vms_filter.unwrap())?;
-        log::debug!("Merging VMs and Devices lists");
-        netbox_devices.append(&mut vms);
+    if let (Some(cache), true) = (netshot_cache(sync_opt), sync_opt.no_cache) {
+        cache.invalidate()?;
+        log::info!("Discarded the cached Netshot inventory");
     }
 
-    log::debug!("Building netbox devices simplified inventory");
-    let netbox_simplified_devices: HashMap<_, _> = netbox_devices
-        .into_iter()
-        .filter_map(|device| match device.primary_ip4 {
-            Some(x) => Some((
-                x.address.split("/").next().unwrap().to_owned(),
-                device.name.unwrap_or(device.id.to_string()),
-            )),
-            None => {
-                log::warn!(
-                    "Device {} is missing its primary IP address, skipping it",
-                    device.name.unwrap_or(device.id.to_string())
-                );
-                None
-            }
-        })
-        .collect();
+    // Kept across the daemon runs, so that they revalidate the Netshot device lists
+    let etag_cache = Arc::new(netshot::EtagCache::default());
+
+    let interval_secs = match sync_opt.interval_secs {
+        Some(interval_secs) => interval_secs,
+        None => return run_once(opt, sync_opt, notifier.as_ref(), rate_limiter, &etag_cache),
+    };
 
-    log::debug!(
-        "Simplified inventories: Netbox({}), Netshot({})",
-        netbox_simplified_devices.len(),
-        netshot_simplified_inventory.len()
+    let shutdown = Shutdown::register()?;
+    log::info!(
+        "Running as a daemon, synchronizing every {}s (jitter up to {}s)",
+        interval_secs,
+        sync_opt.jitter_secs
     );
+    let mut iteration: u64 = 0;
+    while !shutdown.is_requested() {
+        iteration += 1;
+        log::info!("Starting synchronization run #{}", iteration);
+        match run_once(opt, sync_opt, notifier.as_ref(), rate_limiter, &etag_cache) {
+            Ok(_) => log::info!("Synchronization run #{} done", iteration),
+            Err(error) => log::error!("Synchronization run #{} failed: {:#}", iteration, error),
+        }
 
-    log::debug!("Comparing inventories");
+        let delay = daemon::next_delay(interval_secs, sync_opt.jitter_secs);
+        log::debug!("Next synchronization run in {:?}", delay);
+        shutdown.sleep(delay);
+    }
+    log::info!("Termination requested, exiting");
 
-    let mut devices_to_register: Vec<String> = Vec::new();
-    for (ip, hostname) in &netbox_simplified_devices {
-        match netshot_simplified_inventory.get(ip) {
-            Some(x) => log::debug!("{}({}) is present on both", x, ip),
-            None => {
-                log::debug!("{}({}) missing from Netshot", hostname, ip);
-                devices_to_register.push(ip.clone());
-            }
+    Ok(EXIT_SUCCESS)
+}
+
+/// Run a single synchronization and its outputs, returning the process exit code
+fn run_once(
+    opt: &Opt,
+    sync_opt: &SyncOpt,
+    notifier: Option<&Notifier>,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+    etag_cache: &Arc<netshot::EtagCache>,
+) -> Result<i32, Error> {
+    let start = Instant::now();
+    let mut result = sync(opt, sync_opt, rate_limiter, etag_cache);
+    if let Some(notifier) = notifier {
+        let notification = Notification::from_result(sync_opt.check, &result);
+        if let (Err(error), Ok(outcome)) = (notifier.notify(&notification), &mut result) {
+            outcome.add_error(ErrorEntry::new("notify", format!("{:#}", error)));
         }
     }
+    let mut outcome = result?;
+    log::info!("Total run took {:.2}s", start.elapsed().as_secs_f64());
 
-    let mut devices_to_disable: Vec<String> = Vec::new();
-    for (ip, hostname) in &netshot_simplified_inventory {
-        match netbox_simplified_devices.get(*ip) {
-            Some(x) => log::debug!("{}({}) is present on both", x, ip),
-            None => {
-                log::debug!("{}({}) to be disabled (missing on Netbox)", hostname, ip);
-                devices_to_disable.push(ip.to_string());
-            }
+    // A skipped cycle leaves the state of the previous one, its drift and hashes still holding
+    if let (Some(path), false) = (&sync_opt.state_file, outcome.report.skipped) {
+        // Only a run that pushed nothing can be skipped when the inventories stay the same
+        let hashes = Some(outcome.inventory_hashes.clone()).filter(|_| outcome.pushed_nothing());
+        let state = RunState::from_report(&outcome.report).with_hashes(hashes);
+        let delta = state.delta(&RunState::load(path));
+        delta.log();
+        outcome.report.since_last_run = Some(delta);
+        if let Err(error) = state.store(path) {
+            log::error!("Failed to write the state: {:#}", error);
+            outcome.add_error(ErrorEntry::new("state_file", format!("{:#}", error)));
         }
     }
 
-    let mut devices_to_enable: Vec<String> = Vec::new();
-    for device in &netshot_disabled_devices {
-        match netbox_simplified_devices.get(device.management_address.ip.as_str()) {
-            Some(_x) => {
-                log::debug!(
-                    "{}({}) to be enabled (present on Netbox)",
-                    device.name,
-                    device.management_address.ip
-                );
-                devices_to_enable.push(device.management_address.ip.clone());
-            }
-            None => {}
+    if let Some(path) = &sync_opt.report {
+        match outcome.report.write(path) {
+            Ok(()) => log::info!("Report written to {}", path),
+            Err(error) => log::error!("Failed to write the report: {:#}", error),
         }
     }
 
-    log::info!(
-        "Found {} devices missing on Netshot, to be added",
-        devices_to_register.len()
-    );
-    log::info!(
-        "Found {} devices missing on Netbox, to be disabled",
-        devices_to_disable.len()
-    );
-    log::info!(
-        "Found {} devices disabled on Netshot but present on Netbox, to be enabled",
-        devices_to_enable.len()
-    );
+    if let Some(path) = &sync_opt.csv {
+        match csv::write_actions_file(path, &outcome.actions()) {
+            Ok(()) => log::info!("CSV written to {}", path),
+            Err(error) => log::error!("Failed to write the CSV: {:#}", error),
+        }
+    }
+
+    if let Some(path) = &sync_opt.dry_run_diff {
+        match plan::write_plan_file(path, &outcome) {
+            Ok(()) => log::info!("Plan written to {}", path),
+            Err(error) => log::error!("Failed to write the plan: {:#}", error),
+        }
+    } else if sync_opt.check {
+        plan::write_plan(&mut std::io::stdout(), &outcome)?;
+    }
+
+    if let Some(path) = &sync_opt.metrics_file {
+        let metrics =
+            Metrics::from_report(&outcome.report, start.elapsed()).with_timings(&outcome.timings);
+        match metrics.write(path) {
+            Ok(()) => log::info!("Metrics written to {}", path),
+            Err(error) => log::error!("Failed to write the metrics: {:#}", error),
+        }
+    }
+
+    if !outcome.errors.is_empty() {
+        log::error!("{} errors during the run:", outcome.errors.len());
+        for error in &outcome.errors {
+            log::error!("  {}", error);
+        }
+    }
+
+    let write_failures = outcome.write_failures();
+    if !write_failures.is_empty() {
+        log::error!(
+            "{} changes failed to be pushed to Netshot",
+            write_failures.len()
+        );
+    }
+
+    if outcome.report.disable_blocked {
+        log::error!("The disable step was skipped, Netbox returned too few devices");
+        return Ok(EXIT_FAILURE);
+    }
+
+    if outcome.report.timed_out {
+        log::error!(
+            "Run timeout exceeded, {} changes deferred to the next runs",
+            outcome.report.counts.deferred
+        );
+        return Ok(EXIT_TIMEOUT);
+    }
+
+    if !write_failures.is_empty() && !sync_opt.ignore_write_errors {
+        return Ok(EXIT_WRITE_FAILURE);
+    }
+
+    if sync_opt.check && sync_opt.fail_on_drift && outcome.has_drift() {
+        log::warn!("Drift detected between Netbox and Netshot");
+        return Ok(EXIT_DRIFT);
+    }
+
+    Ok(EXIT_SUCCESS)
+}
+
+/// The `diff` subcommand: print the devices to register, disable and enable
+fn run_diff_command(
+    opt: &Opt,
+    diff_opt: &DiffOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<i32, Error> {
+    let (netbox_clients, netshot_client) = clients(opt, rate_limiter)?;
+
+    let sync_config = SyncConfig {
+        match_by_name: diff_opt.match_by_name,
+        reenable: diff_opt.reenable,
+        scope_to_domain: diff_opt.scope_to_domain,
+        ..sync_config(opt)?
+    };
+    let diff = netbox2netshot::run_diff(&sync_config, &netbox_clients, &netshot_client)?;
+
+    let lists = [
+        ("register", &diff.devices_to_register),
+        ("disable", &diff.devices_to_disable),
+        ("enable", &diff.devices_to_enable),
+    ];
+    for (action, devices) in lists.iter() {
+        println!("To {} ({}):", action, devices.len());
+        for device in devices.iter() {
+            println!("  {}", device);
+        }
+    }
+
+    Ok(EXIT_SUCCESS)
+}
 
-    if !opt.check {
-        for device in devices_to_register {
-            let registration = netshot_client.register_device(device, opt.netshot_domain_id);
-            if let Err(error) = registration {
-                log::warn!("Registration failure: {}", error);
+/// The `export` subcommand: dump the inventories of both systems as JSON
+fn run_export_command(
+    opt: &Opt,
+    export_opt: &ExportOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<i32, Error> {
+    let (netbox_clients, netshot_client) = clients(opt, rate_limiter)?;
+
+    let config = sync_config(opt)?;
+    let content = match export_opt.format {
+        ExportFormat::Raw => {
+            let inventories =
+                netbox2netshot::run_export(&config, &netbox_clients, &netshot_client)?;
+            serde_json::to_string_pretty(&inventories)?
+        }
+        ExportFormat::Json | ExportFormat::Csv => {
+            let rows =
+                netbox2netshot::run_simplified_export(&config, &netbox_clients, &netshot_client)?;
+            if export_opt.format == ExportFormat::Json {
+                serde_json::to_string_pretty(&rows)?
+            } else {
+                let mut output = Vec::new();
+                csv::write_inventory_rows(&mut output, &rows)?;
+                String::from_utf8(output)?.trim_end().to_string()
             }
         }
+    };
+
+    match &export_opt.output {
+        Some(path) => {
+            std::fs::write(path, content)
+                .with_context(|| format!("Unable to write the inventories to {}", path))?;
+            log::info!("Inventories written to {}", path);
+        }
+        None => println!("{}", content),
+    }
+
+    Ok(EXIT_SUCCESS)
+}
+
+/// Print the outcome of a `validate` check, returning whether it passed
+fn report_check(name: &str, result: Result<(), Error>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("[PASS] {}", name);
+            true
+        }
+        Err(error) => {
+            println!("[FAIL] {}: {:#}", name, error);
+            false
+        }
+    }
+}
 
-        for device in devices_to_disable {
-            let registration = netshot_client.disable_device(device);
-            if let Err(error) = registration {
-                log::warn!("Disable failure: {}", error);
+/// The `validate` subcommand: check both APIs and the configured domains, without any write
+fn run_validate_command(opt: &Opt, rate_limiter: Option<&Arc<RateLimiter>>) -> Result<i32, Error> {
+    let mut passed = report_check("Netbox filters", check_netbox_filters(opt));
+
+    match build_netbox_clients(opt, rate_limiter) {
+        Ok(netbox_clients) => {
+            passed &= report_check("Netbox client settings", Ok(()));
+            for netbox_client in &netbox_clients {
+                let ping = check_ping("Netbox", &netbox_client.url, netbox_client.ping());
+                passed &= report_check(&format!("Netbox API {}", netbox_client.url), ping);
             }
         }
-        for device in devices_to_enable {
-            let registration = netshot_client.enable_device(device);
-            if let Err(error) = registration {
-                log::warn!("Enable failure: {}", error);
+        Err(error) => passed &= report_check("Netbox client settings", Err(error)),
+    }
+
+    match build_netshot_client(opt, rate_limiter) {
+        Ok(netshot_client) => {
+            passed &= report_check("Netshot client settings", Ok(()));
+            let ping = check_ping("Netshot", &netshot_client.url, netshot_client.ping());
+            passed &= report_check("Netshot API", ping);
+            match netshot_client.get_domains() {
+                Ok(domains) => {
+                    passed &= report_check("Netshot domain listing", Ok(()));
+                    let mut opt = opt.clone();
+                    if let (None, Some(name)) =
+                        (opt.netshot_domain_id, opt.netshot_domain_name.clone())
+                    {
+                        let check = format!("Netshot domain name {}", name);
+                        match netshot::find_domain_id(&domains, &name) {
+                            Ok(domain_id) => {
+                                opt.netshot_domain_id = Some(domain_id);
+                                passed &= report_check(&check, Ok(()));
+                            }
+                            Err(error) => passed &= report_check(&check, Err(error)),
+                        }
+                    }
+                    // The domain mappings are only checked once the default domain is known
+                    if opt.netshot_domain_id.is_some() {
+                        for domain_id in sync_config(&opt)?.domains() {
+                            let exists = match domains.iter().find(|domain| domain.id == domain_id)
+                            {
+                                Some(_) => Ok(()),
+                                None => Err(anyhow!("no such domain on Netshot")),
+                            };
+                            passed &=
+                                report_check(&format!("Netshot domain {}", domain_id), exists);
+                        }
+                    }
+                }
+                Err(error) => passed &= report_check("Netshot domain listing", Err(error.into())),
             }
         }
+        Err(error) => passed &= report_check("Netshot client settings", Err(error)),
+    }
+
+    if passed {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(EXIT_FAILURE)
     }
-    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use flexi_logger::{AdaptiveFormat, Logger};
+/// Print id/name pairs as a table, or as JSON with `--json`
+fn print_id_names<T: Serialize>(
+    list_opt: &ListOpt,
+    items: &[T],
+    id_name: impl Fn(&T) -> (u32, &str),
+) -> Result<(), Error> {
+    if list_opt.json {
+        println!("{}", serde_json::to_string_pretty(items)?);
+        return Ok(());
+    }
 
-    #[ctor::ctor]
-    fn enable_logging() {
-        Logger::try_with_str("debug")
-            .unwrap()
-            .adaptive_format_for_stderr(AdaptiveFormat::Detailed);
+    println!("{:>6}  NAME", "ID");
+    for item in items {
+        let (id, name) = id_name(item);
+        println!("{:>6}  {}", id, name);
     }
+    Ok(())
+}
+
+/// The `list-domains` subcommand: print the domains defined in Netshot
+fn run_list_domains_command(
+    opt: &Opt,
+    list_opt: &ListOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<i32, Error> {
+    let netshot_client = netshot_client(opt, rate_limiter)?;
+    let domains = netshot_client.get_domains()?;
+    print_id_names(list_opt, &domains, |domain| (domain.id, &domain.name))?;
+    Ok(EXIT_SUCCESS)
+}
+
+/// The `list-groups` subcommand: print the device groups defined in Netshot
+fn run_list_groups_command(
+    opt: &Opt,
+    list_opt: &ListOpt,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> Result<i32, Error> {
+    let netshot_client = netshot_client(opt, rate_limiter)?;
+    let groups = netshot_client.get_groups()?;
+    print_id_names(list_opt, &groups, |group| (group.id, &group.name))?;
+    Ok(EXIT_SUCCESS)
 }
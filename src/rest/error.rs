@@ -0,0 +1,195 @@
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// The result of a Netbox/Netshot API call
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// The categorized failures of a Netbox/Netshot API call
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("authentication failed (HTTP {0})")]
+    Unauthorized(u16),
+    #[error("not found")]
+    NotFound,
+    #[error("rate limited")]
+    RateLimited,
+    #[error("server error (HTTP {0})")]
+    Server(u16),
+    #[error("unexpected response (HTTP {0})")]
+    Status(u16),
+    #[error("unable to decode the response: {0}")]
+    Decode(String),
+    #[error("rejected: {0}")]
+    Rejected(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    #[error("not attempted, an earlier change failed")]
+    Aborted,
+    #[error("not attempted, the run timeout was exceeded")]
+    Deferred,
+    #[error("request failed: {0}")]
+    Transport(#[source] reqwest::Error),
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            ApiError::Decode(error.to_string())
+        } else {
+            ApiError::Transport(error)
+        }
+    }
+}
+
+impl ApiError {
+    /// The error matching a response status, none when it is a success
+    pub fn from_status(status: StatusCode) -> Option<Self> {
+        if status.is_success() {
+            return None;
+        }
+        Some(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                ApiError::Unauthorized(status.as_u16())
+            }
+            StatusCode::NOT_FOUND => ApiError::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ApiError::RateLimited,
+            _ if status.is_server_error() => ApiError::Server(status.as_u16()),
+            _ => ApiError::Status(status.as_u16()),
+        })
+    }
+
+    /// Is the request worth a retry (rate limiting, server errors, connection issues and timeouts)
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::RateLimited | ApiError::Server(_) => true,
+            ApiError::Transport(error) => {
+                error.is_connect() || error.is_timeout() || error.is_request()
+            }
+            _ => false,
+        }
+    }
+
+    /// A short machine-readable name of the error kind, used in reports and notifications
+    pub fn category(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound => "not_found",
+            ApiError::RateLimited => "rate_limited",
+            ApiError::Server(_) => "server",
+            ApiError::Status(_) => "status",
+            ApiError::Decode(_) => "decode",
+            ApiError::Rejected(_) => "rejected",
+            ApiError::Timeout(_) => "timeout",
+            ApiError::Aborted => "aborted",
+            ApiError::Deferred => "deferred",
+            ApiError::Transport(_) => "transport",
+        }
+    }
+
+    /// The likely causes of the error, to help diagnosing a failed connection
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized(_) => {
+                "check the API token, its authentication scheme and its permissions"
+            }
+            ApiError::NotFound => {
+                "check the URL, it must be the base URL of the application without the /api path"
+            }
+            ApiError::RateLimited => "the API is rate limiting the requests, retry later",
+            ApiError::Server(_) => "the server is failing, check its logs and status",
+            ApiError::Status(_) | ApiError::Decode(_) => {
+                "check that the URL points to the right application and the proxy settings"
+            }
+            ApiError::Rejected(_) | ApiError::Aborted => "check the server logs",
+            ApiError::Deferred => "raise --run-timeout-secs or lower --limit",
+            ApiError::Timeout(_) => "the server didn't answer in time, check its load",
+            ApiError::Transport(error) => transport_hint(error),
+        }
+    }
+}
+
+/// The likely causes of a transport error, from the errors it was caused by
+fn transport_hint(error: &reqwest::Error) -> &'static str {
+    if error.is_timeout() {
+        return "the server didn't answer in time, check the firewalls and the HTTP timeouts";
+    }
+    let mut causes = Vec::new();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string().to_lowercase());
+        source = cause.source();
+    }
+    let caused_by = |pattern: &str| causes.iter().any(|cause| cause.contains(pattern));
+    if caused_by("dns error") || caused_by("lookup address") {
+        "the hostname can't be resolved, check the URL and the DNS configuration"
+    } else if caused_by("certificate") || caused_by("tls") || caused_by("ssl") {
+        "the TLS handshake failed, check the CA certificate, the client certificate and the URL scheme"
+    } else if caused_by("connection refused") {
+        "the connection was refused, check the URL port and that the server is running"
+    } else if error.is_builder() {
+        "check the URL, it must include the http:// or https:// scheme"
+    } else {
+        "check the URL, the proxy settings and that the server is reachable"
+    }
+}
+
+/// Turn an unsuccessful response into the matching error
+pub fn error_for_status(response: Response) -> ApiResult<Response> {
+    match ApiError::from_status(response.status()) {
+        Some(error) => Err(error),
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_mapping() {
+        assert!(ApiError::from_status(StatusCode::OK).is_none());
+        assert!(matches!(
+            ApiError::from_status(StatusCode::FORBIDDEN),
+            Some(ApiError::Unauthorized(403))
+        ));
+        assert!(matches!(
+            ApiError::from_status(StatusCode::NOT_FOUND),
+            Some(ApiError::NotFound)
+        ));
+        assert!(matches!(
+            ApiError::from_status(StatusCode::TOO_MANY_REQUESTS),
+            Some(ApiError::RateLimited)
+        ));
+        assert!(matches!(
+            ApiError::from_status(StatusCode::BAD_GATEWAY),
+            Some(ApiError::Server(502))
+        ));
+        assert!(matches!(
+            ApiError::from_status(StatusCode::BAD_REQUEST),
+            Some(ApiError::Status(400))
+        ));
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(ApiError::RateLimited.is_retryable());
+        assert!(ApiError::Server(503).is_retryable());
+        assert!(!ApiError::Unauthorized(401).is_retryable());
+        assert!(!ApiError::NotFound.is_retryable());
+        assert!(!ApiError::Decode(String::new()).is_retryable());
+        assert_eq!(ApiError::Unauthorized(401).category(), "unauthorized");
+    }
+
+    #[test]
+    fn transport_hints() {
+        let refused = reqwest::blocking::get("http://127.0.0.1:1/").unwrap_err();
+        assert!(ApiError::from(refused).hint().contains("refused"));
+
+        let unresolved = reqwest::blocking::get("http://netbox.invalid/").unwrap_err();
+        assert!(ApiError::from(unresolved).hint().contains("resolved"));
+
+        assert!(ApiError::NotFound.hint().contains("URL"));
+    }
+}
@@ -1,7 +1,34 @@
-use anyhow::Error;
+use anyhow::{anyhow, Context, Error};
 use reqwest::Identity;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
+
+/// How the API token is sent to the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// The native scheme of the API (`Authorization: Token` on Netbox, `X-Netshot-API-Token` on Netshot)
+    #[default]
+    Token,
+    /// `Authorization: Bearer`, expected by some API gateways
+    Bearer,
+}
+
+impl FromStr for AuthScheme {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "token" => Ok(AuthScheme::Token),
+            "bearer" => Ok(AuthScheme::Bearer),
+            _ => Err(anyhow!(
+                "Invalid authentication scheme {}, expected token or bearer",
+                value
+            )),
+        }
+    }
+}
 
 /// Create an identity from a private key and certificate registered in a PKCS12 file (with or without password)
 pub fn build_identity_from_file(
@@ -19,3 +46,20 @@ pub fn build_identity_from_file(
 
     Ok(identity)
 }
+
+/// Create an identity from a PEM certificate and a separate PEM (PKCS8) private key
+pub fn build_identity_from_pem(certificate: &str, key: &str) -> Result<Identity, Error> {
+    let certificate_pem = fs::read(certificate)
+        .with_context(|| format!("Unable to read the certificate {}", certificate))?;
+    let key_pem = fs::read(key).with_context(|| format!("Unable to read the key {}", key))?;
+
+    log::info!(
+        "Building identity from {} and {} PEM files",
+        certificate,
+        key
+    );
+    let identity = Identity::from_pkcs8_pem(&certificate_pem, &key_pem)
+        .with_context(|| format!("Invalid PEM certificate {} or key {}", certificate, key))?;
+
+    Ok(identity)
+}
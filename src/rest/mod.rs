@@ -1,3 +1,5 @@
+pub mod error;
 pub mod helpers;
 pub mod netbox;
+pub mod netbox_graphql;
 pub mod netshot;
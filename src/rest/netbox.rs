@@ -1,12 +1,21 @@
-use crate::common::APP_USER_AGENT;
-use crate::rest::helpers::build_identity_from_file;
+use crate::common::http::{HttpClientOptions, ProxyUrl};
+use crate::common::parse_ip_from_cidr;
+use crate::common::progress::{Progress, DEFAULT_PROGRESS_EVERY};
+use crate::common::retry::RetryPolicy;
+use crate::rest::error::{error_for_status, ApiError, ApiResult};
+use crate::rest::helpers::AuthScheme;
 use anyhow::{anyhow, Error, Result};
 use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::Proxy;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::{Date, OffsetDateTime, UtcOffset};
 
-const API_LIMIT: u32 = 100;
+/// The default number of objects requested per page, Netbox caps it to its MAX_PAGE_SIZE
+pub const DEFAULT_PAGE_SIZE: u32 = 1000;
 const PATH_PING: &str = "/api/dcim/devices/?name=netbox2netshot-ping";
 const PATH_DCIM_DEVICES: &str = "/api/dcim/devices/";
 const PATH_VIRT_VM: &str = "/api/virtualization/virtual-machines/";
@@ -17,6 +26,42 @@ pub struct NetboxClient {
     pub url: String,
     pub token: String,
     pub client: reqwest::blocking::Client,
+    pub retry_policy: RetryPolicy,
+    /// The number of objects requested per page
+    pub page_size: u32,
+    /// The number of fetched objects between two progress logs, 0 disabling them
+    pub progress_every: usize,
+    /// Request the first page with a cursor, for the Netbox versions supporting it
+    pub cursor_pagination: bool,
+}
+
+/// Deserialize a `null` value as the default one, `#[serde(default)]` only covering absent fields
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// The status field, a `{value, label}` choice on the REST API and a plain value on GraphQL
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusField {
+    Choice { value: String },
+    Value(String),
+}
+
+/// Deserialize the status field into its value, lowercased as the GraphQL enums are not
+fn status_value<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        Option::<StatusField>::deserialize(deserializer)?.map(|status| match status {
+            StatusField::Choice { value } | StatusField::Value(value) => value.to_lowercase(),
+        }),
+    )
 }
 
 /// Represent the primary_ip field from the DCIM device API call
@@ -27,174 +72,589 @@ pub struct PrimaryIP {
     pub address: String,
 }
 
+/// Represent a nested object reference (site, tenant...) from the API calls
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NestedObject {
+    pub id: u32,
+    pub name: String,
+    pub slug: Option<String>,
+}
+
 /// Represent the required information from the DCIM device API call
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Device {
     pub id: u32,
     pub name: Option<String>,
     pub primary_ip4: Option<PrimaryIP>,
+    pub primary_ip6: Option<PrimaryIP>,
+    /// The generic primary IP, either family, absent from the GraphQL objects
+    #[serde(default)]
+    pub primary_ip: Option<PrimaryIP>,
+    #[serde(default)]
+    pub site: Option<NestedObject>,
+    #[serde(default)]
+    pub tenant: Option<NestedObject>,
+    /// The platform, its slug selecting the Netshot driver of the registered device
+    #[serde(default)]
+    pub platform: Option<NestedObject>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<NestedObject>,
+    /// When the object was last modified, absent from the GraphQL objects
+    #[serde(default)]
+    pub last_updated: Option<String>,
+    /// The status value, such as `active` or `offline`
+    #[serde(default, deserialize_with = "status_value")]
+    pub status: Option<String>,
+}
+
+/// The device field the management IP is read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpField {
+    /// `primary_ip4`, falling back to `primary_ip6` for IPv6-only objects
+    #[default]
+    PrimaryIp4,
+    /// `primary_ip6`, falling back to `primary_ip4` for IPv4-only objects
+    PrimaryIp6,
+    /// `primary_ip`, the family being chosen by Netbox
+    PrimaryIp,
+}
+
+impl IpField {
+    /// The field name, as used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpField::PrimaryIp4 => "primary_ip4",
+            IpField::PrimaryIp6 => "primary_ip6",
+            IpField::PrimaryIp => "primary_ip",
+        }
+    }
+}
+
+impl FromStr for IpField {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "primary_ip4" => Ok(IpField::PrimaryIp4),
+            "primary_ip6" => Ok(IpField::PrimaryIp6),
+            "primary_ip" => Ok(IpField::PrimaryIp),
+            _ => Err(anyhow!(
+                "Invalid Netbox IP field {}, expected primary_ip4, primary_ip6 or primary_ip",
+                value
+            )),
+        }
+    }
+}
+
+/// The start of an incremental run: only the objects updated since then are fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    /// A duration before the run, e.g. `90m`, `12h` or `7d`
+    Ago(Duration),
+    /// A fixed time, an RFC 3339 timestamp or a date (midnight UTC)
+    At(OffsetDateTime),
+}
+
+impl Since {
+    /// The UTC timestamp given to the `last_updated__gte` filter, durations counting from now
+    pub fn timestamp(&self) -> Result<String, Error> {
+        let at = match self {
+            Since::Ago(duration) => OffsetDateTime::now_utc()
+                .checked_sub(time::Duration::try_from(*duration)?)
+                .ok_or_else(|| anyhow!("--since {}s is too far back", duration.as_secs()))?,
+            Since::At(at) => at.to_offset(UtcOffset::UTC),
+        };
+        Ok(at.replace_nanosecond(0)?.format(&Rfc3339)?)
+    }
+}
+
+impl FromStr for Since {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Ok(at) = OffsetDateTime::parse(value, &Rfc3339) {
+            return Ok(Since::At(at));
+        }
+        let date_format = time::format_description::parse("[year]-[month]-[day]")?;
+        if let Ok(date) = Date::parse(value, &date_format) {
+            return Ok(Since::At(date.midnight().assume_utc()));
+        }
+
+        let invalid = || {
+            anyhow!(
+                "Invalid --since {}, expected a duration (e.g. 12h or 7d), an RFC 3339 timestamp or a date",
+                value
+            )
+        };
+        let split = value
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (count, unit) = value.split_at(split);
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let unit_secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 604800,
+            _ => return Err(invalid()),
+        };
+        Ok(Since::Ago(Duration::from_secs(
+            count.checked_mul(unit_secs).ok_or_else(invalid)?,
+        )))
+    }
+}
+
+/// Select the objects updated since the given timestamp in a devices/VMs querystring filter
+pub fn with_since_filter(filter: &str, timestamp: &str) -> String {
+    let selection = format!("last_updated__gte={}", timestamp);
+    let filter = filter.trim();
+    if filter.is_empty() {
+        selection
+    } else {
+        format!("{}&{}", filter, selection)
+    }
+}
+
+/// Add the role slugs and device type IDs selecting the devices to a querystring filter.
+///
+/// Netbox ORs the values of a repeated parameter and ANDs the different parameters, so a device
+/// is selected when it has any of the roles and any of the device types
+pub fn with_device_selectors(filter: &str, roles: &[String], device_types: &[u32]) -> String {
+    let roles = roles
+        .iter()
+        .map(|role| role.trim())
+        .filter(|role| !role.is_empty())
+        .map(|role| format!("role={}", role));
+    let device_types = device_types
+        .iter()
+        .map(|device_type| format!("device_type_id={}", device_type));
+    let filter = filter.trim();
+    let params: Vec<String> = Some(filter.to_string())
+        .filter(|filter| !filter.is_empty())
+        .into_iter()
+        .chain(roles)
+        .chain(device_types)
+        .collect();
+    params.join("&")
 }
 
 /// Represent the API response from /api/dcim/devices call
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NetboxDCIMDeviceList {
+    #[serde(default, deserialize_with = "null_as_default")]
     count: u32,
+    #[serde(default)]
     next: Option<String>,
+    #[serde(default)]
     previous: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
     results: Vec<Device>,
 }
 
-/// Extract the offset from the URL returned from the API
-fn extract_offset(url_string: &String) -> Result<u32, Error> {
+/// The status selected when no `--netbox-status` is given and the filter has none
+pub const DEFAULT_STATUS: &str = "active";
+
+/// The statuses of the devices disabled on Netshot by `--disable-offline` by default
+pub const DEFAULT_OFFLINE_STATUSES: [&str; 2] = ["offline", "decommissioning"];
+
+/// Add the offline statuses to a status selection, active by default, so the offline devices
+/// are fetched as well. A selection of any status is left as is
+pub fn with_offline_statuses(statuses: &[String], offline_statuses: &[String]) -> Vec<String> {
+    if !statuses.is_empty() && statuses.iter().all(|status| status.trim().is_empty()) {
+        return statuses.to_vec();
+    }
+    let mut statuses = if statuses.is_empty() {
+        vec![DEFAULT_STATUS.to_string()]
+    } else {
+        statuses.to_vec()
+    };
+    for status in offline_statuses {
+        if !statuses.contains(status) {
+            statuses.push(status.clone());
+        }
+    }
+    statuses
+}
+
+/// Add the status selection to a devices/VMs filter, a querystring or GraphQL arguments.
+///
+/// Without statuses, `active` is selected unless the filter already selects a status. Empty
+/// statuses are dropped, so a single empty one disables the selection
+pub fn with_status_filter(filter: &str, statuses: &[String], graphql: bool) -> String {
+    let filter = filter.trim();
+    let has_status = if graphql {
+        filter
+            .split(',')
+            .any(|argument| argument.trim().starts_with("status"))
+    } else {
+        filter.split('&').any(|param| param.starts_with("status="))
+    };
+
+    let statuses: Vec<&str> = if statuses.is_empty() {
+        if has_status {
+            Vec::new()
+        } else {
+            vec![DEFAULT_STATUS]
+        }
+    } else {
+        statuses
+            .iter()
+            .map(|status| status.trim())
+            .filter(|status| !status.is_empty())
+            .collect()
+    };
+    if statuses.is_empty() {
+        return filter.to_string();
+    }
+
+    let (selection, separator) = if graphql {
+        let values: Vec<String> = statuses
+            .iter()
+            .map(|status| format!("\"{}\"", status))
+            .collect();
+        (format!("status: [{}]", values.join(", ")), ", ")
+    } else {
+        let params: Vec<String> = statuses
+            .iter()
+            .map(|status| format!("status={}", status))
+            .collect();
+        (params.join("&"), "&")
+    };
+    if filter.is_empty() {
+        selection
+    } else {
+        format!("{}{}{}", filter, separator, selection)
+    }
+}
+
+/// Check the structure of a devices/VMs querystring filter, returning a warning per suspicious pair.
+///
+/// The field names are not checked, only mistakes such as a full URL or a `?` are rejected since
+/// the filter is appended to the API path
+pub fn check_filter(filter: &str) -> Result<Vec<String>, Error> {
+    let filter = filter.trim();
+    if filter.contains("://") {
+        return Err(anyhow!("expected a querystring, not a full URL"));
+    }
+    if filter.contains('?') {
+        return Err(anyhow!("the querystring must not contain a '?'"));
+    }
+    reqwest::Url::parse(&format!("http://netbox.invalid/?{}", filter))
+        .map_err(|error| anyhow!("not a valid querystring: {}", error))?;
+
+    let mut warnings = Vec::new();
+    if !filter.is_empty() && filter.split('&').any(str::is_empty) {
+        warnings.push(String::from(
+            "empty parameter, check for a doubled or trailing '&'",
+        ));
+    }
+    for param in filter.split('&').filter(|param| !param.is_empty()) {
+        match param.split_once('=') {
+            None => warnings.push(format!("'{}' has no value, expected key=value", param)),
+            Some(("", _)) => warnings.push(format!("'{}' has no key", param)),
+            Some((key, "")) => warnings.push(format!("'{}' has an empty value", key)),
+            Some((key, value)) if key.contains(char::is_whitespace) || value.contains('=') => {
+                warnings.push(format!("'{}' looks malformed", param))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(warnings)
+}
+
+/// Where a page starts: an offset, or with cursor pagination the lowest ID of its objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagePosition {
+    Offset(u32),
+    Cursor(u32),
+}
+
+impl PagePosition {
+    /// The querystring parameter selecting the page
+    fn query(&self) -> String {
+        match self {
+            PagePosition::Offset(offset) => format!("offset={}", offset),
+            PagePosition::Cursor(start) => format!("start={}", start),
+        }
+    }
+}
+
+/// Extract the position of the next page from the URL returned from the API, following the
+/// cursor (`start`) when there is one and the offset otherwise
+fn extract_position(url_string: &str) -> Result<PagePosition, Error> {
     let url = reqwest::Url::parse(url_string)?;
+    if let Some((_, start)) = url.query_pairs().find(|(key, _)| key == "start") {
+        return Ok(PagePosition::Cursor(start.parse()?));
+    }
     let offset_string = url.query_pairs().find(|(key, _)| key == "offset");
     match offset_string {
-        Some((_, x)) => Ok(x.parse()?),
+        Some((_, x)) => Ok(PagePosition::Offset(x.parse()?)),
         None => Err(anyhow!("No offset found in url")),
     }
 }
 
+/// Why a Netbox object has no usable management address
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AddressError {
+    #[error("no primary IPv4 address, nor an IPv6 one to fall back to")]
+    NoIpv4,
+    #[error("no primary IPv6 address, nor an IPv4 one to fall back to")]
+    NoIpv6,
+    #[error("no primary IP address")]
+    NoPrimaryIp,
+    #[error("{0}")]
+    Unparseable(String),
+}
+
+impl AddressError {
+    /// A short machine-readable name of the reason, for the logs and reports
+    pub fn category(&self) -> &'static str {
+        match self {
+            AddressError::NoIpv4 => "no_ipv4",
+            AddressError::NoIpv6 => "no_ipv6",
+            AddressError::NoPrimaryIp => "no_primary_ip",
+            AddressError::Unparseable(_) => "unparseable",
+        }
+    }
+}
+
 impl Device {
     /// Is this a valid device for import
     pub fn is_valid(&self) -> bool {
-        self.primary_ip4.is_some() && self.name.is_some()
+        self.management_ip(IpField::default()).is_some() && self.name.is_some()
+    }
+
+    /// The management IP of the device, read from the given field
+    pub fn management_ip(&self, field: IpField) -> Option<&PrimaryIP> {
+        match field {
+            IpField::PrimaryIp4 => self.primary_ip4.as_ref().or(self.primary_ip6.as_ref()),
+            IpField::PrimaryIp6 => self.primary_ip6.as_ref().or(self.primary_ip4.as_ref()),
+            IpField::PrimaryIp => self.primary_ip.as_ref(),
+        }
+    }
+
+    /// The parsed management IP of the device, read from the given field, or why there is none
+    pub fn management_address(&self, field: IpField) -> Result<IpAddr, AddressError> {
+        let primary_ip = self.management_ip(field).ok_or(match field {
+            IpField::PrimaryIp4 => AddressError::NoIpv4,
+            IpField::PrimaryIp6 => AddressError::NoIpv6,
+            IpField::PrimaryIp => AddressError::NoPrimaryIp,
+        })?;
+        parse_ip_from_cidr(&primary_ip.address)
+            .map_err(|error| AddressError::Unparseable(error.to_string()))
     }
 }
 
 impl NetboxClient {
     /// Create a client without authentication
-    pub fn new_anonymous(url: String, proxy: Option<String>) -> Result<Self, Error> {
-        NetboxClient::new(url, None, proxy, None, None)
+    pub fn new_anonymous(url: String, proxy: Option<ProxyUrl>) -> Result<Self, Error> {
+        NetboxClient::new(url, None, &HttpClientOptions::with_proxy(proxy))
     }
 
     /// Create a client with the given authentication token
     pub fn new(
         url: String,
         token: Option<String>,
-        proxy: Option<String>,
-        tls_client_certificate: Option<String>,
-        tls_client_certificate_password: Option<String>,
+        options: &HttpClientOptions,
     ) -> Result<Self, Error> {
         log::debug!("Creating new Netbox client to {}", url);
-        let mut http_client = reqwest::blocking::Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .timeout(Duration::from_secs(5));
+        let mut http_client = options.builder()?;
 
         http_client = match token {
             Some(ref t) => {
                 let mut http_headers = HeaderMap::new();
-                let header_value = HeaderValue::from_str(format!("Token {}", t).as_str())?;
+                let prefix = match options.auth_scheme {
+                    AuthScheme::Token => "Token",
+                    AuthScheme::Bearer => "Bearer",
+                };
+                let header_value = HeaderValue::from_str(format!("{} {}", prefix, t).as_str())?;
                 http_headers.insert("Authorization", header_value);
                 http_client.default_headers(http_headers)
             }
             None => http_client,
         };
 
-        http_client = match proxy {
-            Some(p) => http_client.proxy(Proxy::all(p)?),
-            None => http_client,
-        };
-
-        http_client = match tls_client_certificate {
-            Some(c) => http_client.identity(build_identity_from_file(
-                c,
-                tls_client_certificate_password,
-            )?),
-            None => http_client,
-        };
-
         Ok(Self {
             url,
             token: token.unwrap_or("".to_string()),
             client: http_client.build()?,
+            retry_policy: RetryPolicy::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            progress_every: DEFAULT_PROGRESS_EVERY,
+            cursor_pagination: false,
         })
     }
 
+    /// Use the given retry policy for the requests sent by this client
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Request the first page with a cursor (`start`) instead of an offset, which Netbox can
+    /// serve much faster on large tables. The `next` links are followed whatever their kind
+    pub fn with_cursor_pagination(mut self, cursor_pagination: bool) -> Self {
+        self.cursor_pagination = cursor_pagination;
+        self
+    }
+
+    /// Request the given number of objects per page, at least one
+    pub fn with_page_size(mut self, page_size: u32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Log the fetch progress every given number of objects, 0 disabling it
+    pub fn with_progress_every(mut self, progress_every: usize) -> Self {
+        self.progress_every = progress_every;
+        self
+    }
+
     /// Ping the service to make sure it is reachable and pass the authentication (if there is any)
-    pub fn ping(&self) -> Result<bool, Error> {
+    pub fn ping(&self) -> ApiResult<()> {
         let url = format!("{}{}", self.url, PATH_PING);
         log::debug!("Pinging {}", url);
-        let response = self.client.get(url).send()?;
+        let response = self.retry_policy.send(self.client.get(url), true)?;
         log::debug!("Ping response: {}", response.status());
-        Ok(response.status().is_success())
+        error_for_status(response)?;
+        Ok(())
+    }
+
+    /// Get a single device by its Netbox ID, `None` when it doesn't exist
+    pub fn get_device_by_id(&self, id: u32) -> ApiResult<Option<Device>> {
+        let url = format!("{}{}{}/", self.url, PATH_DCIM_DEVICES, id);
+        log::debug!("Fetching the Netbox device {}", id);
+        match error_for_status(self.retry_policy.send(self.client.get(url), true)?) {
+            Ok(response) => Ok(Some(response.json()?)),
+            Err(ApiError::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
     }
 
     /// Get a single device page
     pub fn get_devices_page(
         &self,
         path: &str,
-        query_string: &String,
+        query_string: &str,
         limit: u32,
-        offset: u32,
-    ) -> Result<NetboxDCIMDeviceList, Error> {
+        position: PagePosition,
+    ) -> ApiResult<NetboxDCIMDeviceList> {
         let url = format!(
-            "{}{}?limit={}&offset={}&{}",
-            self.url, path, limit, offset, query_string
+            "{}{}?limit={}&{}&{}",
+            self.url,
+            path,
+            limit,
+            position.query(),
+            query_string
         );
-        let page: NetboxDCIMDeviceList = self.client.get(url).send()?.json()?;
+        let response = error_for_status(self.retry_policy.send(self.client.get(url), true)?)?;
+        let page: NetboxDCIMDeviceList = response.json()?;
         Ok(page)
     }
 
-    /// Get the devices using the given filter
-    pub fn get_devices(&self, query_string: &String) -> Result<Vec<Device>, Error> {
-        let mut devices: Vec<Device> = Vec::new();
-        let mut offset = 0;
+    /// Pass every page of the given endpoint to `handle_page`, following the `next` links until
+    /// exhaustion, and return the number of objects fetched
+    fn for_each_page(
+        &self,
+        path: &str,
+        query_string: &str,
+        mut handle_page: impl FnMut(Vec<Device>),
+    ) -> ApiResult<usize> {
+        let mut fetched = 0;
+        let mut position = if self.cursor_pagination {
+            PagePosition::Cursor(0)
+        } else {
+            PagePosition::Offset(0)
+        };
+        let mut pages = 0;
+        let mut progress = None;
 
         loop {
-            let mut response =
-                self.get_devices_page(PATH_DCIM_DEVICES, &query_string, API_LIMIT, offset)?;
+            let response = self.get_devices_page(path, query_string, self.page_size, position)?;
 
-            devices.append(&mut response.results);
+            // Netbox may return smaller pages than requested, the next link follows its own size
+            fetched += response.results.len();
+            pages += 1;
 
-            let pages_count = response.count / API_LIMIT;
+            // The cursor pages don't count the matches
             log::debug!(
-                "Got {} devices on the {} matches (page {}/{})",
-                devices.len(),
+                "Got {} objects from {} on the {} matches (page {})",
+                fetched,
+                path,
                 response.count,
-                (offset / API_LIMIT),
-                pages_count
+                pages
             );
+            progress
+                .get_or_insert_with(|| {
+                    Progress::new(
+                        "Fetched Netbox objects",
+                        response.count as usize,
+                        self.progress_every,
+                    )
+                })
+                .add(response.results.len());
+            handle_page(response.results);
 
             match response.next {
                 Some(x) => {
-                    offset = extract_offset(&x)?;
+                    position = extract_position(&x).map_err(|error| {
+                        ApiError::Decode(format!("invalid next page: {}", error))
+                    })?;
                 }
                 None => break,
             }
         }
 
-        log::info!("Fetched {} devices from Netbox", devices.len());
-        Ok(devices)
+        Ok(fetched)
     }
 
-    /// Get the VMs as device using the given filter
-    pub fn get_vms(&self, query_string: &String) -> Result<Vec<Device>, Error> {
+    /// Get every page of the given endpoint by following the `next` links until exhaustion
+    fn get_all_pages(&self, path: &str, query_string: &str) -> ApiResult<Vec<Device>> {
         let mut devices: Vec<Device> = Vec::new();
-        let mut offset = 0;
-
-        loop {
-            let mut response =
-                self.get_devices_page(PATH_VIRT_VM, &query_string, API_LIMIT, offset)?;
+        self.for_each_page(path, query_string, |mut page| devices.append(&mut page))?;
+        Ok(devices)
+    }
 
-            devices.append(&mut response.results);
+    /// Get the devices using the given filter
+    pub fn get_devices(&self, query_string: &str) -> ApiResult<Vec<Device>> {
+        let devices = self.get_all_pages(PATH_DCIM_DEVICES, query_string)?;
+        log::info!("Fetched {} devices from Netbox", devices.len());
+        Ok(devices)
+    }
 
-            let pages_count = response.count / API_LIMIT;
-            log::debug!(
-                "Got {} VM devices on the {} matches (page {}/{})",
-                devices.len(),
-                response.count,
-                (offset / API_LIMIT),
-                pages_count
-            );
+    /// Pass the devices selected by the given filter to `handle_device` page by page, without
+    /// collecting them, and return their number
+    pub fn for_each_device(
+        &self,
+        query_string: &str,
+        mut handle_device: impl FnMut(Device),
+    ) -> ApiResult<usize> {
+        let count = self.for_each_page(PATH_DCIM_DEVICES, query_string, |page| {
+            page.into_iter().for_each(&mut handle_device)
+        })?;
+        log::info!("Fetched {} devices from Netbox", count);
+        Ok(count)
+    }
 
-            match response.next {
-                Some(x) => {
-                    offset = extract_offset(&x)?;
-                }
-                None => break,
-            }
-        }
+    /// Pass the VMs selected by the given filter to `handle_vm` page by page, without collecting
+    /// them, and return their number
+    pub fn for_each_vm(
+        &self,
+        query_string: &str,
+        mut handle_vm: impl FnMut(Device),
+    ) -> ApiResult<usize> {
+        let count = self.for_each_page(PATH_VIRT_VM, query_string, |page| {
+            page.into_iter().for_each(&mut handle_vm)
+        })?;
+        log::info!("Fetched {} VM devices from Netbox", count);
+        Ok(count)
+    }
 
+    /// Get the VMs as device using the given filter
+    pub fn get_vms(&self, query_string: &str) -> ApiResult<Vec<Device>> {
+        let devices = self.get_all_pages(PATH_VIRT_VM, query_string)?;
         log::info!("Fetched {} VM devices from Netbox", devices.len());
         Ok(devices)
     }
@@ -217,11 +677,33 @@ mod tests {
     fn authenticated_initialization() {
         let url = mockito::server_url();
         let token = String::from("hello");
-        let client = NetboxClient::new(url.clone(), Some(token.clone()), None, None, None).unwrap();
+        let client = NetboxClient::new(
+            url.clone(),
+            Some(token.clone()),
+            &HttpClientOptions::default(),
+        )
+        .unwrap();
         assert_eq!(client.token, token);
         assert_eq!(client.url, url);
     }
 
+    #[test]
+    fn bearer_authentication() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_PING)
+            .match_header("Authorization", "Bearer hello")
+            .with_body_from_file("tests/data/netbox/ping.json")
+            .create();
+
+        let options = HttpClientOptions {
+            auth_scheme: AuthScheme::Bearer,
+            ..Default::default()
+        };
+        let client = NetboxClient::new(url, Some(String::from("hello")), &options).unwrap();
+        assert!(client.ping().is_ok());
+    }
+
     #[test]
     fn failed_ping() {
         let url = mockito::server_url();
@@ -231,8 +713,8 @@ mod tests {
             .create();
 
         let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
-        let ping = client.ping().unwrap();
-        assert_eq!(ping, false);
+        let error = client.ping().unwrap_err();
+        assert!(matches!(error, ApiError::Unauthorized(403)));
     }
 
     #[test]
@@ -244,11 +726,11 @@ mod tests {
             .create();
 
         let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
-        let ping = client.ping().unwrap();
-        assert_eq!(ping, true);
+        assert!(client.ping().is_ok());
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
     fn single_good_device() {
         let url = mockito::server_url();
 
@@ -258,7 +740,7 @@ mod tests {
             .create();
 
         let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
-        let devices = client.get_devices(&String::from("")).unwrap();
+        let devices = client.get_devices("").unwrap();
 
         assert_eq!(devices.len(), 1);
 
@@ -271,6 +753,88 @@ mod tests {
     }
 
     #[test]
+    fn device_with_location() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/device_with_location.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let devices = client.get_devices("").unwrap();
+
+        let device = devices.first().unwrap();
+
+        assert_eq!(device.site.as_ref().unwrap().name, "DC1");
+        assert_eq!(device.tenant.as_ref().unwrap().name, "Customer A");
+    }
+
+    #[test]
+    fn device_with_tags() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/device_with_tags.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let devices = client.get_devices("").unwrap();
+
+        let device = devices.first().unwrap();
+
+        let slugs: Vec<&str> = device
+            .tags
+            .iter()
+            .filter_map(|tag| tag.slug.as_deref())
+            .collect();
+        assert_eq!(slugs, vec!["core", "critical"]);
+    }
+
+    #[test]
+    fn device_with_platform() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/device_with_platform.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let devices = client.get_devices("").unwrap();
+
+        let device = devices.first().unwrap();
+
+        assert_eq!(
+            device.platform.as_ref().unwrap().slug.as_deref(),
+            Some("ios")
+        );
+    }
+
+    #[test]
+    fn device_by_id() {
+        let url = mockito::server_url();
+
+        let _found = mockito::mock("GET", "/api/dcim/devices/1/")
+            .with_body_from_file("tests/data/netbox/device_detail.json")
+            .create();
+        let _missing = mockito::mock("GET", "/api/dcim/devices/2/")
+            .with_status(404)
+            .with_body(r#"{"detail": "Not found."}"#)
+            .create();
+
+        let client = NetboxClient::new_anonymous(url, None).unwrap();
+
+        let device = client.get_device_by_id(1).unwrap().unwrap();
+        assert_eq!(device.id, 1_u32);
+        assert_eq!(device.name.as_deref(), Some("test-device"));
+        assert!(device.is_valid());
+        assert!(client.get_device_by_id(2).unwrap().is_none());
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn single_device_without_primary_ip() {
         let url = mockito::server_url();
 
@@ -280,7 +844,7 @@ mod tests {
             .create();
 
         let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
-        let devices = client.get_devices(&String::from("")).unwrap();
+        let devices = client.get_devices("").unwrap();
 
         assert_eq!(devices.len(), 1);
 
@@ -290,6 +854,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn single_device_without_name() {
         let url = mockito::server_url();
 
@@ -299,7 +864,7 @@ mod tests {
             .create();
 
         let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
-        let devices = client.get_devices(&String::from("")).unwrap();
+        let devices = client.get_devices("").unwrap();
 
         assert_eq!(devices.len(), 1);
 
@@ -307,4 +872,358 @@ mod tests {
 
         assert_eq!(device.is_valid(), false);
     }
+
+    #[test]
+    fn single_ipv6_only_device() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/single_ipv6_only_device.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let devices = client.get_devices("").unwrap();
+
+        assert_eq!(devices.len(), 1);
+
+        let device = devices.first().unwrap();
+
+        assert!(device.primary_ip4.is_none());
+        assert_eq!(
+            device.management_ip(IpField::PrimaryIp4).unwrap().address,
+            "2001:db8::1/128"
+        );
+        assert!(device.is_valid());
+    }
+
+    #[test]
+    fn null_results() {
+        let url = mockito::server_url();
+
+        let null_results = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netbox/null_results.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        assert!(client.get_devices("").unwrap().is_empty());
+        null_results.assert();
+
+        // Without any of the list fields
+        let empty_object = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_body("{}")
+            .create();
+        drop(null_results);
+        assert!(client.get_devices("").unwrap().is_empty());
+        empty_object.assert();
+    }
+
+    #[test]
+    fn unauthorized_with_null_results() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Any)
+            .with_status(403)
+            .with_body_from_file("tests/data/netbox/null_results.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let error = client.get_devices("").unwrap_err();
+
+        assert!(matches!(error, ApiError::Unauthorized(403)));
+    }
+
+    #[test]
+    fn paginated_devices() {
+        let url = mockito::server_url();
+
+        let _mock_page1 = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "0".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page1.json")
+            .create();
+        let _mock_page2 = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "100".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page2.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let devices = client.get_devices("").unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name.as_ref().unwrap(), "test-device-1");
+        assert_eq!(devices[1].name.as_ref().unwrap(), "test-device-2");
+    }
+
+    #[test]
+    fn cursor_paginated_devices() {
+        let url = mockito::server_url();
+
+        let _mock_page1 = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("start".into(), "0".into()),
+                mockito::Matcher::UrlEncoded("limit".into(), "1".into()),
+            ]))
+            .with_body_from_file("tests/data/netbox/cursor_devices_page1.json")
+            .create();
+        let _mock_page2 = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::UrlEncoded("start".into(), "2".into()))
+            .with_body_from_file("tests/data/netbox/cursor_devices_page2.json")
+            .create();
+        let offset = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::Regex("offset=".into()))
+            .expect(0)
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None)
+            .unwrap()
+            .with_page_size(1)
+            .with_cursor_pagination(true);
+        let devices = client.get_devices("").unwrap();
+
+        offset.assert();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name.as_ref().unwrap(), "test-device-1");
+        assert_eq!(devices[1].name.as_ref().unwrap(), "test-device-2");
+    }
+
+    #[test]
+    fn next_page_position() {
+        assert_eq!(
+            extract_position("http://netbox.example.org/api/dcim/devices/?limit=100&offset=200")
+                .unwrap(),
+            PagePosition::Offset(200)
+        );
+        assert_eq!(
+            extract_position("http://netbox.example.org/api/dcim/devices/?limit=100&start=1234")
+                .unwrap(),
+            PagePosition::Cursor(1234)
+        );
+        assert!(extract_position("http://netbox.example.org/api/dcim/devices/?limit=100").is_err());
+    }
+
+    #[test]
+    fn filter_structure() {
+        assert!(check_filter("").unwrap().is_empty());
+        assert!(check_filter("site=paris&role=core-switch")
+            .unwrap()
+            .is_empty());
+        assert!(check_filter("https://netbox.example.org/api/dcim/devices/?site=paris").is_err());
+        assert!(check_filter("?site=paris").is_err());
+
+        let warnings = check_filter("site=paris&&role&tenant=&=core").unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                "empty parameter, check for a doubled or trailing '&'",
+                "'role' has no value, expected key=value",
+                "'tenant' has an empty value",
+                "'=core' has no key",
+            ]
+        );
+    }
+
+    #[test]
+    fn status_filter() {
+        let statuses = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+
+        assert_eq!(with_status_filter("", &[], false), "status=active");
+        assert_eq!(
+            with_status_filter("site=dc1", &[], false),
+            "site=dc1&status=active"
+        );
+        assert_eq!(
+            with_status_filter("site=dc1&status=planned", &[], false),
+            "site=dc1&status=planned"
+        );
+        assert_eq!(
+            with_status_filter("site=dc1", &statuses(&["active", "staged"]), false),
+            "site=dc1&status=active&status=staged"
+        );
+        assert_eq!(
+            with_status_filter("site=dc1", &statuses(&[""]), false),
+            "site=dc1"
+        );
+        assert_eq!(
+            with_status_filter("site: \"dc1\"", &[], true),
+            "site: \"dc1\", status: [\"active\"]"
+        );
+        assert_eq!(
+            with_status_filter("status: \"planned\"", &[], true),
+            "status: \"planned\""
+        );
+    }
+
+    #[test]
+    fn offline_statuses() {
+        let statuses = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        let offline = statuses(&DEFAULT_OFFLINE_STATUSES);
+
+        assert_eq!(
+            with_offline_statuses(&[], &offline),
+            statuses(&["active", "offline", "decommissioning"])
+        );
+        assert_eq!(
+            with_offline_statuses(&statuses(&["staged", "offline"]), &offline),
+            statuses(&["staged", "offline", "decommissioning"])
+        );
+        assert_eq!(
+            with_offline_statuses(&statuses(&[""]), &offline),
+            statuses(&[""])
+        );
+    }
+
+    #[test]
+    fn management_address_skip_reasons() {
+        let device = |primary_ip4: &str, primary_ip6: &str| -> Device {
+            serde_json::from_str(&format!(
+                r#"{{"id": 1, "name": "sw1", "primary_ip4": {}, "primary_ip6": {}}}"#,
+                primary_ip4, primary_ip6
+            ))
+            .unwrap()
+        };
+        let v4 = r#"{"id": 1, "family": 4, "address": "10.0.0.1/32"}"#;
+        let broken = r#"{"id": 1, "family": 4, "address": "10.0.0/32"}"#;
+
+        assert_eq!(
+            device(v4, "null").management_address(IpField::PrimaryIp6),
+            Ok("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            device("null", "null").management_address(IpField::PrimaryIp4),
+            Err(AddressError::NoIpv4)
+        );
+        assert_eq!(
+            device("null", "null").management_address(IpField::PrimaryIp6),
+            Err(AddressError::NoIpv6)
+        );
+        assert_eq!(
+            device(v4, "null").management_address(IpField::PrimaryIp),
+            Err(AddressError::NoPrimaryIp)
+        );
+        let error = device(broken, "null")
+            .management_address(IpField::PrimaryIp4)
+            .unwrap_err();
+        assert_eq!(error.category(), "unparseable");
+        assert_eq!(error.to_string(), "Invalid IP address 10.0.0/32");
+    }
+
+    #[test]
+    fn device_status() {
+        let device: Device = serde_json::from_str(
+            r#"{"id": 1, "name": "sw1", "primary_ip4": null, "primary_ip6": null,
+                "status": {"value": "offline", "label": "Offline"}}"#,
+        )
+        .unwrap();
+        assert_eq!(device.status.as_deref(), Some("offline"));
+
+        let device: Device = serde_json::from_str(
+            r#"{"id": 1, "name": "sw1", "primary_ip4": null, "primary_ip6": null, "status": null}"#,
+        )
+        .unwrap();
+        assert!(device.status.is_none());
+    }
+
+    #[test]
+    fn since_filter() {
+        let since: Since = "2024-05-01T10:30:00+02:00".parse().unwrap();
+        assert_eq!(since.timestamp().unwrap(), "2024-05-01T08:30:00Z");
+        let since: Since = "2024-05-01".parse().unwrap();
+        assert_eq!(since.timestamp().unwrap(), "2024-05-01T00:00:00Z");
+        assert_eq!(
+            "90m".parse::<Since>().unwrap(),
+            Since::Ago(Duration::from_secs(5400))
+        );
+        assert_eq!(
+            "7d".parse::<Since>().unwrap(),
+            Since::Ago(Duration::from_secs(7 * 86400))
+        );
+        for invalid in ["", "12", "h", "12y", "-1h", "2024-13-01"] {
+            assert!(invalid.parse::<Since>().is_err(), "{}", invalid);
+        }
+
+        let timestamp = Since::Ago(Duration::from_secs(3600)).timestamp().unwrap();
+        let at = OffsetDateTime::parse(&timestamp, &Rfc3339).unwrap();
+        let elapsed = OffsetDateTime::now_utc() - at;
+        assert!(elapsed >= time::Duration::hours(1) && elapsed < time::Duration::minutes(61));
+
+        assert_eq!(
+            with_since_filter("", "2024-05-01T00:00:00Z"),
+            "last_updated__gte=2024-05-01T00:00:00Z"
+        );
+        assert_eq!(
+            with_since_filter("status=active", "2024-05-01T00:00:00Z"),
+            "status=active&last_updated__gte=2024-05-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn device_selectors() {
+        let roles = vec!["access".to_string(), "core".to_string()];
+
+        assert_eq!(with_device_selectors("site=dc1", &[], &[]), "site=dc1");
+        assert_eq!(
+            with_device_selectors("", &roles, &[]),
+            "role=access&role=core"
+        );
+        assert_eq!(
+            with_device_selectors("site=dc1", &roles, &[12]),
+            "site=dc1&role=access&role=core&device_type_id=12"
+        );
+        assert_eq!(
+            with_device_selectors(" ", &[" ".to_string()], &[3, 4]),
+            "device_type_id=3&device_type_id=4"
+        );
+        assert_eq!(
+            with_status_filter(&with_device_selectors("", &roles, &[]), &[], false),
+            "role=access&role=core&status=active"
+        );
+    }
+
+    #[test]
+    fn configured_page_size() {
+        let url = mockito::server_url();
+
+        let mock = mockito::mock("GET", PATH_DCIM_DEVICES)
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("limit".into(), "250".into()),
+                mockito::Matcher::UrlEncoded("offset".into(), "0".into()),
+            ]))
+            .with_body_from_file("tests/data/netbox/single_good_device.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None)
+            .unwrap()
+            .with_page_size(250);
+        let devices = client.get_devices("").unwrap();
+
+        assert_eq!(devices.len(), 1);
+        mock.assert();
+        assert_eq!(client.with_page_size(0).page_size, 1);
+    }
+
+    #[test]
+    fn paginated_vms() {
+        let url = mockito::server_url();
+
+        let _mock_page1 = mockito::mock("GET", PATH_VIRT_VM)
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "0".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page1.json")
+            .create();
+        let _mock_page2 = mockito::mock("GET", PATH_VIRT_VM)
+            .match_query(mockito::Matcher::UrlEncoded("offset".into(), "100".into()))
+            .with_body_from_file("tests/data/netbox/paginated_devices_page2.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let vms = client.get_vms("").unwrap();
+
+        assert_eq!(vms.len(), 2);
+    }
 }
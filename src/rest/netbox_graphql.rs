@@ -0,0 +1,269 @@
+use crate::rest::error::{error_for_status, ApiError, ApiResult};
+use crate::rest::netbox::{Device, NestedObject, NetboxClient, PrimaryIP};
+use serde::{Deserialize, Serialize};
+
+const PATH_GRAPHQL: &str = "/graphql/";
+
+/// The fields fetched for both the devices and the VMs
+const OBJECT_FIELDS: &str = "id name status primary_ip4 { id address } primary_ip6 { id address } \
+                             site { id name slug } tenant { id name slug } \
+                             platform { id name slug } tags { id name slug }";
+
+#[derive(Debug, Serialize)]
+struct GraphQLRequest {
+    query: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLResponse {
+    data: Option<InventoryData>,
+    #[serde(default)]
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InventoryData {
+    #[serde(default)]
+    device_list: Vec<GraphQLObject>,
+    #[serde(default)]
+    virtual_machine_list: Vec<GraphQLObject>,
+}
+
+/// GraphQL IDs are strings, the objects are converted to the REST [`Device`] once decoded
+#[derive(Debug, Deserialize)]
+struct GraphQLObject {
+    id: String,
+    name: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    primary_ip4: Option<GraphQLAddress>,
+    primary_ip6: Option<GraphQLAddress>,
+    site: Option<GraphQLNested>,
+    tenant: Option<GraphQLNested>,
+    #[serde(default)]
+    platform: Option<GraphQLNested>,
+    #[serde(default)]
+    tags: Vec<GraphQLNested>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLAddress {
+    id: String,
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLNested {
+    id: String,
+    name: String,
+    slug: Option<String>,
+}
+
+/// Parse a GraphQL ID into the numeric ID used by the REST API
+fn parse_id(id: &str) -> ApiResult<u32> {
+    id.parse()
+        .map_err(|_| ApiError::Decode(format!("invalid object ID {}", id)))
+}
+
+impl GraphQLAddress {
+    fn into_primary_ip(self, family: u8) -> ApiResult<PrimaryIP> {
+        Ok(PrimaryIP {
+            id: parse_id(&self.id)?,
+            family,
+            address: self.address,
+        })
+    }
+}
+
+impl GraphQLNested {
+    fn into_nested_object(self) -> ApiResult<NestedObject> {
+        Ok(NestedObject {
+            id: parse_id(&self.id)?,
+            name: self.name,
+            slug: self.slug,
+        })
+    }
+}
+
+impl GraphQLObject {
+    fn into_device(self) -> ApiResult<Device> {
+        Ok(Device {
+            id: parse_id(&self.id)?,
+            name: self.name,
+            primary_ip4: self
+                .primary_ip4
+                .map(|ip| ip.into_primary_ip(4))
+                .transpose()?,
+            primary_ip6: self
+                .primary_ip6
+                .map(|ip| ip.into_primary_ip(6))
+                .transpose()?,
+            primary_ip: None,
+            site: self
+                .site
+                .map(GraphQLNested::into_nested_object)
+                .transpose()?,
+            tenant: self
+                .tenant
+                .map(GraphQLNested::into_nested_object)
+                .transpose()?,
+            platform: self
+                .platform
+                .map(GraphQLNested::into_nested_object)
+                .transpose()?,
+            tags: self
+                .tags
+                .into_iter()
+                .map(GraphQLNested::into_nested_object)
+                .collect::<ApiResult<_>>()?,
+            last_updated: None,
+            status: self.status.map(|status| status.to_lowercase()),
+        })
+    }
+}
+
+/// A list field of the query, the filter being raw GraphQL arguments such as `status: "active"`
+fn list_field(name: &str, filter: &str) -> String {
+    if filter.trim().is_empty() {
+        format!("{} {{ {} }}", name, OBJECT_FIELDS)
+    } else {
+        format!("{}({}) {{ {} }}", name, filter, OBJECT_FIELDS)
+    }
+}
+
+/// The query fetching the devices and, if a VM filter is given, the VMs
+fn inventory_query(devices_filter: &str, vms_filter: Option<&str>) -> String {
+    let mut fields = list_field("device_list", devices_filter);
+    if let Some(vms_filter) = vms_filter {
+        fields.push(' ');
+        fields.push_str(&list_field("virtual_machine_list", vms_filter));
+    }
+    format!("query {{ {} }}", fields)
+}
+
+impl NetboxClient {
+    /// Get the devices and, if a VM filter is given, the VMs in a single GraphQL query
+    pub fn get_inventory_graphql(
+        &self,
+        devices_filter: &str,
+        vms_filter: Option<&str>,
+    ) -> ApiResult<(Vec<Device>, Vec<Device>)> {
+        let url = format!("{}{}", self.url, PATH_GRAPHQL);
+        let request = GraphQLRequest {
+            query: inventory_query(devices_filter, vms_filter),
+        };
+        log::debug!("GraphQL query: {}", request.query);
+
+        let response = error_for_status(
+            self.retry_policy
+                .send(self.client.post(url).json(&request), true)?,
+        )?;
+        let response: GraphQLResponse = response.json()?;
+
+        if !response.errors.is_empty() {
+            let messages: Vec<String> = response
+                .errors
+                .into_iter()
+                .map(|error| error.message)
+                .collect();
+            return Err(ApiError::Rejected(messages.join("; ")));
+        }
+        let data = response
+            .data
+            .ok_or_else(|| ApiError::Decode(String::from("no data in the GraphQL response")))?;
+
+        let devices = data
+            .device_list
+            .into_iter()
+            .map(GraphQLObject::into_device)
+            .collect::<ApiResult<Vec<Device>>>()?;
+        let vms = data
+            .virtual_machine_list
+            .into_iter()
+            .map(GraphQLObject::into_device)
+            .collect::<ApiResult<Vec<Device>>>()?;
+        log::info!(
+            "Fetched {} devices and {} VM devices from Netbox GraphQL",
+            devices.len(),
+            vms.len()
+        );
+
+        Ok((devices, vms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rest::netbox::IpField;
+    use mockito;
+
+    #[test]
+    fn query_with_filters() {
+        let query = inventory_query("", None);
+        assert!(query.starts_with("query { device_list { id name status"));
+        assert!(!query.contains("virtual_machine_list"));
+
+        let query = inventory_query("status: \"active\"", Some(""));
+        assert!(query.contains("device_list(status: \"active\") {"));
+        assert!(query.contains("virtual_machine_list { id name"));
+    }
+
+    #[test]
+    fn graphql_inventory() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", PATH_GRAPHQL)
+            .match_body(mockito::Matcher::Regex(
+                "device_list.*virtual_machine_list".to_string(),
+            ))
+            .with_body_from_file("tests/data/netbox/graphql_inventory.json")
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let (devices, vms) = client.get_inventory_graphql("", Some("")).unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, 1);
+        assert_eq!(devices[0].name.as_ref().unwrap(), "test-device");
+        assert_eq!(
+            devices[0]
+                .management_ip(IpField::PrimaryIp4)
+                .unwrap()
+                .address,
+            "1.2.3.4/32"
+        );
+        assert_eq!(devices[0].site.as_ref().unwrap().name, "DC1");
+        assert_eq!(devices[0].platform.as_ref().unwrap().name, "Cisco IOS");
+        assert_eq!(devices[0].tags[0].slug.as_ref().unwrap(), "core");
+        assert_eq!(devices[0].status.as_deref(), Some("active"));
+        assert_eq!(
+            devices[1]
+                .management_ip(IpField::PrimaryIp4)
+                .unwrap()
+                .family,
+            6
+        );
+        assert_eq!(vms.len(), 1);
+        assert!(vms[0].management_ip(IpField::PrimaryIp4).is_none());
+    }
+
+    #[test]
+    fn graphql_errors() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", PATH_GRAPHQL)
+            .with_body(r#"{"data": null, "errors": [{"message": "Cannot query field \"foo\""}]}"#)
+            .create();
+
+        let client = NetboxClient::new_anonymous(url.clone(), None).unwrap();
+        let error = client.get_inventory_graphql("foo: 1", None).unwrap_err();
+
+        assert!(matches!(error, ApiError::Rejected(_)));
+    }
+}
@@ -1,23 +1,83 @@
-use crate::common::APP_USER_AGENT;
-use crate::rest::helpers::build_identity_from_file;
+use crate::common;
+use crate::common::http::HttpClientOptions;
+use crate::common::retry::RetryPolicy;
+use crate::rest::error::{error_for_status, ApiError, ApiResult};
+use crate::rest::helpers::AuthScheme;
 use anyhow::{anyhow, Error, Result};
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::Proxy;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
 use serde;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const PATH_DEVICES: &str = "/api/devices";
-const PATH_DEVICES_SEARCH: &str = "/api/devices/search";
+/// The path the Netshot REST API is mounted under, the paths below being relative to it
+pub const DEFAULT_API_BASE: &str = "/api";
+const PATH_DEVICES: &str = "/devices";
+const PATH_DEVICES_SEARCH: &str = "/devices/search";
+const PATH_DEVICES_BULK: &str = "/devices/bulk";
+const PATH_TASKS: &str = "/tasks";
+const PATH_DOMAINS: &str = "/domains";
+const PATH_GROUPS: &str = "/groups";
+
+/// The delay before polling a task again, doubled after each poll up to the maximum
+const TASK_POLL_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const TASK_POLL_MAX_DELAY: Duration = Duration::from_secs(30);
+const STATUS_DISABLED: &str = "DISABLED";
+/// The status of a registration Netshot rejected because the device is already managed
+const STATUS_ALREADY_REGISTERED: &str = "ALREADY_REGISTERED";
 
 #[derive(Debug)]
 pub struct NetshotClient {
     pub url: String,
     pub token: String,
     pub client: reqwest::blocking::Client,
+    pub retry_policy: RetryPolicy,
+    /// The path the API is mounted under, e.g. `/api`
+    pub api_base: String,
+    /// The full device list, fetched once when the search API is unavailable
+    device_cache: Mutex<Option<Vec<Device>>>,
+    /// The device lists last returned with an ETag, revalidated instead of downloaded again
+    etag_cache: Arc<EtagCache>,
+    /// Set once the server rejected a bulk registration
+    bulk_unsupported: AtomicBool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The device lists returned by Netshot along with their ETag, by URL.
+///
+/// Shared between the successive clients of a daemon, so an unchanged inventory is answered
+/// with a 304 instead of being downloaded again
+#[derive(Debug, Default)]
+pub struct EtagCache {
+    entries: Mutex<HashMap<String, (String, Vec<Device>)>>,
+}
+
+impl EtagCache {
+    /// The ETag and devices last returned for the given URL
+    fn get(&self, url: &str) -> Option<(String, Vec<Device>)> {
+        self.entries.lock().unwrap().get(url).cloned()
+    }
+
+    /// Remember the devices returned for the given URL, forgetting them without an ETag
+    fn store(&self, url: &str, etag: Option<String>, devices: &[Device]) {
+        let mut entries = self.entries.lock().unwrap();
+        match etag {
+            Some(etag) => {
+                entries.insert(url.to_string(), (etag, devices.to_vec()));
+            }
+            None => {
+                entries.remove(url);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ManagementAddress {
     #[serde(rename = "prefixLength")]
     pub prefix_length: u8,
@@ -26,13 +86,142 @@ pub struct ManagementAddress {
     pub ip: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Domain {
+    pub id: u32,
+    pub name: String,
+}
+
+/// The ID of the domain with the given name, failing when it is missing or ambiguous
+pub fn find_domain_id(domains: &[Domain], name: &str) -> Result<u32, Error> {
+    let matching: Vec<&Domain> = domains
+        .iter()
+        .filter(|domain| domain.name == name)
+        .collect();
+    match matching.as_slice() {
+        [domain] => Ok(domain.id),
+        [] => Err(anyhow!("No Netshot domain is named {}", name)),
+        _ => Err(anyhow!(
+            "The Netshot domain name {} is ambiguous, matching IDs {}",
+            name,
+            matching
+                .iter()
+                .map(|domain| domain.id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Check that every given domain ID exists, failing with the list of the valid ones otherwise
+pub fn check_domain_ids(domains: &[Domain], domain_ids: &[u32]) -> Result<(), Error> {
+    let unknown: Vec<String> = domain_ids
+        .iter()
+        .filter(|domain_id| !domains.iter().any(|domain| domain.id == **domain_id))
+        .map(|domain_id| domain_id.to_string())
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let valid: Vec<String> = domains
+        .iter()
+        .map(|domain| format!("{} ({})", domain.id, domain.name))
+        .collect();
+    Err(anyhow!(
+        "No Netshot domain with ID {}, the valid domains are: {}",
+        unknown.join(", "),
+        if valid.is_empty() {
+            String::from("none")
+        } else {
+            valid.join(", ")
+        }
+    ))
+}
+
+/// The body of the Netshot error responses
+#[derive(Debug, Deserialize)]
+struct ErrorPayload {
+    #[serde(rename = "errorMsg")]
+    error_message: String,
+    #[serde(rename = "errorCode", default)]
+    error_code: Option<i32>,
+}
+
+/// The longest raw error body kept in the error message
+const RAW_ERROR_BODY_LIMIT: usize = 200;
+
+/// Netshot's own explanation of a failure, from its error body or the raw body of a client error
+fn error_explanation(body: &str, client_error: bool) -> Option<String> {
+    if let Ok(payload) = serde_json::from_str::<ErrorPayload>(body) {
+        return Some(match payload.error_code {
+            Some(code) => format!("{} (error {})", payload.error_message, code),
+            None => payload.error_message,
+        });
+    }
+    let body = body.trim();
+    if !client_error || body.is_empty() {
+        return None;
+    }
+    Some(body.chars().take(RAW_ERROR_BODY_LIMIT).collect())
+}
+
+/// The Netshot error code of a registration rejected because the IP address is already managed
+const ERROR_CODE_ALREADY_MANAGED: i32 = 131;
+
+/// The Netshot message of the same rejection, the only thing the bulk results carry
+const ERROR_MESSAGE_ALREADY_MANAGED: &str = "The IP address is already managed";
+
+/// Is the rejection explained by the device being already registered, e.g. by a concurrent run,
+/// from the explanation given by `error_explanation`
+fn is_already_registered(explanation: &str) -> bool {
+    explanation.contains(&format!("(error {})", ERROR_CODE_ALREADY_MANAGED))
+}
+
+/// Is the error of a bulk result the one of an already registered device
+fn is_already_registered_message(message: &str) -> bool {
+    message
+        .trim()
+        .trim_end_matches('.')
+        .eq_ignore_ascii_case(ERROR_MESSAGE_ALREADY_MANAGED)
+}
+
+/// Turn an unsuccessful response into the matching error, replaced by a rejection carrying
+/// Netshot's explanation when the body gives one
+fn netshot_error_for_status(response: Response) -> ApiResult<Response> {
+    let status = response.status();
+    let error = match ApiError::from_status(status) {
+        Some(error) => error,
+        None => return Ok(response),
+    };
+    if !matches!(error, ApiError::Status(_) | ApiError::Server(_)) {
+        return Err(error);
+    }
+    let body = response.text().unwrap_or_default();
+    match error_explanation(&body, status.is_client_error()) {
+        Some(explanation) => Err(ApiError::Rejected(format!(
+            "{} (HTTP {})",
+            explanation,
+            status.as_u16()
+        ))),
+        None => Err(error),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Device {
     pub id: u32,
     pub name: String,
     #[serde(rename = "mgmtAddress")]
     pub management_address: ManagementAddress,
     pub status: String,
+    #[serde(rename = "mgmtDomain", default)]
+    pub management_domain: Option<Domain>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,6 +234,56 @@ struct NewDevicePayload {
 
     #[serde(rename = "domainId")]
     domain_id: u32,
+
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    group_id: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comments: Option<String>,
+
+    /// The device name, Netshot naming it after its discovery otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    /// The driver of the device, only used by Netshot without auto discovery
+    #[serde(rename = "deviceType", skip_serializing_if = "Option::is_none")]
+    device_type: Option<String>,
+}
+
+impl NewDevicePayload {
+    /// The payload registering the given device, discovered by Netshot unless its driver is known
+    fn new(device: &NewDevice, domain_id: u32, group_id: Option<u32>) -> Self {
+        Self {
+            auto_discover: device.driver.is_none(),
+            ip_address: device.ip_address.clone(),
+            domain_id,
+            group_id,
+            comments: device.comments.clone(),
+            name: device.name.clone(),
+            device_type: device.driver.clone(),
+        }
+    }
+}
+
+/// A device to register: its management IP, optional free-form comments, explicit name and driver
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewDevice {
+    pub ip_address: String,
+    pub comments: Option<String>,
+    pub name: Option<String>,
+    /// The Netshot driver, such as `CiscoIOS12`, skipping the discovery of the device type
+    pub driver: Option<String>,
+}
+
+impl From<String> for NewDevice {
+    fn from(ip_address: String) -> Self {
+        NewDevice {
+            ip_address,
+            comments: None,
+            name: None,
+            driver: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,6 +291,115 @@ pub struct NewDeviceCreatedPayload {
     #[serde(rename = "id")]
     pub task_id: u32,
     pub status: String,
+    /// The ID of the registered device, 0 or absent when not known yet
+    #[serde(rename = "deviceId", default)]
+    pub device_id: Option<u32>,
+}
+
+/// The outcome of a single device of a bulk registration
+#[derive(Debug, Deserialize)]
+struct BulkDeviceCreatedPayload {
+    target: String,
+    #[serde(rename = "id", default)]
+    task_id: Option<u32>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(rename = "deviceId", default)]
+    device_id: Option<u32>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl BulkDeviceCreatedPayload {
+    fn into_result(self) -> ApiResult<NewDeviceCreatedPayload> {
+        match (self.error, self.task_id) {
+            (Some(error), _) if is_already_registered_message(&error) => {
+                log::info!(
+                    "Device {} is already registered, nothing to do",
+                    self.target
+                );
+                Ok(NewDeviceCreatedPayload::already_registered())
+            }
+            (Some(error), _) => Err(ApiError::Rejected(error)),
+            (None, Some(task_id)) => Ok(NewDeviceCreatedPayload {
+                task_id,
+                status: self.status.unwrap_or_default(),
+                device_id: self.device_id,
+            }),
+            (None, None) => Err(ApiError::Decode(format!(
+                "no task returned for {}",
+                self.target
+            ))),
+        }
+    }
+}
+
+impl Device {
+    /// Does the device belong to the given domain, unknown when Netshot doesn't tell
+    pub fn in_domain(&self, domain_id: u32) -> Option<bool> {
+        self.management_domain
+            .as_ref()
+            .map(|domain| domain.id == domain_id)
+    }
+
+    /// Is the device disabled on Netshot
+    pub fn is_disabled(&self) -> bool {
+        self.status == STATUS_DISABLED
+    }
+
+    /// The canonical management IP of the device, tolerating zero-padded IPv4 and any IPv6 notation
+    pub fn management_ip(&self) -> Result<IpAddr, Error> {
+        common::parse_ip_from_cidr(&self.management_address.ip)
+    }
+}
+
+impl NewDeviceCreatedPayload {
+    /// The no-op registration of a device Netshot already manages
+    pub fn already_registered() -> Self {
+        Self {
+            task_id: 0,
+            status: String::from(STATUS_ALREADY_REGISTERED),
+            device_id: None,
+        }
+    }
+
+    /// Was the device already registered, nothing being scheduled
+    pub fn is_already_registered(&self) -> bool {
+        self.status == STATUS_ALREADY_REGISTERED
+    }
+
+    /// The ID of the registered device, if Netshot already knows it
+    pub fn known_device_id(&self) -> Option<u32> {
+        self.device_id.filter(|id| *id != 0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NewTaskPayload {
+    #[serde(rename = "type")]
+    task_type: String,
+    device: u32,
+    comments: String,
+    #[serde(rename = "debugEnabled")]
+    debug_enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Task {
+    pub id: u32,
+    pub status: String,
+}
+
+impl Task {
+    /// Has the task reached a final status
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "SUCCESS" | "FAILURE" | "CANCELLED")
+    }
+
+    /// Has the task completed successfully
+    pub fn is_success(&self) -> bool {
+        self.status == "SUCCESS"
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,6 +407,11 @@ struct UpdateDevicePayload {
     enabled: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct RenameDevicePayload {
+    name: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceUpdatedPayload {
     pub status: String,
@@ -77,86 +430,248 @@ pub struct DeviceSearchResultPayload {
 
 impl NetshotClient {
     /// Create a client with the given authentication token
-    pub fn new(
-        url: String,
-        token: String,
-        proxy: Option<String>,
-        tls_client_certificate: Option<String>,
-        tls_client_certificate_password: Option<String>,
-    ) -> Result<Self, Error> {
+    pub fn new(url: String, token: String, options: &HttpClientOptions) -> Result<Self, Error> {
         log::debug!("Creating new Netshot client to {}", url);
         let mut http_headers = HeaderMap::new();
-        let header_value = HeaderValue::from_str(token.as_str())?;
-        http_headers.insert("X-Netshot-API-Token", header_value);
+        match options.auth_scheme {
+            AuthScheme::Token => {
+                http_headers.insert(
+                    "X-Netshot-API-Token",
+                    HeaderValue::from_str(token.as_str())?,
+                );
+            }
+            AuthScheme::Bearer => {
+                let header_value = HeaderValue::from_str(format!("Bearer {}", token).as_str())?;
+                http_headers.insert("Authorization", header_value);
+            }
+        }
         http_headers.insert("Accept", HeaderValue::from_str("application/json")?);
-        let mut http_client = reqwest::blocking::Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .timeout(Duration::from_secs(5))
-            .default_headers(http_headers);
-
-        http_client = match proxy {
-            Some(p) => http_client.proxy(Proxy::all(p)?),
-            None => http_client,
-        };
-
-        http_client = match tls_client_certificate {
-            Some(c) => http_client.identity(build_identity_from_file(
-                c,
-                tls_client_certificate_password,
-            )?),
-            None => http_client,
-        };
+        let http_client = options.builder()?.default_headers(http_headers);
 
         Ok(Self {
             url,
             token,
             client: http_client.build()?,
+            retry_policy: RetryPolicy::default(),
+            api_base: String::from(DEFAULT_API_BASE),
+            device_cache: Mutex::new(None),
+            etag_cache: Arc::new(EtagCache::default()),
+            bulk_unsupported: AtomicBool::new(false),
         })
     }
 
-    /// To be implemented server side, always return true for now
-    pub fn ping(&self) -> Result<bool, Error> {
-        log::warn!("Not health check implemented on Netshot, ping will always succeed");
-        Ok(true)
+    /// Use the given retry policy for the requests sent by this client
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Keep the ETags of the device lists in the given cache, to share it with other clients
+    pub fn with_etag_cache(mut self, etag_cache: Arc<EtagCache>) -> Self {
+        self.etag_cache = etag_cache;
+        self
+    }
+
+    /// Send the requests to the API mounted under the given path, with or without its slashes
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        let api_base = api_base.trim().trim_matches('/');
+        self.api_base = if api_base.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", api_base)
+        };
+        self
+    }
+
+    /// The URL of the given API path
+    fn api_url(&self, path: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.url.trim_end_matches('/'),
+            self.api_base,
+            path
+        )
+    }
+
+    /// Check that the API URLs are valid, before sending any request
+    pub fn check_api_url(&self) -> Result<(), Error> {
+        let url = self.api_url(PATH_DEVICES);
+        if self.api_base.contains(['?', '#']) || self.api_base.contains("://") {
+            return Err(anyhow!(
+                "The Netshot API base must be a path, not {}",
+                self.api_base
+            ));
+        }
+        let parsed = reqwest::Url::parse(&url)
+            .map_err(|error| anyhow!("Invalid Netshot API URL {}: {}", url, error))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(anyhow!(
+                "Invalid Netshot API URL {}: expected an http(s) URL",
+                url
+            ));
+        }
+        Ok(())
+    }
+
+    /// Ping the service through the domains listing, which requires a valid token
+    pub fn ping(&self) -> ApiResult<()> {
+        let url = self.api_url(PATH_DOMAINS);
+        log::debug!("Pinging {}", url);
+        let response = self.retry_policy.send(self.client.get(url), true)?;
+        log::debug!("Ping response: {}", response.status());
+        error_for_status(response)?;
+        Ok(())
+    }
+
+    /// Get the domains defined in Netshot
+    pub fn get_domains(&self) -> ApiResult<Vec<Domain>> {
+        let url = self.api_url(PATH_DOMAINS);
+        let response = error_for_status(self.retry_policy.send(self.client.get(url), true)?)?;
+        let domains: Vec<Domain> = response.json()?;
+
+        log::debug!("Got {} domains from Netshot", domains.len());
+
+        Ok(domains)
     }
 
-    /// Get devices registered in Netshot
-    pub fn get_devices(&self,
-                       domain_id: u32,
-    ) -> Result<Vec<Device>, Error> {
-        let url = format!("{}{}?group={}", self.url, PATH_DEVICES, domain_id);
-        let devices: Vec<Device> = self.client.get(url).send()?.json()?;
+    /// Get the device groups defined in Netshot
+    pub fn get_groups(&self) -> ApiResult<Vec<Group>> {
+        let url = self.api_url(PATH_GROUPS);
+        let response = error_for_status(self.retry_policy.send(self.client.get(url), true)?)?;
+        let groups: Vec<Group> = response.json()?;
+
+        log::debug!("Got {} groups from Netshot", groups.len());
+
+        Ok(groups)
+    }
+
+    /// Get devices registered in Netshot, the list being revalidated with `If-None-Match` when
+    /// Netshot returned an ETag for it before
+    pub fn get_devices(&self, domain_id: u32) -> ApiResult<Vec<Device>> {
+        let url = format!("{}?group={}", self.api_url(PATH_DEVICES), domain_id);
+        let cached = self.etag_cache.get(&url);
+        let mut request = self.client.get(&url);
+        if let Some((etag, _)) = &cached {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        let response = self.retry_policy.send(request, true)?;
+        if let (StatusCode::NOT_MODIFIED, Some((_, devices))) = (response.status(), cached) {
+            log::debug!(
+                "The {} devices of Netshot domain {} are unchanged",
+                devices.len(),
+                domain_id
+            );
+            return Ok(devices);
+        }
+
+        let response = error_for_status(response)?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let devices: Vec<Device> = response.json()?;
+        self.etag_cache.store(&url, etag, &devices);
 
         log::debug!("Got {} devices from Netshot", devices.len());
 
         Ok(devices)
     }
 
+    /// Get devices registered in Netshot that belong to the given domain, keeping the ones
+    /// whose domain isn't known
+    pub fn get_devices_in_domain(&self, domain_id: u32) -> ApiResult<Vec<Device>> {
+        let mut devices = self.get_devices(domain_id)?;
+        devices.retain(|device| device.in_domain(domain_id).unwrap_or(true));
+        log::debug!(
+            "Kept {} devices from Netshot domain {}",
+            devices.len(),
+            domain_id
+        );
+        Ok(devices)
+    }
+
+    /// Find the device with the given management IP, using the search API when available and
+    /// a cached full device list otherwise
+    pub fn get_device_by_ip(&self, ip: IpAddr) -> ApiResult<Option<Device>> {
+        match self.search_device(format!("[IP] IS {}", ip)) {
+            Ok(result) => return Ok(result.devices.into_iter().next()),
+            Err(error @ ApiError::NotFound) | Err(error @ ApiError::Status(_)) => {
+                log::debug!(
+                    "Device search unavailable ({}), looking {} up in the full device list",
+                    error,
+                    ip
+                );
+            }
+            Err(error) => return Err(error),
+        }
+
+        let mut cache = self.device_cache.lock().unwrap();
+        if cache.is_none() {
+            let url = self.api_url(PATH_DEVICES);
+            let response = error_for_status(self.retry_policy.send(self.client.get(url), true)?)?;
+            *cache = Some(response.json()?);
+        }
+
+        Ok(cache.as_ref().and_then(|devices| {
+            devices
+                .iter()
+                .find(|device| device.management_ip().ok() == Some(ip))
+                .cloned()
+        }))
+    }
+
     /// Register a given IP into Netshot and return the corresponding device
     pub fn register_device(
         &self,
         ip_address: String,
         domain_id: u32,
-    ) -> Result<NewDeviceCreatedPayload, Error> {
+    ) -> ApiResult<NewDeviceCreatedPayload> {
+        self.register_device_in_group(ip_address, domain_id, None)
+    }
+
+    /// Register a given IP into Netshot, optionally assigning it to a device group
+    pub fn register_device_in_group(
+        &self,
+        ip_address: String,
+        domain_id: u32,
+        group_id: Option<u32>,
+    ) -> ApiResult<NewDeviceCreatedPayload> {
+        self.register_new_device(&NewDevice::from(ip_address), domain_id, group_id)
+    }
+
+    /// Register a device into Netshot with its comments, optionally assigning it to a device group
+    pub fn register_new_device(
+        &self,
+        device: &NewDevice,
+        domain_id: u32,
+        group_id: Option<u32>,
+    ) -> ApiResult<NewDeviceCreatedPayload> {
+        let ip_address = &device.ip_address;
         log::info!("Registering new device with IP {}", ip_address);
 
-        let new_device = NewDevicePayload {
-            auto_discover: true,
-            ip_address: ip_address.clone(),
-            domain_id,
-        };
+        let new_device = NewDevicePayload::new(device, domain_id, group_id);
 
-        let url = format!("{}{}", self.url, PATH_DEVICES);
-        let response = self.client.post(url).json(&new_device).send()?;
+        let url = self.api_url(PATH_DEVICES);
+        let response = self
+            .retry_policy
+            .send(self.client.post(url).json(&new_device), false)?;
 
-        if !response.status().is_success() {
-            log::warn!(
-                "Failed to register new device {}, got status {}",
-                ip_address,
-                response.status().to_string()
-            );
-            return Err(anyhow!("Failed to register new device {}", ip_address));
-        }
+        let response = match netshot_error_for_status(response) {
+            Ok(response) => response,
+            Err(ApiError::Rejected(explanation)) if is_already_registered(&explanation) => {
+                log::info!(
+                    "Device {} is already registered ({}), nothing to do",
+                    ip_address,
+                    explanation
+                );
+                return Ok(NewDeviceCreatedPayload::already_registered());
+            }
+            Err(error) => {
+                log::warn!("Failed to register new device {}: {}", ip_address, error);
+                return Err(error);
+            }
+        };
 
         let device_registration: NewDeviceCreatedPayload = response.json()?;
         log::debug!(
@@ -168,27 +683,187 @@ impl NetshotClient {
         Ok(device_registration)
     }
 
+    /// Register the given devices into Netshot, sending them in bulk requests of `batch_size` devices.
+    ///
+    /// Returns one result per IP, in the same order. When the server rejects a bulk request,
+    /// its devices are registered one by one, and so are the following batches if the bulk
+    /// endpoint isn't supported.
+    pub fn register_devices(
+        &self,
+        devices: &[NewDevice],
+        domain_id: u32,
+        group_id: Option<u32>,
+        batch_size: usize,
+    ) -> Vec<(String, ApiResult<NewDeviceCreatedPayload>)> {
+        let mut results = Vec::with_capacity(devices.len());
+        for batch in devices.chunks(batch_size.max(1)) {
+            if !self.bulk_unsupported.load(Ordering::SeqCst) {
+                match self.register_batch(batch, domain_id, group_id) {
+                    Ok(batch_results) => {
+                        let ips = batch.iter().map(|device| device.ip_address.clone());
+                        results.extend(ips.zip(batch_results));
+                        continue;
+                    }
+                    Err(error) => {
+                        log::warn!(
+                            "Bulk registration of {} devices failed ({}), registering them one by one",
+                            batch.len(),
+                            error
+                        );
+                    }
+                }
+            }
+            for device in batch {
+                let result = self.register_new_device(device, domain_id, group_id);
+                results.push((device.ip_address.clone(), result));
+            }
+        }
+        results
+    }
+
+    /// Send a single bulk registration request, returning one result per IP
+    fn register_batch(
+        &self,
+        devices: &[NewDevice],
+        domain_id: u32,
+        group_id: Option<u32>,
+    ) -> ApiResult<Vec<ApiResult<NewDeviceCreatedPayload>>> {
+        log::info!("Registering {} new devices in bulk", devices.len());
+        let new_devices: Vec<NewDevicePayload> = devices
+            .iter()
+            .map(|device| NewDevicePayload::new(device, domain_id, group_id))
+            .collect();
+
+        let url = self.api_url(PATH_DEVICES_BULK);
+        let response = self
+            .retry_policy
+            .send(self.client.post(url).json(&new_devices), false)?;
+        // Only a missing endpoint disables the bulk requests, not the rejection of a batch
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            self.bulk_unsupported.store(true, Ordering::SeqCst);
+        }
+        let response = netshot_error_for_status(response)?;
+        let mut payloads: Vec<BulkDeviceCreatedPayload> = response.json()?;
+
+        Ok(devices
+            .iter()
+            .map(|device| &device.ip_address)
+            .map(|ip_address| {
+                match payloads
+                    .iter()
+                    .position(|payload| &payload.target == ip_address)
+                {
+                    Some(index) => payloads.swap_remove(index).into_result(),
+                    None => Err(ApiError::Decode(format!(
+                        "no result returned for {}",
+                        ip_address
+                    ))),
+                }
+            })
+            .collect())
+    }
+
+    /// Schedule a snapshot (configuration fetch) of the given device
+    pub fn trigger_snapshot(&self, device_id: u32) -> ApiResult<Task> {
+        log::info!("Scheduling snapshot of device {}", device_id);
+
+        let new_task = NewTaskPayload {
+            task_type: String::from(".TakeSnapshotTask"),
+            device: device_id,
+            comments: String::from("Snapshot requested by netbox2netshot"),
+            debug_enabled: false,
+        };
+
+        let url = self.api_url(PATH_TASKS);
+        let response = self
+            .retry_policy
+            .send(self.client.post(url).json(&new_task), false)?;
+
+        let response = match netshot_error_for_status(response) {
+            Ok(response) => response,
+            Err(error) => {
+                log::warn!(
+                    "Failed to schedule snapshot of device {}: {}",
+                    device_id,
+                    error
+                );
+                return Err(error);
+            }
+        };
+
+        let task: Task = response.json()?;
+        log::debug!(
+            "Snapshot of device {} scheduled with task ID {}",
+            device_id,
+            task.id
+        );
+
+        Ok(task)
+    }
+
+    /// Get the given task, to follow its status
+    pub fn get_task(&self, task_id: u32) -> ApiResult<Task> {
+        let url = format!("{}/{}", self.api_url(PATH_TASKS), task_id);
+        let response = error_for_status(self.retry_policy.send(self.client.get(url), true)?)?;
+        let task: Task = response.json()?;
+        log::debug!("Task {} is {}", task.id, task.status);
+        Ok(task)
+    }
+
+    /// Poll the given task until it is finished, backing off between the polls, failing when it
+    /// doesn't succeed or isn't finished by the deadline
+    pub fn wait_for_task(&self, task_id: u32, deadline: Instant) -> ApiResult<Task> {
+        let mut delay = TASK_POLL_INITIAL_DELAY;
+        loop {
+            let task = self.get_task(task_id)?;
+            if task.is_finished() {
+                if task.is_success() {
+                    return Ok(task);
+                }
+                return Err(ApiError::Rejected(format!(
+                    "task {} ended with status {}",
+                    task_id, task.status
+                )));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ApiError::Timeout(format!(
+                    "task {} still {}",
+                    task_id, task.status
+                )));
+            }
+            thread::sleep(delay.min(deadline - now));
+            delay = (delay * 2).min(TASK_POLL_MAX_DELAY);
+        }
+    }
+
     /// Search for a device
-    pub fn search_device(&self, query_string: String) -> Result<DeviceSearchResultPayload, Error> {
-        let url = format!("{}{}", self.url, PATH_DEVICES_SEARCH);
+    pub fn search_device(&self, query_string: String) -> ApiResult<DeviceSearchResultPayload> {
+        let url = self.api_url(PATH_DEVICES_SEARCH);
 
         let query = DeviceSearchQueryPayload {
             query: query_string.clone(),
         };
 
-        let response = self.client.post(url).json(&query).send()?;
+        let response = self
+            .retry_policy
+            .send(self.client.post(url).json(&query), true)?;
 
-        if !response.status().is_success() {
-            log::warn!(
-                "Failed to search for device with query `{}`: {}",
-                query_string.clone(),
-                response.status().to_string()
-            );
-            return Err(anyhow!(
-                "Failed to search for device with query: {}",
-                query_string
-            ));
-        }
+        let response = match netshot_error_for_status(response) {
+            Ok(response) => response,
+            Err(error) => {
+                log::warn!(
+                    "Failed to search for device with query `{}`: {}",
+                    query_string.clone(),
+                    error
+                );
+                return Err(error);
+            }
+        };
 
         let search_result: DeviceSearchResultPayload = response.json()?;
         log::debug!(
@@ -204,28 +879,28 @@ impl NetshotClient {
         &self,
         ip_address: String,
         enabled: bool,
-    ) -> Result<Option<DeviceUpdatedPayload>, Error> {
+    ) -> ApiResult<Option<DeviceUpdatedPayload>> {
         log::info!(
             "Setting device with IP {} to enabled={}",
             ip_address,
             enabled
         );
 
-        let state = UpdateDevicePayload { enabled: enabled };
+        let state = UpdateDevicePayload { enabled };
 
         // Search for the device ID
         let response = self.search_device(format!("[IP] IS {}", ip_address))?;
-        let device = response.devices.first().unwrap();
+        let device = response.devices.first().ok_or(ApiError::NotFound)?;
 
-        if !enabled && device.status == "DISABLED" {
-            log::warn!(
+        if !enabled && device.is_disabled() {
+            log::info!(
                 "Device {}({}) is already disabled, skipping",
                 device.name,
                 ip_address
             );
             return Ok(Option::None);
-        } else if enabled && device.status != "DISABLED" {
-            log::warn!(
+        } else if enabled && !device.is_disabled() {
+            log::info!(
                 "Device {}({}) is already enabled, skipping",
                 device.name,
                 ip_address
@@ -233,21 +908,22 @@ impl NetshotClient {
             return Ok(Option::None);
         }
 
-        let url = format!("{}{}/{}", self.url, PATH_DEVICES, device.id);
-        let response = self.client.put(url).json(&state).send()?;
+        let url = format!("{}/{}", self.api_url(PATH_DEVICES), device.id);
+        let response = self
+            .retry_policy
+            .send(self.client.put(url).json(&state), true)?;
 
-        if !response.status().is_success() {
-            log::warn!(
-                "Failed to update state for device {}, got status {}",
-                ip_address,
-                response.status().to_string()
-            );
-            return Err(anyhow!(
-                "Failed to update state for device {}, got status {}",
-                ip_address,
-                response.status().to_string()
-            ));
-        }
+        let response = match netshot_error_for_status(response) {
+            Ok(response) => response,
+            Err(error) => {
+                log::warn!(
+                    "Failed to update state for device {}: {}",
+                    ip_address,
+                    error
+                );
+                return Err(error);
+            }
+        };
 
         let device_update: DeviceUpdatedPayload = response.json()?;
         log::debug!("Device state of {} set to enabled={}", ip_address, enabled);
@@ -256,17 +932,54 @@ impl NetshotClient {
     }
 
     /// Disable a given device
-    pub fn disable_device(
-        &self,
-        ip_address: String,
-    ) -> Result<Option<DeviceUpdatedPayload>, Error> {
+    pub fn disable_device(&self, ip_address: String) -> ApiResult<Option<DeviceUpdatedPayload>> {
         self.set_device_enabled(ip_address, false)
     }
 
     /// Enable a given device
-    pub fn enable_device(&self, ip_address: String) -> Result<Option<DeviceUpdatedPayload>, Error> {
+    pub fn enable_device(&self, ip_address: String) -> ApiResult<Option<DeviceUpdatedPayload>> {
         self.set_device_enabled(ip_address, true)
     }
+
+    /// Delete the device with the given ID
+    pub fn delete_device(&self, device_id: u32) -> ApiResult<()> {
+        log::info!("Deleting device {}", device_id);
+
+        let url = format!("{}/{}", self.api_url(PATH_DEVICES), device_id);
+        let response = self.retry_policy.send(self.client.delete(url), true)?;
+
+        if let Err(error) = netshot_error_for_status(response) {
+            log::warn!("Failed to delete device {}: {}", device_id, error);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Rename the device with the given ID
+    pub fn update_device_name(
+        &self,
+        device_id: u32,
+        name: String,
+    ) -> ApiResult<DeviceUpdatedPayload> {
+        log::info!("Renaming device {} to {}", device_id, name);
+
+        let url = format!("{}/{}", self.api_url(PATH_DEVICES), device_id);
+        let response = self.retry_policy.send(
+            self.client.put(url).json(&RenameDevicePayload { name }),
+            true,
+        )?;
+
+        let response = match netshot_error_for_status(response) {
+            Ok(response) => response,
+            Err(error) => {
+                log::warn!("Failed to rename device {}: {}", device_id, error);
+                return Err(error);
+            }
+        };
+
+        Ok(response.json()?)
+    }
 }
 
 #[cfg(test)]
@@ -274,25 +987,135 @@ mod tests {
     use super::*;
     use mockito;
 
+    /// The path of the given API path under the default API base
+    fn api_path(path: &str) -> String {
+        format!("{}{}", DEFAULT_API_BASE, path)
+    }
+
     #[test]
     fn authenticated_initialization() {
         let url = mockito::server_url();
         let token = String::from("hello");
-        let client = NetshotClient::new(url.clone(), token.clone(), None, None, None).unwrap();
+        let client =
+            NetshotClient::new(url.clone(), token.clone(), &HttpClientOptions::default()).unwrap();
         assert_eq!(client.token, token);
         assert_eq!(client.url, url);
     }
 
     #[test]
+    fn bearer_authentication() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_DEVICES).as_str())
+            .match_query(mockito::Matcher::Any)
+            .match_header("Authorization", "Bearer hello")
+            .match_header("X-Netshot-API-Token", mockito::Matcher::Missing)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .create();
+
+        let options = HttpClientOptions {
+            auth_scheme: AuthScheme::Bearer,
+            ..Default::default()
+        };
+        let client = NetshotClient::new(url, String::from("hello"), &options).unwrap();
+        assert_eq!(client.get_devices(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn etag_revalidation() {
+        let url = mockito::server_url();
+        let devices_path = api_path(PATH_DEVICES);
+
+        let full = mockito::mock("GET", devices_path.as_str())
+            .match_query(mockito::Matcher::UrlEncoded("group".into(), "42".into()))
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_header("etag", "\"v1\"")
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .expect(1)
+            .create();
+        let unchanged = mockito::mock("GET", devices_path.as_str())
+            .match_query(mockito::Matcher::UrlEncoded("group".into(), "42".into()))
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .expect(1)
+            .create();
+        let without_etag = mockito::mock("GET", devices_path.as_str())
+            .match_query(mockito::Matcher::UrlEncoded("group".into(), "43".into()))
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .expect(2)
+            .create();
+
+        // The cache outlives the client, like in daemon mode
+        let etag_cache = Arc::new(EtagCache::default());
+        for _ in 0..2 {
+            let client =
+                NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default())
+                    .unwrap()
+                    .with_etag_cache(Arc::clone(&etag_cache));
+            let devices = client.get_devices(42).unwrap();
+            assert_eq!(devices.len(), 1);
+            assert_eq!(devices[0].name, "test-device");
+            assert_eq!(client.get_devices(43).unwrap().len(), 1);
+        }
+
+        full.assert();
+        unchanged.assert();
+        without_etag.assert();
+    }
+
+    #[test]
+    fn custom_api_base() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", "/netshot/api/v1/domains")
+            .with_body_from_file("tests/data/netshot/domains.json")
+            .create();
+
+        let client = NetshotClient::new(url, String::new(), &HttpClientOptions::default())
+            .unwrap()
+            .with_api_base("netshot/api/v1/");
+        assert_eq!(client.api_base, "/netshot/api/v1");
+        assert!(client.check_api_url().is_ok());
+        assert!(!client.get_domains().unwrap().is_empty());
+
+        assert_eq!(client.with_api_base("/").api_base, "");
+        let client = NetshotClient::new(
+            String::from("netshot.example.org"),
+            String::new(),
+            &HttpClientOptions::default(),
+        )
+        .unwrap();
+        assert!(client.check_api_url().is_err());
+        assert!(client.with_api_base("/api?x=1").check_api_url().is_err());
+    }
+
+    #[test]
+    fn failed_ping() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_DOMAINS).as_str())
+            .with_status(401)
+            .create();
+
+        let client = NetshotClient::new(url, String::from("bad"), &Default::default()).unwrap();
+        let error = client.ping().unwrap_err();
+        assert!(matches!(error, ApiError::Unauthorized(401)));
+        assert!(error.hint().contains("token"));
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
     fn single_good_device() {
         let url = mockito::server_url();
 
-        let _mock = mockito::mock("GET", PATH_DEVICES)
+        let _mock = mockito::mock("GET", api_path(PATH_DEVICES).as_str())
             .match_query(mockito::Matcher::Any)
             .with_body_from_file("tests/data/netshot/single_good_device.json")
             .create();
 
-        let client = NetshotClient::new(url.clone(), String::new(), None, None, None).unwrap();
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
         let devices = client.get_devices(1).unwrap();
 
         assert_eq!(devices.len(), 1);
@@ -308,30 +1131,423 @@ mod tests {
     fn good_device_registration() {
         let url = mockito::server_url();
 
-        let _mock = mockito::mock("POST", PATH_DEVICES)
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
             .match_query(mockito::Matcher::Any)
             .match_body(r#"{"autoDiscover":true,"ipAddress":"1.2.3.4","domainId":2}"#)
             .with_body_from_file("tests/data/netshot/good_device_registration.json")
             .create();
 
-        let client = NetshotClient::new(url.clone(), String::new(), None, None, None).unwrap();
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
         let registration = client.register_device(String::from("1.2.3.4"), 2).unwrap();
 
         assert_eq!(registration.task_id, 504);
         assert_eq!(registration.status, "SCHEDULED");
     }
 
+    #[test]
+    fn known_device_registration() {
+        let url = mockito::server_url();
+
+        let _new = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"ipAddress":"1.2.3.4"}"#.to_string(),
+            ))
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .create();
+        let _known = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"ipAddress":"1.2.3.5"}"#.to_string(),
+            ))
+            .with_body_from_file("tests/data/netshot/known_device_registration.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+
+        let registration = client.register_device(String::from("1.2.3.4"), 2).unwrap();
+        assert_eq!(registration.known_device_id(), None);
+
+        let registration = client.register_device(String::from("1.2.3.5"), 2).unwrap();
+        assert_eq!(registration.known_device_id(), Some(12));
+    }
+
+    #[test]
+    fn device_registration_with_driver() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .match_query(mockito::Matcher::Any)
+            .match_body(r#"{"autoDiscover":false,"ipAddress":"1.2.3.4","domainId":2,"deviceType":"CiscoIOS12"}"#)
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(1)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let device = NewDevice {
+            driver: Some(String::from("CiscoIOS12")),
+            ..NewDevice::from(String::from("1.2.3.4"))
+        };
+        let registration = client.register_new_device(&device, 2, None).unwrap();
+
+        _mock.assert();
+        assert_eq!(registration.task_id, 504);
+    }
+
+    #[test]
+    fn unauthorized_device_registration() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_status(403)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let error = client
+            .register_device(String::from("1.2.3.4"), 2)
+            .unwrap_err();
+
+        assert!(matches!(error, ApiError::Unauthorized(403)));
+    }
+
+    #[test]
+    fn malformed_devices_list() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_DEVICES).as_str())
+            .match_query(mockito::Matcher::Any)
+            .with_body("<html>Maintenance</html>")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let error = client.get_devices(1).unwrap_err();
+
+        assert_eq!(error.category(), "decode");
+    }
+
+    #[test]
+    fn domains_list() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_DOMAINS).as_str())
+            .with_body_from_file("tests/data/netshot/domains.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let domains = client.get_domains().unwrap();
+
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[1].id, 2);
+        assert_eq!(domains[1].name, "DC1");
+    }
+
+    #[test]
+    fn domain_id_by_name() {
+        let domains = vec![
+            Domain {
+                id: 1,
+                name: String::from("Default"),
+            },
+            Domain {
+                id: 2,
+                name: String::from("DC1"),
+            },
+            Domain {
+                id: 3,
+                name: String::from("DC1"),
+            },
+        ];
+
+        assert_eq!(find_domain_id(&domains, "Default").unwrap(), 1);
+        assert!(find_domain_id(&domains, "DC2").is_err());
+        let error = find_domain_id(&domains, "DC1").unwrap_err();
+        assert!(error.to_string().contains("2, 3"));
+
+        assert!(check_domain_ids(&domains, &[1, 3]).is_ok());
+        let error = check_domain_ids(&domains, &[1, 7]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No Netshot domain with ID 7, the valid domains are: 1 (Default), 2 (DC1), 3 (DC1)"
+        );
+    }
+
+    #[test]
+    fn groups_list() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_GROUPS).as_str())
+            .with_body_from_file("tests/data/netshot/groups.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let groups = client.get_groups().unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].id, 3);
+        assert_eq!(groups[0].name, "Core routers");
+    }
+
+    #[test]
+    fn devices_in_domain() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("GET", api_path(PATH_DEVICES).as_str())
+            .match_query(mockito::Matcher::Any)
+            .with_body_from_file("tests/data/netshot/multi_domain_devices.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+
+        assert_eq!(client.get_devices(1).unwrap().len(), 3);
+        let devices = client.get_devices_in_domain(1).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert!(devices
+            .iter()
+            .all(|device| device.in_domain(1) == Some(true)));
+    }
+
+    #[test]
+    fn device_by_ip_found() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES_SEARCH).as_str())
+            .match_body(r#"{"query":"[IP] IS 1.2.3.4"}"#)
+            .with_body_from_file("tests/data/netshot/search.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let device = client
+            .get_device_by_ip("1.2.3.4".parse().unwrap())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(device.id, 2318);
+        assert_eq!(device.name, "test-device.dc");
+    }
+
+    #[test]
+    fn device_by_ip_not_found() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES_SEARCH).as_str())
+            .with_body_from_file("tests/data/netshot/search_empty.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let device = client.get_device_by_ip("1.2.3.5".parse().unwrap()).unwrap();
+
+        assert!(device.is_none());
+    }
+
+    #[test]
+    fn device_by_ip_without_search_api() {
+        let url = mockito::server_url();
+
+        let _search = mockito::mock("POST", api_path(PATH_DEVICES_SEARCH).as_str())
+            .with_status(404)
+            .create();
+        let list = mockito::mock("GET", api_path(PATH_DEVICES).as_str())
+            .with_body_from_file("tests/data/netshot/single_good_device.json")
+            .expect(1)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let found = client.get_device_by_ip("1.2.3.4".parse().unwrap()).unwrap();
+        let missing = client.get_device_by_ip("1.2.3.5".parse().unwrap()).unwrap();
+
+        assert_eq!(found.unwrap().name, "test-device");
+        assert!(missing.is_none());
+        list.assert();
+    }
+
+    #[test]
+    fn trigger_snapshot() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_TASKS).as_str())
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"type":".TakeSnapshotTask","device":2318}"#.to_string(),
+            ))
+            .with_body_from_file("tests/data/netshot/snapshot_task.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let task = client.trigger_snapshot(2318).unwrap();
+
+        assert_eq!(task.id, 505);
+        assert_eq!(task.status, "SCHEDULED");
+    }
+
+    #[test]
+    fn wait_for_task() {
+        let url = mockito::server_url();
+
+        let _done = mockito::mock(
+            "GET",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_TASKS, 505).as_str(),
+        )
+        .with_body(r#"{"id": 505, "status": "SUCCESS"}"#)
+        .create();
+        let _failed = mockito::mock(
+            "GET",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_TASKS, 506).as_str(),
+        )
+        .with_body(r#"{"id": 506, "status": "FAILURE"}"#)
+        .create();
+        let _running = mockito::mock(
+            "GET",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_TASKS, 507).as_str(),
+        )
+        .with_body(r#"{"id": 507, "status": "RUNNING"}"#)
+        .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let deadline = Instant::now();
+
+        assert!(client.wait_for_task(505, deadline).unwrap().is_success());
+        assert!(matches!(
+            client.wait_for_task(506, deadline).unwrap_err(),
+            ApiError::Rejected(_)
+        ));
+        assert!(matches!(
+            client.wait_for_task(507, deadline).unwrap_err(),
+            ApiError::Timeout(_)
+        ));
+    }
+
+    #[test]
+    fn bulk_registration() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES_BULK).as_str())
+            .match_body(r#"[{"autoDiscover":true,"ipAddress":"1.2.3.4","domainId":2},{"autoDiscover":true,"ipAddress":"1.2.3.5","domainId":2,"comments":"core,critical","name":"dc1-sw5"}]"#)
+            .with_body_from_file("tests/data/netshot/bulk_registration.json")
+            .expect(1)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let devices = vec![
+            NewDevice::from(String::from("1.2.3.4")),
+            NewDevice {
+                ip_address: String::from("1.2.3.5"),
+                comments: Some(String::from("core,critical")),
+                name: Some(String::from("dc1-sw5")),
+                driver: None,
+            },
+        ];
+        let results = client.register_devices(&devices, 2, None, 50);
+
+        _mock.assert();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "1.2.3.4");
+        assert_eq!(results[0].1.as_ref().unwrap().task_id, 504);
+        assert!(matches!(results[1].1, Err(ApiError::Rejected(_))));
+    }
+
+    #[test]
+    fn bulk_registration_fallback() {
+        let url = mockito::server_url();
+
+        let bulk = mockito::mock("POST", api_path(PATH_DEVICES_BULK).as_str())
+            .with_status(404)
+            .expect(1)
+            .create();
+        let single = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(3)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let devices: Vec<NewDevice> = ["1.2.3.4", "1.2.3.5", "1.2.3.6"]
+            .iter()
+            .map(|ip| NewDevice::from(ip.to_string()))
+            .collect();
+        let results = client.register_devices(&devices, 2, None, 2);
+
+        bulk.assert();
+        single.assert();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+    }
+
+    #[test]
+    fn rejected_bulk_registration() {
+        let url = mockito::server_url();
+
+        let bulk = mockito::mock("POST", api_path(PATH_DEVICES_BULK).as_str())
+            .with_status(400)
+            .with_body(r#"{"errorMsg": "Invalid IP address", "errorCode": 100}"#)
+            .expect(3)
+            .create();
+        let single = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .expect(2)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let devices: Vec<NewDevice> = ["1.2.3.4", "1.2.3.5", "1.2.3.6"]
+            .iter()
+            .map(|ip| NewDevice::from(ip.to_string()))
+            .collect();
+
+        let error = client.register_batch(&devices[..1], 2, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "rejected: Invalid IP address (error 100) (HTTP 400)"
+        );
+        let results = client.register_devices(&devices[1..], 2, None, 1);
+
+        bulk.assert();
+        single.assert();
+        assert_eq!(results.len(), 2);
+        assert!(!client.bulk_unsupported.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn good_device_registration_in_group() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .match_query(mockito::Matcher::Any)
+            .match_body(r#"{"autoDiscover":true,"ipAddress":"1.2.3.4","domainId":2,"groupId":7}"#)
+            .with_body_from_file("tests/data/netshot/good_device_registration.json")
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let registration = client
+            .register_device_in_group(String::from("1.2.3.4"), 2, Some(7))
+            .unwrap();
+
+        assert_eq!(registration.task_id, 504);
+    }
+
     #[test]
     fn search_devices() {
         let url = mockito::server_url();
 
-        let _mock = mockito::mock("POST", PATH_DEVICES_SEARCH)
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES_SEARCH).as_str())
             .match_query(mockito::Matcher::Any)
             .match_body(r#"{"query":"[IP] IS 1.2.3.4"}"#)
             .with_body_from_file("tests/data/netshot/search.json")
             .create();
 
-        let client = NetshotClient::new(url.clone(), String::new(), None, None, None).unwrap();
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
         let result = client
             .search_device(String::from("[IP] IS 1.2.3.4"))
             .unwrap();
@@ -344,21 +1560,171 @@ mod tests {
     fn disable_device() {
         let url = mockito::server_url();
 
-        let _mock = mockito::mock("PUT", format!("{}/{}", PATH_DEVICES, 2318).as_str())
-            .match_query(mockito::Matcher::Any)
-            .match_body(r#"{"enabled":false}"#)
-            .with_body_from_file("tests/data/netshot/disable_device.json")
-            .create();
+        let _mock = mockito::mock(
+            "PUT",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_DEVICES, 2318).as_str(),
+        )
+        .match_query(mockito::Matcher::Any)
+        .match_body(r#"{"enabled":false}"#)
+        .with_body_from_file("tests/data/netshot/disable_device.json")
+        .create();
 
-        let _mock2 = mockito::mock("POST", PATH_DEVICES_SEARCH)
+        let _mock2 = mockito::mock("POST", api_path(PATH_DEVICES_SEARCH).as_str())
             .match_query(mockito::Matcher::Any)
             .match_body(r#"{"query":"[IP] IS 1.2.3.4"}"#)
             .with_body_from_file("tests/data/netshot/search.json")
             .create();
 
-        let client = NetshotClient::new(url.clone(), String::new(), None, None, None).unwrap();
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
         let registration = client.disable_device(String::from("1.2.3.4")).unwrap();
 
         assert_eq!(registration.unwrap().status, "DISABLED");
     }
+
+    #[test]
+    fn registration_error_explanation() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_status(400)
+            .with_body(r#"{"errorMsg": "Invalid domain ID", "errorCode": 110}"#)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let error = client
+            .register_device(String::from("1.2.3.4"), 2)
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "rejected: Invalid domain ID (error 110) (HTTP 400)"
+        );
+    }
+
+    #[test]
+    fn already_registered_device() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_status(400)
+            .with_body(r#"{"errorMsg": "The IP address is already managed", "errorCode": 131}"#)
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let registration = client.register_device(String::from("1.2.3.4"), 2).unwrap();
+
+        assert!(registration.is_already_registered());
+        assert!(registration.known_device_id().is_none());
+    }
+
+    #[test]
+    fn other_rejections_are_failures() {
+        let url = mockito::server_url();
+
+        let _mock = mockito::mock("POST", api_path(PATH_DEVICES).as_str())
+            .with_status(400)
+            .with_body(
+                r#"{"errorMsg": "A device already exists in another domain", "errorCode": 150}"#,
+            )
+            .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let error = client
+            .register_device(String::from("1.2.3.4"), 2)
+            .unwrap_err();
+        assert!(matches!(error, ApiError::Rejected(_)));
+
+        let bulk_result = |error: &str| {
+            BulkDeviceCreatedPayload {
+                target: String::from("1.2.3.4"),
+                task_id: None,
+                status: None,
+                device_id: None,
+                error: Some(error.to_string()),
+            }
+            .into_result()
+        };
+        assert!(bulk_result("The IP address is already managed.")
+            .unwrap()
+            .is_already_registered());
+        assert!(matches!(
+            bulk_result("Task already running"),
+            Err(ApiError::Rejected(_))
+        ));
+    }
+
+    #[test]
+    fn error_explanations() {
+        assert_eq!(
+            error_explanation(r#"{"errorMsg": "Invalid domain"}"#, false).unwrap(),
+            "Invalid domain"
+        );
+        assert_eq!(
+            error_explanation("  Bad request  ", true).unwrap(),
+            "Bad request"
+        );
+        assert_eq!(
+            error_explanation("x".repeat(500).as_str(), true)
+                .unwrap()
+                .len(),
+            200
+        );
+        // Raw bodies of server errors are usually proxy pages, not explanations
+        assert!(error_explanation("<html>Bad gateway</html>", false).is_none());
+        assert!(error_explanation("", true).is_none());
+    }
+
+    #[test]
+    fn delete_device() {
+        let url = mockito::server_url();
+
+        let mock = mockito::mock(
+            "DELETE",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_DEVICES, 2318).as_str(),
+        )
+        .with_status(204)
+        .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        client.delete_device(2318).unwrap();
+        mock.assert();
+
+        let _mock = mockito::mock(
+            "DELETE",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_DEVICES, 2319).as_str(),
+        )
+        .with_status(404)
+        .create();
+        assert!(matches!(
+            client.delete_device(2319).unwrap_err(),
+            ApiError::NotFound
+        ));
+    }
+
+    #[test]
+    fn update_device_name() {
+        let url = mockito::server_url();
+
+        let mock = mockito::mock(
+            "PUT",
+            format!("{}{}/{}", DEFAULT_API_BASE, PATH_DEVICES, 2318).as_str(),
+        )
+        .match_body(r#"{"name":"renamed-device"}"#)
+        .with_body(r#"{"status": "INPRODUCTION"}"#)
+        .create();
+
+        let client =
+            NetshotClient::new(url.clone(), String::new(), &HttpClientOptions::default()).unwrap();
+        let update = client
+            .update_device_name(2318, String::from("renamed-device"))
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(update.status, "INPRODUCTION");
+    }
 }
@@ -0,0 +1,140 @@
+//! End-to-end synchronization runs against fake Netbox and Netshot APIs, through the public
+//! library API only
+
+use netbox2netshot::common::retry::RetryPolicy;
+use netbox2netshot::rest::netbox::NetboxClient;
+use netbox2netshot::rest::netshot::NetshotClient;
+use netbox2netshot::{run_sync, SyncConfig};
+
+/// The fake Netbox inventory: sw1 (10.0.0.1) and sw2 (10.0.0.2), one device per page
+fn mock_netbox() -> Vec<mockito::Mock> {
+    [("0", "page1"), ("1", "page2")]
+        .iter()
+        .map(|(offset, page)| {
+            mockito::mock("GET", "/api/dcim/devices/")
+                .match_query(mockito::Matcher::AllOf(vec![
+                    mockito::Matcher::UrlEncoded("limit".into(), "1".into()),
+                    mockito::Matcher::UrlEncoded("offset".into(), offset.to_string()),
+                    mockito::Matcher::UrlEncoded("status".into(), "active".into()),
+                ]))
+                .with_body_from_file(format!("tests/data/sync/netbox_devices_{}.json", page))
+                .expect(1)
+                .create()
+        })
+        .collect()
+}
+
+/// The fake Netshot inventory: sw1 (10.0.0.1) and decommissioned (10.0.0.9)
+fn mock_netshot_devices() -> mockito::Mock {
+    mockito::mock("GET", "/api/devices")
+        .match_query(mockito::Matcher::UrlEncoded("group".into(), "1".into()))
+        .with_body_from_file("tests/data/sync/netshot_devices.json")
+        .create()
+}
+
+fn clients() -> (Vec<NetboxClient>, NetshotClient) {
+    let url = mockito::server_url();
+    let netbox_client = NetboxClient::new_anonymous(url.clone(), None)
+        .unwrap()
+        .with_page_size(1);
+    let netshot_client = NetshotClient::new(url, String::from("token"), &Default::default())
+        .unwrap()
+        .with_retry_policy(RetryPolicy::new(0, 1, false));
+    (vec![netbox_client], netshot_client)
+}
+
+fn config() -> SyncConfig {
+    SyncConfig {
+        netshot_domain_id: 1,
+        netbox_devices_filters: vec![String::from("status=active")],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn sync_registers_and_disables() {
+    let netbox = mock_netbox();
+    let _netshot = mock_netshot_devices();
+    let register = mockito::mock("POST", "/api/devices")
+        .match_header("X-Netshot-API-Token", "token")
+        .match_body(r#"{"autoDiscover":true,"ipAddress":"10.0.0.2","domainId":1}"#)
+        .with_body_from_file("tests/data/netshot/good_device_registration.json")
+        .expect(1)
+        .create();
+    let search = mockito::mock("POST", "/api/devices/search")
+        .match_body(r#"{"query":"[IP] IS 10.0.0.9"}"#)
+        .with_body_from_file("tests/data/sync/netshot_search.json")
+        .expect(1)
+        .create();
+    let disable = mockito::mock("PUT", "/api/devices/9")
+        .match_body(r#"{"enabled":false}"#)
+        .with_body_from_file("tests/data/netshot/disable_device.json")
+        .expect(1)
+        .create();
+
+    let (netbox_clients, netshot_client) = clients();
+    let outcome = run_sync(&config(), &netbox_clients, &netshot_client).unwrap();
+
+    for page in &netbox {
+        page.assert();
+    }
+    register.assert();
+    search.assert();
+    disable.assert();
+    assert_eq!(outcome.devices_to_register[0].hostname, "sw2");
+    assert_eq!(outcome.devices_to_disable[0].hostname, "decommissioned");
+    assert_eq!(outcome.report.counts.register, 1);
+    assert_eq!(outcome.report.counts.disable, 1);
+    assert_eq!(outcome.failures, 0);
+    assert!(outcome.errors.is_empty());
+}
+
+#[test]
+fn sync_reports_failed_writes() {
+    let _netbox = mock_netbox();
+    let _netshot = mock_netshot_devices();
+    let _register = mockito::mock("POST", "/api/devices")
+        .with_status(500)
+        .create();
+    let _search = mockito::mock("POST", "/api/devices/search")
+        .with_body_from_file("tests/data/sync/netshot_search.json")
+        .create();
+    let _disable = mockito::mock("PUT", "/api/devices/9")
+        .with_body_from_file("tests/data/netshot/disable_device.json")
+        .create();
+
+    let (netbox_clients, netshot_client) = clients();
+    let outcome = run_sync(&config(), &netbox_clients, &netshot_client).unwrap();
+
+    assert_eq!(outcome.failures, 1);
+    assert_eq!(outcome.report.counts.disable, 1);
+    assert_eq!(outcome.errors.len(), 1);
+    assert_eq!(outcome.errors[0].ip.as_deref(), Some("10.0.0.2"));
+    assert_eq!(
+        outcome.report.devices_to_register[0].error_category,
+        Some("server")
+    );
+}
+
+#[test]
+fn sync_is_idempotent() {
+    let _netbox = mock_netbox();
+    let _netshot = mock_netshot_devices();
+    let _register = mockito::mock("POST", "/api/devices")
+        .with_status(400)
+        .with_body(r#"{"errorMsg": "The IP address is already managed", "errorCode": 131}"#)
+        .create();
+    let _search = mockito::mock("POST", "/api/devices/search")
+        .with_body_from_file("tests/data/sync/netshot_search_disabled.json")
+        .create();
+    let disable = mockito::mock("PUT", "/api/devices/9").expect(0).create();
+
+    let (netbox_clients, netshot_client) = clients();
+    let outcome = run_sync(&config(), &netbox_clients, &netshot_client).unwrap();
+
+    disable.assert();
+    assert_eq!(outcome.failures, 0);
+    assert_eq!(outcome.report.counts.unchanged, 2);
+    assert!(outcome.report.devices_to_register[0].unchanged);
+    assert_eq!(outcome.report.devices_to_register[0].success, Some(true));
+}